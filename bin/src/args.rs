@@ -1,11 +1,15 @@
 use clap::{Parser, Subcommand};
 use log::LevelFilter;
+use macc_lib::settings::Network;
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
     RunNode {
         #[clap(short, long, help="Path to the config file", default_value_t=String::from("config.json"))]
         config: String,
+
+        #[clap(short, long, help = "Network to run on, overrides the config")]
+        network: Option<Network>,
     },
 
     GenerateClientJson {
@@ -16,6 +20,9 @@ pub enum Command {
     GetAddress {
         #[clap(short, long, help = "The Public key in hex format")]
         pb_key: String,
+
+        #[clap(short, long, help = "Network of the address", default_value_t = Network::Mainnet)]
+        network: Network,
     },
 
     CreateTransaction {
@@ -27,6 +34,9 @@ pub enum Command {
 
         #[clap(long, help = "Output in json format")]
         vout: String,
+
+        #[clap(short, long, help = "Network of the output addresses", default_value_t = Network::Mainnet)]
+        network: Network,
     },
     // TODO: get mine?
 }
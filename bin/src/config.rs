@@ -1,3 +1,4 @@
+use macc_lib::settings::{Network, Settings};
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 use std::error::Error;
@@ -5,9 +6,13 @@ use std::fs;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub port: u16,
+    #[serde(default)]
+    pub network: Network,
+    // the default port of the network if not set
+    #[serde(default)]
+    pub port: Option<u16>,
     pub address: String,
-    pub data_file: String,
+    pub data_dir: String,
     pub trusted_nodes: Vec<String>,
 
     #[serde(skip)]
@@ -37,6 +42,19 @@ impl Config {
         Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
     }
 
+    pub fn port(&self) -> u16 {
+        self.port
+            .unwrap_or_else(|| Settings::for_network(self.network).default_port)
+    }
+
+    // networks other than mainnet keep their data in a subdirectory
+    pub fn network_data_dir(&self) -> String {
+        match self.network {
+            Network::Mainnet => self.data_dir.clone(),
+            network => format!("{}/{}", self.data_dir, network),
+        }
+    }
+
     pub fn save(&self) -> std::io::Result<()> {
         fs::write(&self.path, serde_json::to_string(self)?)
     }
@@ -45,9 +63,10 @@ impl Config {
 impl Default for Config {
     fn default() -> Self {
         Config {
-            port: 8033,
+            network: Network::default(),
+            port: None,
             address: "your_address".to_string(),
-            data_file: "node.bin".to_string(),
+            data_dir: "node_data".to_string(),
             trusted_nodes: Vec::new(),
 
             path: "config.json".to_string(),
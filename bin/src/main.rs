@@ -1,8 +1,9 @@
 use clap::Parser;
-use log::{info, warn, LevelFilter};
+use log::{info, LevelFilter};
 use macc_lib::{
-    ecdsa::{create_rng, create_secp, pb_key_from_bytes, Client, pb_key_to_addr},
+    ecdsa::{create_rng, create_secp, pb_key_from_bytes, pb_key_to_addr_with_version, Client},
     hex::{FromHex, ToHex},
+    settings::{Network, Settings},
     PublicKey,
 };
 #[macro_use]
@@ -24,26 +25,38 @@ use args::{Args, Command};
 
 mod netio;
 mod server;
+mod storage;
+use storage::Storage;
 mod worker;
 
+#[cfg(test)]
+mod testing;
+
 static LOGGER: CustomLogger = CustomLogger;
 
-fn start_node(config: &str) {
+fn start_node(config: &str, network: Option<Network>) {
     // create tokio runtime
     let rt: Runtime = Runtime::new().expect("Couldn't create tokio runtime!");
 
     rt.block_on(async {
         // create config
-        let config = Config::new(config);
-
-        // create shared data
-        let data = if let Some(data) = Data::from_file(&config.data_file, config.clone()) {
-            info!("Loaded node data from file!");
-            data
-        } else {
-            warn!("Failed to load node data, creating new data!");
-            Data::new(true, None, config.clone(), None, None, None, None, None)
-        };
+        let mut config = Config::new(config);
+        if let Some(network) = network {
+            config.network = network;
+        }
+        info!("Running on {}!", config.network);
+
+        // create shared data from the committed state
+        let storage =
+            Storage::open(&config.network_data_dir()).expect("Couldn't open the block storage!");
+        let data = Data::open(storage, config.clone()).expect("Couldn't load the block storage!");
+        info!(
+            "Loaded {} blocks from the block storage!",
+            data.blockchain
+                .read()
+                .expect("Couldn't lock blockchain for reading!")
+                .height()
+        );
 
         let mining_data = (share(None), share(None));
 
@@ -57,7 +70,7 @@ fn start_node(config: &str) {
             match signal::ctrl_c().await {
                 Ok(()) => {
                     log::warn!("Shutting down");
-    
+
                     // shutdown
                     *data
                         .running
@@ -66,7 +79,7 @@ fn start_node(config: &str) {
                 }
                 Err(_) => {
                     log::error!("Shutting down");
-    
+
                     // shutdown
                     *data
                         .running
@@ -75,10 +88,10 @@ fn start_node(config: &str) {
                 }
             }
 
-            data.save(&config.data_file)
-                .expect("Couldn't save node data!");
-            info!("Saved node data to file!");
-
+            // every processed block is committed already
+            // but the state could've changed since the last commit
+            data.commit().expect("Couldn't commit node data!");
+            info!("Committed node data!");
         });
         let _ = tokio::join!(h_worker, h_miner, h_server, h_signal);
     });
@@ -93,18 +106,21 @@ fn generate_client_json(save: &Option<String>) {
     let json = serde_json::to_string(&client).expect("Couldn't serialize client!");
 
     if let Some(path) = save {
-        std::fs::write(path, &json).expect(&format!("Couldn't write to {}!", path));
+        std::fs::write(path, &json).unwrap_or_else(|_| panic!("Couldn't write to {}!", path));
         println!("Wrote client json to `{}`!", path);
     } else {
         println!("{}", json);
     }
 }
 
-fn create_transaction(client_json: &str, vin: &str, vout: &str) {
+fn create_transaction(client_json: &str, vin: &str, vout: &str, network: Network) {
     let mut client: Client = serde_json::from_str(
         &std::fs::read_to_string(client_json).expect("Could't find the client json!"),
     )
     .expect("Invalid client json!");
+    let settings = Settings::for_network(network);
+    client.address_version = settings.address_version;
+    client.script_address_version = settings.script_address_version;
 
     println!(
         "{}",
@@ -144,7 +160,7 @@ fn create_transaction(client_json: &str, vin: &str, vout: &str) {
         "Transaction created with hash: {}",
         tx.hash().expect("Couldn't hash the transaction!").to_hex()
     );
-    println!("");
+    println!();
     println!(
         "{}",
         serde_json::to_string(&tx).expect("Couldn't serialize the transaction!")
@@ -152,17 +168,20 @@ fn create_transaction(client_json: &str, vin: &str, vout: &str) {
 
     std::fs::write(
         client_json,
-        &serde_json::to_string(&client).expect("Couldn't serialize client!"),
+        serde_json::to_string(&client).expect("Couldn't serialize client!"),
     )
     .expect("Couldn't update client json");
 }
 
-fn get_address(pb_key: &str) {
+fn get_address(pb_key: &str, network: Network) {
     let pb_key: PublicKey =
         pb_key_from_bytes(&Vec::from_hex(pb_key).expect("Public key isn't in hex format!"))
             .expect("Couldn't deserialize Public key!");
-    
-    let addr = pb_key_to_addr(&pb_key.serialize());
+
+    let addr = pb_key_to_addr_with_version(
+        &pb_key.serialize(),
+        Settings::for_network(network).address_version,
+    );
     println!("{}", addr);
 }
 
@@ -175,13 +194,14 @@ fn main() {
         .expect("Couldn't set the logger!");
 
     match &args.command {
-        Command::RunNode { config } => start_node(config),
+        Command::RunNode { config, network } => start_node(config, *network),
         Command::GenerateClientJson { save } => generate_client_json(save),
-        Command::GetAddress { pb_key } => get_address(&pb_key),
+        Command::GetAddress { pb_key, network } => get_address(pb_key, *network),
         Command::CreateTransaction {
             client_json,
             vin,
             vout,
-        } => create_transaction(client_json, vin, vout),
+            network,
+        } => create_transaction(client_json, vin, vout, *network),
     }
 }
@@ -5,7 +5,7 @@ use crate::{
     types::{share, Shared},
 };
 
-use macc_lib::blockchain::{Block, Blockchain, Transaction};
+use macc_lib::blockchain::{Block, BlockHeader, Transaction};
 use reqwest::Client;
 
 pub struct NetIO {
@@ -63,7 +63,7 @@ impl NetIO {
                 //}
             }
 
-            (*config.write().expect("Couldn't write nodes!")).trusted_nodes = valid_nodes;
+            config.write().expect("Couldn't write nodes!").trusted_nodes = valid_nodes;
         });
     }
 
@@ -75,7 +75,7 @@ impl NetIO {
         let endpoint = format!(
             "block?height={}&port={}",
             block_height,
-            &self.config.read().expect("Couldn't read config!").port
+            self.config.read().expect("Couldn't read config!").port()
         );
 
         self.broadcast(endpoint, data);
@@ -95,11 +95,30 @@ impl NetIO {
         Ok(())
     }
 
-    pub async fn get_blockchain(&self, node: &str) -> Option<Blockchain> {
-        let url = format!("http://{}/blockchain", node);
-        let bc_json = self.get(url).await?;
+    pub async fn get_height(&self, node: &str) -> Option<usize> {
+        let url = format!("http://{}/height", node);
+        let height_json = self.get(url).await?;
 
-        serde_json::from_str(&bc_json).ok()
+        serde_json::from_str(&height_json).ok()
+    }
+
+    pub async fn get_headers(
+        &self,
+        node: &str,
+        start: usize,
+        stop: usize,
+    ) -> Option<Vec<BlockHeader>> {
+        let url = format!("http://{}/headers?start={}&stop={}", node, start, stop);
+        let headers_json = self.get(url).await?;
+
+        serde_json::from_str(&headers_json).ok()
+    }
+
+    pub async fn get_blocks(&self, node: &str, start: usize, stop: usize) -> Option<Vec<Block>> {
+        let url = format!("http://{}/blockchain?start={}&stop={}", node, start, stop);
+        let blocks_json = self.get(url).await?;
+
+        serde_json::from_str(&blocks_json).ok()
     }
 
     pub fn save(&self) -> std::io::Result<()> {
@@ -1,10 +1,15 @@
-use std::net::{SocketAddr, IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use crate::types::Data;
-use macc_lib::blockchain::{Block, Transaction};
+use macc_lib::{
+    blockchain::{utils, Block, Transaction},
+    emission,
+    hex::FromHex,
+};
 use rocket::{
     fairing::{Fairing, Info, Kind},
     http::Header,
+    response::status::BadRequest,
     serde::json::Json,
     Request, Response, State,
 };
@@ -28,11 +33,75 @@ fn get_blockchain(
     Some(RawJson(json))
 }
 
+#[get("/headers?<start>&<stop>")]
+fn get_headers(data: &State<Data>, start: Option<usize>, stop: Option<usize>) -> Option<RawJson> {
+    let blockchain = &*data.blockchain.read().ok()?;
+    let start = start.unwrap_or(0);
+    let stop = stop.unwrap_or(blockchain.height());
+
+    let json = serde_json::to_string(&blockchain.headers(start, stop)?).ok()?;
+    Some(RawJson(json))
+}
+
+#[get("/proof?<hash>")]
+fn get_tx_proof(data: &State<Data>, hash: &str) -> Option<RawJson> {
+    let hash: [u8; 32] = Vec::from_hex(hash).ok()?.try_into().ok()?;
+
+    let json = serde_json::to_string(&data.blockchain.read().ok()?.tx_proof(&hash)?).ok()?;
+    Some(RawJson(json))
+}
+
+#[get("/fees?<height>")]
+fn get_block_fees(data: &State<Data>, height: usize) -> Option<RawJson> {
+    let fees = data.blockchain.read().ok()?.fees(height, &data.settings)?;
+
+    let json = serde_json::to_string(&serde_json::json!({
+        "reward": fees.reward.to_string(),
+        "fees": fees.fees.to_string(),
+        "claimed": fees.claimed.to_string(),
+        "burned": fees.burned().to_string(),
+    }))
+    .ok()?;
+    Some(RawJson(json))
+}
+
 #[get("/height")]
 fn get_block_height(data: &State<Data>) -> Option<RawJson> {
     let json = serde_json::to_string(&(*data.blockchain.read().ok()?).height()).ok()?;
     Some(RawJson(json))
 }
+
+#[get("/work")]
+fn get_work(data: &State<Data>) -> Option<RawJson> {
+    let blockchain = &*data.blockchain.read().ok()?;
+    // numbers are too large for json, so they are sent as decimal strings
+    let hashrate = blockchain
+        .hashrate(data.settings.adjustment_interval as usize)
+        .map(|hashrate| hashrate.to_string());
+
+    let json = serde_json::to_string(&serde_json::json!({
+        "total_work": blockchain.total_work().to_string(),
+        "hashrate": hashrate,
+    }))
+    .ok()?;
+    Some(RawJson(json))
+}
+
+#[get("/supply")]
+fn get_supply(data: &State<Data>) -> Option<RawJson> {
+    let height = data.blockchain.read().ok()?.height();
+    let settings = &data.settings;
+
+    // amounts can be too large for json numbers as well
+    let json = serde_json::to_string(&serde_json::json!({
+        "supply": emission::supply(height, settings).to_string(),
+        "max_supply": emission::max_supply(settings).map(|max| max.to_string()),
+        "next_reward": emission::block_reward(height, settings).to_string(),
+    }))
+    .ok()?;
+    Some(RawJson(json))
+}
+
 #[get("/txstore")]
 fn get_tx_store(data: &State<Data>) -> Option<RawJson> {
     let json = serde_json::to_string(&*data.store.read().ok()?).ok()?;
@@ -41,10 +110,29 @@ fn get_tx_store(data: &State<Data>) -> Option<RawJson> {
 
 // POST
 #[post("/transaction", data = "<transaction>")]
-fn post_transaction(data: &State<Data>, transaction: Json<Transaction>) -> Option<()> {
-    data.i_transactions.write().ok()?.push(transaction.0);
+fn post_transaction(
+    data: &State<Data>,
+    transaction: Json<Transaction>,
+) -> Result<(), BadRequest<String>> {
+    // tell the sender why the transaction is rejected
+    utils::check_tx_limits(&transaction.0, &data.settings)
+        .map_err(|e| BadRequest(e.to_string()))?;
+    let mempool = data.mempool.read().map_err(|_| BadRequest(String::new()))?;
+    utils::is_valid_tx(
+        &transaction.0,
+        mempool.store(),
+        mempool.median_time(),
+        &data.settings,
+    )
+    .map_err(|e| BadRequest(e.to_string()))?;
+    drop(mempool);
 
-    Some(())
+    data.i_transactions
+        .write()
+        .map_err(|_| BadRequest(String::new()))?
+        .push(transaction.0);
+
+    Ok(())
 }
 
 #[post("/block?<height>&<port>", data = "<block>")]
@@ -55,11 +143,10 @@ fn post_block(
     port: String,
     ip_addr: SocketAddr,
 ) -> Option<()> {
-    data.i_blocks.write().ok()?.push((
-        format!("{}:{}", ip_addr.ip().to_string(), port),
-        height,
-        block.0,
-    ));
+    data.i_blocks
+        .write()
+        .ok()?
+        .push((format!("{}:{}", ip_addr.ip(), port), height, block.0));
 
     Some(())
 }
@@ -69,6 +156,7 @@ fn index() -> &'static str {
     "For a list of RESTAPI endpoints checkout https://github.com/32byte/macc-full/blob/master/docs/client-rest-api.md"
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CORS;
 
 #[rocket::async_trait]
@@ -95,14 +183,26 @@ pub async fn start(data: Data) {
     // NICE-TO-HAVE: custom loglevel for rocket
     let config = rocket::Config {
         address: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-        port: data.config.port,
+        port: data.config.port(),
         ..Default::default()
     };
 
     let _ = rocket::custom(config)
         .attach(CORS)
         .mount("/", routes![index])
-        .mount("/", routes![get_blockchain, get_block_height, get_tx_store])
+        .mount(
+            "/",
+            routes![
+                get_blockchain,
+                get_headers,
+                get_tx_proof,
+                get_block_fees,
+                get_block_height,
+                get_work,
+                get_supply,
+                get_tx_store
+            ],
+        )
         .mount("/", routes![post_transaction, post_block])
         .manage(data)
         .launch()
@@ -0,0 +1,466 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use log::{debug, warn};
+use macc_lib::blockchain::{utils, Block, BlockUndo, Blockchain, TxStore};
+
+// on-disk layout of the node data:
+//
+// blocks.dat - append-only file of bincode (Block, BlockUndo) records,
+//              a record holds the outputs a block adds and the ones it spends
+// index.dat  - append-only file of fixed size (hash, offset, len) entries,
+//              one per height, an entry commits the block record it points to
+// utxo.dat   - snapshot of the store with the height and hash of the tip
+//              it belongs to, replaced atomically with a rename
+//
+// a block is written before its index entry and the snapshot last,
+// so after a crash everything after the last complete index entry
+// is dropped and the store is caught up from the blocks after the snapshot
+//
+// the snapshot is only replaced every `SNAPSHOT_INTERVAL` blocks
+// or if a reorganization removed its block
+const BLOCKS_FILE: &str = "blocks.dat";
+const INDEX_FILE: &str = "index.dat";
+const UTXO_FILE: &str = "utxo.dat";
+
+// blocks committed before the store snapshot is replaced
+const SNAPSHOT_INTERVAL: usize = 1000;
+
+// hash (32 bytes), offset (8 bytes) and len (4 bytes)
+const INDEX_ENTRY_LEN: u64 = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IndexEntry {
+    hash: [u8; 32],
+    offset: u64,
+    len: u32,
+}
+
+impl IndexEntry {
+    fn to_bytes(self) -> [u8; INDEX_ENTRY_LEN as usize] {
+        let mut bytes = [0u8; INDEX_ENTRY_LEN as usize];
+        bytes[..32].copy_from_slice(&self.hash);
+        bytes[32..40].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[40..].copy_from_slice(&self.len.to_le_bytes());
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            hash: bytes[..32].try_into().expect("UNREACHABLE!"),
+            offset: u64::from_le_bytes(bytes[32..40].try_into().expect("UNREACHABLE!")),
+            len: u32::from_le_bytes(bytes[40..].try_into().expect("UNREACHABLE!")),
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.offset + self.len as u64
+    }
+}
+
+fn invalid_data<E: std::fmt::Display>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+pub struct Storage {
+    dir: PathBuf,
+    blocks: File,
+    index: File,
+    // index entries of the committed blocks, the position is the height
+    entries: Vec<IndexEntry>,
+    // height of the snapshot on disk, 0 if there is none for the committed chain
+    snapshot_height: usize,
+    snapshot_interval: usize,
+}
+
+impl Storage {
+    // opens the storage in `dir` and drops everything which wasn't committed
+    pub fn open(dir: &str) -> io::Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+
+        let open = |name: &str| {
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(dir.join(name))
+        };
+        let mut blocks = open(BLOCKS_FILE)?;
+        let mut index = open(INDEX_FILE)?;
+
+        let mut bytes = Vec::new();
+        index.read_to_end(&mut bytes)?;
+        let blocks_len = blocks.seek(SeekFrom::End(0))?;
+
+        // keep the complete entries which point to complete block records
+        let mut entries: Vec<IndexEntry> = Vec::new();
+        for chunk in bytes.chunks_exact(INDEX_ENTRY_LEN as usize) {
+            let entry = IndexEntry::from_bytes(chunk);
+            let offset = entries.last().map(|last| last.end()).unwrap_or(0);
+
+            if entry.offset != offset || entry.end() > blocks_len {
+                break;
+            }
+            entries.push(entry);
+        }
+
+        // drop partial writes
+        let index_len = entries.len() as u64 * INDEX_ENTRY_LEN;
+        let blocks_end = entries.last().map(|last| last.end()).unwrap_or(0);
+        if index_len != bytes.len() as u64 || blocks_end != blocks_len {
+            warn!("Dropping uncommitted data from the block storage!");
+            index.set_len(index_len)?;
+            blocks.set_len(blocks_end)?;
+            index.sync_all()?;
+            blocks.sync_all()?;
+        }
+
+        Ok(Self {
+            dir,
+            blocks,
+            index,
+            entries,
+            snapshot_height: 0,
+            snapshot_interval: SNAPSHOT_INTERVAL,
+        })
+    }
+
+    pub fn height(&self) -> usize {
+        self.entries.len()
+    }
+
+    // reads the committed blockchain and the store belonging to it
+    pub fn load(&mut self) -> io::Result<(Blockchain, TxStore)> {
+        let mut blocks = Vec::with_capacity(self.height());
+        let mut undo = Vec::with_capacity(self.height());
+
+        self.blocks.seek(SeekFrom::Start(0))?;
+        for entry in &self.entries {
+            let mut bytes = vec![0u8; entry.len as usize];
+            self.blocks.read_exact(&mut bytes)?;
+
+            let (block, block_undo): (Block, BlockUndo) =
+                bincode::deserialize(&bytes).map_err(invalid_data)?;
+            if block.hash(None).map_err(invalid_data)? != entry.hash {
+                return Err(invalid_data("block doesn't match its index entry"));
+            }
+
+            blocks.push(block);
+            undo.push(block_undo);
+        }
+
+        let blockchain = Blockchain::from_parts(blocks, undo).expect("UNREACHABLE!");
+        let (snapshot_height, store) = self.load_store(&blockchain);
+        self.snapshot_height = snapshot_height;
+
+        Ok((blockchain, store))
+    }
+
+    // reads the snapshot of the store and applies the blocks committed after it
+    // returns the height of the snapshot, 0 if it wasn't usable
+    fn load_store(&self, blockchain: &Blockchain) -> (usize, TxStore) {
+        if blockchain.height() == 0 {
+            return (0, TxStore::new_empty());
+        }
+
+        let snapshot: Option<(usize, [u8; 32], TxStore)> = fs::read(self.dir.join(UTXO_FILE))
+            .ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok());
+
+        let (snapshot_height, mut store) = match snapshot {
+            // the snapshot has to belong to a block of the committed chain
+            Some((height, tip, store))
+                if height > 0
+                    && height <= self.height()
+                    && self.entries[height - 1].hash == tip =>
+            {
+                (height, store)
+            }
+            _ => {
+                warn!("No usable store snapshot, rebuilding the store from the blocks!");
+                (0, TxStore::new_empty())
+            }
+        };
+
+        if snapshot_height < blockchain.height() {
+            debug!(
+                "Applying blocks {} to {} to the store",
+                snapshot_height,
+                blockchain.height()
+            );
+        }
+        for height in snapshot_height..blockchain.height() {
+            utils::add_block_to_store(blockchain.at(height as i32), &mut store);
+        }
+
+        (snapshot_height, store)
+    }
+
+    // makes the committed state the blockchain and store
+    // only the blocks which differ from the committed ones are written
+    pub fn commit(&mut self, blockchain: &Blockchain, store: &TxStore) -> io::Result<()> {
+        // blocks commit to their previous block, so walking down from the top
+        // finds the number of blocks which are already committed
+        let mut common = self.height().min(blockchain.height());
+        while common > 0
+            && blockchain
+                .at(common as i32 - 1)
+                .hash(None)
+                .map_err(invalid_data)?
+                != self.entries[common - 1].hash
+        {
+            common -= 1;
+        }
+
+        // remove the blocks of another branch, the index first
+        if common < self.height() {
+            let offset = self.entries[common].offset;
+            self.index.set_len(common as u64 * INDEX_ENTRY_LEN)?;
+            self.index.sync_all()?;
+            self.blocks.set_len(offset)?;
+            self.blocks.sync_all()?;
+            self.entries.truncate(common);
+        }
+
+        // append the new blocks, each block before its index entry
+        for height in common..blockchain.height() {
+            let block = blockchain.at(height as i32);
+            let undo = blockchain.undo(height).expect("UNREACHABLE!");
+            let bytes = bincode::serialize(&(block, undo)).map_err(invalid_data)?;
+
+            let entry = IndexEntry {
+                hash: block.hash(None).map_err(invalid_data)?,
+                offset: self.entries.last().map(|last| last.end()).unwrap_or(0),
+                len: bytes.len() as u32,
+            };
+
+            self.blocks.write_all(&bytes)?;
+            self.blocks.sync_data()?;
+            self.index.write_all(&entry.to_bytes())?;
+            self.index.sync_data()?;
+
+            self.entries.push(entry);
+        }
+
+        // the blocks after the snapshot are applied when loading,
+        // so it only has to be replaced once they take too long to apply
+        // or if it belongs to a removed block
+        if common < self.snapshot_height
+            || self.height() >= self.snapshot_height + self.snapshot_interval
+        {
+            self.write_snapshot(store)?;
+        }
+
+        Ok(())
+    }
+
+    // replaces the snapshot with the store of the committed tip
+    fn write_snapshot(&mut self, store: &TxStore) -> io::Result<()> {
+        let tip = self
+            .entries
+            .last()
+            .map(|last| last.hash)
+            .unwrap_or([0u8; 32]);
+        let bytes = bincode::serialize(&(self.height(), tip, store)).map_err(invalid_data)?;
+
+        let tmp = self.dir.join(format!("{}.tmp", UTXO_FILE));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(UTXO_FILE))?;
+        self.snapshot_height = self.height();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        io::{self, Write},
+        path::{Path, PathBuf},
+    };
+
+    use macc_lib::{
+        blockchain::{Blockchain, TxStore},
+        ecdsa::{create_rng, create_secp, Client},
+    };
+
+    use super::{IndexEntry, Storage, BLOCKS_FILE, INDEX_FILE, UTXO_FILE};
+    use crate::testing::{extend, settings};
+
+    // empty directory for the storage of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("macc-storage-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn open(dir: &Path) -> io::Result<Storage> {
+        Storage::open(dir.to_str().expect("UNREACHABLE!"))
+    }
+
+    fn append(path: PathBuf, bytes: &[u8]) -> io::Result<()> {
+        OpenOptions::new().append(true).open(path)?.write_all(bytes)
+    }
+
+    fn file_len(path: PathBuf) -> u64 {
+        fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+    }
+
+    fn write_snapshot(dir: &Path, height: usize, tip: [u8; 32], store: &TxStore) {
+        let bytes = bincode::serialize(&(height, tip, store)).expect("UNREACHABLE!");
+        fs::write(dir.join(UTXO_FILE), bytes).expect("Couldn't write the snapshot!");
+    }
+
+    fn read_snapshot(dir: &Path) -> (usize, [u8; 32], TxStore) {
+        let bytes = fs::read(dir.join(UTXO_FILE)).expect("Couldn't read the snapshot!");
+        bincode::deserialize(&bytes).expect("Couldn't decode the snapshot!")
+    }
+
+    // reopening the storage gives exactly the committed state
+    fn assert_reloads(dir: &Path, chain: &Blockchain, store: &TxStore) -> io::Result<()> {
+        let (loaded, loaded_store) = open(dir)?.load()?;
+
+        assert_eq!(
+            bincode::serialize(&loaded).map_err(super::invalid_data)?,
+            bincode::serialize(chain).map_err(super::invalid_data)?
+        );
+        assert_eq!(&loaded_store, store);
+
+        Ok(())
+    }
+
+    fn clients() -> (Client, Client) {
+        let secp = create_secp();
+        let mut rng = create_rng().expect("Couldn't create rng!");
+
+        (
+            Client::new_random(&secp, &mut rng),
+            Client::new_random(&secp, &mut rng),
+        )
+    }
+
+    #[test]
+    fn test_reorg_truncation() -> io::Result<()> {
+        let dir = test_dir("reorg");
+        let (alice, bob) = clients();
+
+        let mut store = TxStore::new_empty();
+        let mut chain = Blockchain::new(&mut store, &settings());
+        extend(&mut chain, &mut store, 4, &alice);
+
+        open(&dir)?.commit(&chain, &store)?;
+        assert_reloads(&dir, &chain, &store)?;
+
+        // another branch replaces the last two blocks
+        let (mut branch, mut branch_store) = (chain.clone(), store.clone());
+        branch.disconnect_tip(&mut branch_store);
+        branch.disconnect_tip(&mut branch_store);
+        extend(&mut branch, &mut branch_store, 3, &bob);
+
+        let mut storage = open(&dir)?;
+        storage.load()?;
+        storage.commit(&branch, &branch_store)?;
+        assert_eq!(storage.height(), 6);
+        // the blocks of the old branch are gone from the files
+        let end = storage.entries.last().map(IndexEntry::end).unwrap_or(0);
+        assert_eq!(file_len(dir.join(BLOCKS_FILE)), end);
+        assert_eq!(file_len(dir.join(INDEX_FILE)), 6 * super::INDEX_ENTRY_LEN);
+        drop(storage);
+
+        assert_reloads(&dir, &branch, &branch_store)?;
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_torn_writes() -> io::Result<()> {
+        let dir = test_dir("torn");
+        let (alice, _) = clients();
+
+        let mut store = TxStore::new_empty();
+        let mut chain = Blockchain::new(&mut store, &settings());
+        extend(&mut chain, &mut store, 4, &alice);
+        open(&dir)?.commit(&chain, &store)?;
+
+        let index_len = file_len(dir.join(INDEX_FILE));
+        let blocks_len = file_len(dir.join(BLOCKS_FILE));
+
+        // half of an index entry
+        append(dir.join(INDEX_FILE), &[1u8; 10])?;
+        assert_eq!(open(&dir)?.height(), 5);
+        assert_eq!(file_len(dir.join(INDEX_FILE)), index_len);
+
+        // an index entry pointing to a block record which wasn't completely written
+        append(dir.join(BLOCKS_FILE), &[2u8; 50])?;
+        let entry = IndexEntry {
+            hash: [3u8; 32],
+            offset: blocks_len,
+            len: 100,
+        };
+        append(dir.join(INDEX_FILE), &entry.to_bytes())?;
+        assert_eq!(open(&dir)?.height(), 5);
+        assert_eq!(file_len(dir.join(INDEX_FILE)), index_len);
+        assert_eq!(file_len(dir.join(BLOCKS_FILE)), blocks_len);
+
+        assert_reloads(&dir, &chain, &store)?;
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_snapshot() -> io::Result<()> {
+        let dir = test_dir("snapshot");
+        let (alice, bob) = clients();
+
+        let mut store = TxStore::new_empty();
+        let mut chain = Blockchain::new(&mut store, &settings());
+        extend(&mut chain, &mut store, 4, &alice);
+
+        let mut storage = open(&dir)?;
+        storage.snapshot_interval = 2;
+        storage.commit(&chain, &store)?;
+        let tip = chain.at(-1).hash(None).map_err(super::invalid_data)?;
+        assert_eq!(read_snapshot(&dir), (5, tip, store.clone()));
+
+        // the next block is only appended, the snapshot stays behind
+        // like after a crash before it was replaced
+        extend(&mut chain, &mut store, 1, &alice);
+        storage.commit(&chain, &store)?;
+        assert_eq!(read_snapshot(&dir).0, 5);
+        assert_reloads(&dir, &chain, &store)?;
+
+        // snapshots which don't belong to the committed chain are ignored
+        write_snapshot(&dir, 10, tip, &TxStore::new_empty());
+        assert_reloads(&dir, &chain, &store)?;
+        write_snapshot(&dir, 3, [7u8; 32], &TxStore::new_empty());
+        assert_reloads(&dir, &chain, &store)?;
+
+        // a reorganization below the snapshot replaces it
+        let (mut old, mut old_store) = (chain.clone(), store.clone());
+        old.disconnect_tip(&mut old_store);
+        write_snapshot(&dir, 5, tip, &old_store);
+        let mut storage = open(&dir)?;
+        storage.snapshot_interval = 2;
+        storage.load()?;
+        assert_eq!(storage.snapshot_height, 5);
+
+        let (mut branch, mut branch_store) = (chain.clone(), store.clone());
+        branch.disconnect_tip(&mut branch_store);
+        branch.disconnect_tip(&mut branch_store);
+        extend(&mut branch, &mut branch_store, 1, &bob);
+        storage.commit(&branch, &branch_store)?;
+        let branch_tip = branch.at(-1).hash(None).map_err(super::invalid_data)?;
+        assert_eq!(read_snapshot(&dir), (5, branch_tip, branch_store.clone()));
+
+        assert_reloads(&dir, &branch, &branch_store)?;
+        fs::remove_dir_all(&dir)
+    }
+}
@@ -0,0 +1,34 @@
+// helpers shared by the tests of the node
+
+use macc_lib::{
+    blockchain::{utils, Block, Blockchain, Transaction, TxStore},
+    ecdsa::{create_lock_with_addr, pb_key_to_addr_with_version, Client},
+    settings::Settings,
+    utils::{current_time, find_nonce},
+};
+
+pub fn settings() -> Settings {
+    Settings {
+        coinbase_maturity: 1,
+        ..Settings::regtest()
+    }
+}
+
+// adds `count` blocks mined by `miner` to the chain
+pub fn extend(chain: &mut Blockchain, store: &mut TxStore, count: usize, miner: &Client) {
+    let settings = settings();
+
+    for _ in 0..count {
+        let height = chain.height();
+        let reward = utils::calculate_mining_reward(height, &settings);
+        let address =
+            pb_key_to_addr_with_version(&miner.pb_key.serialize(), settings.address_version);
+        let cb_tx = Transaction::new_coinbase(height, reward, create_lock_with_addr(&address));
+
+        let diff = chain.next_difficulty(&settings);
+        let previous = chain.at(-1).hash(None).expect("UNREACHABLE!");
+        let mut b = Block::new(current_time(), previous, diff, vec![cb_tx]).expect("UNREACHABLE!");
+        b.header.nonce = find_nonce(&b, &diff).expect("UNREACHABLE!");
+        chain.add(store, b);
+    }
+}
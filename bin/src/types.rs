@@ -1,7 +1,9 @@
-use macc_lib::{blockchain::*, settings::Settings};
+use macc_lib::{
+    blockchain::*, blocktree::BlockTree, mempool::Mempool, settings::Settings, target::Target,
+};
 use std::sync::{Arc, RwLock};
 
-use super::Config;
+use super::{storage::Storage, Config};
 
 pub type Shared<T> = Arc<RwLock<T>>;
 
@@ -9,12 +11,19 @@ pub fn share<T>(data: T) -> Shared<T> {
     Arc::new(RwLock::new(data))
 }
 
+// difficulty, previous hash and transactions of the block to mine
+pub type MiningTask = (Target, [u8; 32], Vec<Transaction>);
+// task for the miner and the block found by the miner
+pub type MiningData = (Shared<Option<MiningTask>>, Shared<Option<Block>>);
+
 #[derive(Clone)]
 pub struct Data {
     pub running: Shared<bool>,
 
     pub settings: Settings,
     pub config: Config,
+    // committed state on disk
+    pub storage: Shared<Storage>,
 
     // current state of the blockchain
     pub blockchain: Shared<Blockchain>,
@@ -23,7 +32,10 @@ pub struct Data {
     pub store: Shared<TxStore>,
     // current state of the difficulty
     // can be technically derived from the blockchain and settings
-    pub difficulty: Shared<[u8; 32]>,
+    pub difficulty: Shared<Target>,
+    // all known branches, the active one is `blockchain`
+    // can be technically derived from the blockchain
+    pub tree: Shared<BlockTree>,
 
     // incoming blocks from outside nodes (not verified)
     // vector of node-addr, block_height, block
@@ -31,79 +43,90 @@ pub struct Data {
     // incoming transactions from outside nodes (not verified)
     pub i_transactions: Shared<Vec<Transaction>>,
 
-    // transactions which are verified but not in the memory yet
-    pub mem_transactions: Shared<Vec<Transaction>>,
-    // updated store with the values of mem_transactions
-    // to prevent having double transactions
-    // only for verified that, not an accurate representation
-    // of the next state of the store
-    pub mem_store: Shared<TxStore>,
+    // verified transactions waiting to be mined
+    pub mempool: Shared<Mempool>,
 }
 
 impl Data {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         running: bool,
         settings: Option<Settings>,
         config: Config,
+        storage: Storage,
         blockchain: Option<Blockchain>,
         store: Option<TxStore>,
-        difficulty: Option<[u8; 32]>,
+        difficulty: Option<Target>,
         i_blocks: Option<Vec<(String, usize, Block)>>,
         i_transactions: Option<Vec<Transaction>>,
     ) -> Self {
+        let blockchain = blockchain.unwrap_or_else(Blockchain::new_empty);
+        let median_time = blockchain.median_time_past(&settings.unwrap_or_default());
+        let tree = BlockTree::from_chain(&blockchain);
+        let store = store.unwrap_or_else(TxStore::new_empty);
+
         Self {
             running: share(running),
             settings: settings.unwrap_or_default(),
             config,
-            blockchain: share(blockchain.unwrap_or_else(Blockchain::new_empty)),
-            store: share(store.unwrap_or_else(TxStore::new_empty)),
+            storage: share(storage),
+            blockchain: share(blockchain),
+            store: share(store.clone()),
             difficulty: share(if let Some(diff) = difficulty {
                 diff
             } else {
                 settings.unwrap_or_default().start_difficulty
             }),
+            tree: share(tree),
             i_blocks: share(i_blocks.unwrap_or_default()),
             i_transactions: share(i_transactions.unwrap_or_default()),
 
             // NICE-TO-HAVE: maybe serialize this?
-            mem_transactions: share(Vec::new()),
-            mem_store: share(TxStore::new_empty()),
+            mempool: share(Mempool::new(
+                store.clone(),
+                median_time,
+                &settings.unwrap_or_default(),
+            )),
         }
     }
 
-    pub fn save(&self, path: &str) -> Option<()> {
-        let mut bytes: Vec<u8> = Vec::new();
-
-        bytes.extend(
-            &(bincode::serialize(&(
-                (*self.blockchain.read().ok()?).clone(),
-                (*self.store.read().ok()?).clone(),
-                (*self.difficulty.read().ok()?).clone(),
-            ))
-            .ok()?),
-        );
-
-        std::fs::write(path, bytes).ok()?;
-
-        Some(())
-    }
+    // loads the committed state of the block storage
+    pub fn open(mut storage: Storage, config: Config) -> std::io::Result<Self> {
+        let (mut blockchain, mut store) = storage.load()?;
+        let settings = Settings::for_network(config.network);
 
-    pub fn from_file(path: &str, config: Config) -> Option<Self> {
-        let bytes = std::fs::read(path).ok()?;
-
-        let de: (Blockchain, TxStore, [u8; 32]) = bincode::deserialize(&bytes).ok()?;
+        // a new node starts with the genesis block of the network
+        if blockchain.height() == 0 {
+            blockchain = Blockchain::new(&mut store, &settings);
+        }
+        let difficulty = blockchain.difficulty(&settings);
 
-        Some(Self::new(
+        Ok(Self::new(
             true,
-            None,
+            Some(settings),
             config,
-            Some(de.0),
-            Some(de.1),
-            Some(de.2),
+            storage,
+            Some(blockchain),
+            Some(store),
+            Some(difficulty),
             None,
             None,
         ))
     }
 
+    // commits the current blockchain and store to the block storage
+    pub fn commit(&self) -> std::io::Result<()> {
+        let blockchain = self
+            .blockchain
+            .read()
+            .expect("Couldn't lock blockchain for reading");
+        let store = self.store.read().expect("Couldn't lock store for reading");
+
+        self.storage
+            .write()
+            .expect("Couldn't lock storage for writing")
+            .commit(&blockchain, &store)
+    }
+
     // NICE-TO-HAVE: helper functions for reading / writing since its ugly
 }
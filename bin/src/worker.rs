@@ -1,25 +1,196 @@
 use log::{debug, error, info, warn};
 use macc_lib::{
-    blockchain::{difficulty, utils, Block, Blockchain, Transaction, TxStore},
+    blockchain::{
+        difficulty, utils, Block, BlockHeader, Blockchain, Coinbase, Transaction, TxStore,
+        ValidationError,
+    },
+    blocktree::BlockTree,
     ecdsa::{self, create_rng},
     rand::{rngs::OsRng, Rng},
     settings::Settings,
     utils::current_time,
 };
 
-use crate::{netio::NetIO, types::Shared};
+use crate::{netio::NetIO, types::MiningData};
 
 use super::types::Data;
 
-async fn request_blockchain(
+// number of blocks requested at once while syncing with a node
+const SYNC_BATCH_SIZE: usize = 100;
+// number of blocks synced from a node for each of its blocks we can't place
+const MAX_SYNC_BLOCKS: usize = 10 * SYNC_BATCH_SIZE;
+
+// working copy of the chain state, written back once all blocks are processed
+struct ChainState {
+    tree: BlockTree,
+    blockchain: Blockchain,
+    store: TxStore,
+    // transactions of blocks which are no longer part of the active chain
+    orphaned: Vec<Transaction>,
+}
+
+impl ChainState {
+    // inserts the block into the tree and reports if the active chain changed
+    fn insert(
+        &mut self,
+        settings: &Settings,
+        node: &str,
+        block: Block,
+    ) -> Result<bool, ValidationError> {
+        match self
+            .tree
+            .insert(block, &mut self.blockchain, &mut self.store, settings)?
+        {
+            Some(reorg) => {
+                if !reorg.disconnected.is_empty() {
+                    info!(
+                        "{} has sent a heavier branch, {} blocks were replaced!",
+                        node,
+                        reorg.disconnected.len()
+                    );
+                } else {
+                    info!("{} has sent a new valid block!", node);
+                }
+                self.orphaned.extend(reorg.orphaned_transactions());
+
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+// another node the blockchain is synced with
+trait Peer {
+    // name of the node in the logs
+    fn name(&self) -> &str;
+
+    async fn height(&self) -> Option<usize>;
+
+    async fn headers(&self, start: usize, stop: usize) -> Option<Vec<BlockHeader>>;
+
+    async fn blocks(&self, start: usize, stop: usize) -> Option<Vec<Block>>;
+}
+
+// node reached through its rest api
+struct RemoteNode<'a> {
+    net_client: &'a NetIO,
+    node: &'a str,
+}
+
+impl Peer for RemoteNode<'_> {
+    fn name(&self) -> &str {
+        self.node
+    }
+
+    async fn height(&self) -> Option<usize> {
+        self.net_client.get_height(self.node).await
+    }
+
+    async fn headers(&self, start: usize, stop: usize) -> Option<Vec<BlockHeader>> {
+        self.net_client.get_headers(self.node, start, stop).await
+    }
+
+    async fn blocks(&self, start: usize, stop: usize) -> Option<Vec<Block>> {
+        self.net_client.get_blocks(self.node, start, stop).await
+    }
+}
+
+// number of blocks our blockchain shares with the node
+// blocks commit to their previous block, so if the blocks at a height
+// are identical all blocks below are as well and a binary search works
+async fn common_height(
+    blockchain: &Blockchain,
+    peer: &impl Peer,
+    node_height: usize,
+) -> Option<usize> {
+    let same_at =
+        |height: usize, hash: [u8; 32]| blockchain.at(height as i32).hash(None).ok() == Some(hash);
+
+    let mut low = 0;
+    let mut high = blockchain.height().min(node_height);
+
+    while low < high {
+        // usually the node only extends our blockchain, so check the top first
+        let mid = if low == 0 && high == blockchain.height().min(node_height) {
+            high
+        } else {
+            (low + high).div_ceil(2)
+        };
+
+        let header = peer.headers(mid - 1, mid).await?.pop()?;
+        if same_at(mid - 1, header.hash(None).ok()?) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    Some(low)
+}
+
+// downloads at most `max_blocks` blocks of the node which we don't share
+// in batches and reports if the active chain changed
+// the height of the node is only a claim, so syncing stops at the first
+// batch which is short or contains an invalid block
+async fn sync_blocks(
     settings: &Settings,
-    net_client: &NetIO,
-    node: &str,
-) -> Option<(Blockchain, TxStore, [u8; 32])> {
-    let bc = net_client.get_blockchain(node).await?;
-    let (store, diff) = bc.is_valid(settings)?;
+    state: &mut ChainState,
+    peer: &impl Peer,
+    max_blocks: usize,
+) -> bool {
+    let mut modified = false;
+    let node = peer.name();
+
+    let node_height = match peer.height().await {
+        Some(height) => height,
+        None => return modified,
+    };
+    let start = match common_height(&state.blockchain, peer, node_height).await {
+        Some(start) => start,
+        None => return modified,
+    };
+
+    // the blocks up to the last checkpoint are final, a node which doesn't
+    // share them is on another network or a fork which can't become active
+    let checkpoint = utils::final_height(settings).min(state.blockchain.height());
+    if start < checkpoint {
+        warn!(
+            "{} only shares {} blocks but the first {} are final, not syncing!",
+            node, start, checkpoint
+        );
+        return modified;
+    }
+
+    // the rest is synced in the next round
+    let stop = node_height.min(start.saturating_add(max_blocks));
+    debug!("Syncing blocks {} to {} from {}", start, stop, node);
+
+    for batch_start in (start..stop).step_by(SYNC_BATCH_SIZE) {
+        let batch_stop = (batch_start + SYNC_BATCH_SIZE).min(stop);
+        let blocks = match peer.blocks(batch_start, batch_stop).await {
+            Some(blocks) => blocks,
+            None => return modified,
+        };
+        let short = blocks.len() < batch_stop - batch_start;
+
+        for block in blocks.into_iter().take(batch_stop - batch_start) {
+            match state.insert(settings, node, block) {
+                Ok(changed) => modified |= changed,
+                Err(e) => {
+                    debug!("{} has sent an invalid block while syncing: {}", node, e);
+                    return modified;
+                }
+            }
+        }
+
+        if short {
+            debug!("{} has sent fewer blocks than requested", node);
+            return modified;
+        }
+    }
 
-    Some((bc, store, diff))
+    modified
 }
 
 async fn process_blocks(data: &Data, net_client: &NetIO) -> Option<bool> {
@@ -33,61 +204,28 @@ async fn process_blocks(data: &Data, net_client: &NetIO) -> Option<bool> {
     }
     info!("Processing {} new blocks!", blocks_to_process);
 
-    // clone the current blockchain
-    let mut blockchain = data.blockchain.try_read().ok()?.clone();
-    // clone the current store
-    let mut store = data.store.try_read().ok()?.clone();
-    // clone the current difficulty
-    let mut difficulty = *data.difficulty.try_read().ok()?;
-
+    // clone the current block tree, blockchain and store
+    let mut state = ChainState {
+        tree: data.tree.try_read().ok()?.clone(),
+        blockchain: data.blockchain.try_read().ok()?.clone(),
+        store: data.store.try_read().ok()?.clone(),
+        orphaned: Vec::new(),
+    };
     let mut modified = false;
 
-    // adjust difficulty to be the one of the next block
-    for (node, block_height, block) in i_blocks {
-        // the block belongs to a blockchain no longer than ours
-        // -> ignore this block
-        if block_height <= blockchain.height() {
-            debug!("{} has sent a block from a smaller blockchain", node);
-            continue;
-        }
-
-        // advance the difficulty
-        let diff = blockchain.adjust_difficulty(difficulty, &data.settings);
-
-        let block_valid_as_next = blockchain
-            .valid_next(&block, &store, &diff, &data.settings)
-            .unwrap_or(false);
-
-        // this block belongs to a longer blockchain (at least +2)
-        // or this block belongs to a blockchain with lenght + 1 but the blockchain
-        // is not identical to this one
-        if block_height > blockchain.height() + 1 || !block_valid_as_next {
-            debug!(
-                "{} has sent a block from a bigger or other blockchain!",
-                node
-            );
-            // request the blockchain from the node
-            if let Some((bc, st, di)) = request_blockchain(&data.settings, net_client, &node).await
-            {
-                info!(
-                    "{} has a bigger blockchain, this blockchain will be replaced!",
-                    node
-                );
-                blockchain = bc;
-                store = st;
-                difficulty = di;
-
-                modified = true;
+    for (node, _, block) in i_blocks {
+        match state.insert(&data.settings, &node, block) {
+            Ok(changed) => modified |= changed,
+            Err(ValidationError::OrphanBlock) => {
+                // the block builds on blocks we don't know yet
+                debug!("{} has sent a block of an unknown branch!", node);
+                let peer = RemoteNode {
+                    net_client,
+                    node: &node,
+                };
+                modified |= sync_blocks(&data.settings, &mut state, &peer, MAX_SYNC_BLOCKS).await;
             }
-        } else {
-            // the block belongs to a blockchain exactly 1 block longer than our
-            // and the block is valid
-
-            info!("{} has sent a new valid block!", node);
-            // TODO: add to node trusted nodes
-            blockchain.add(&mut store, block);
-            difficulty = diff;
-            modified = true;
+            Err(e) => debug!("{} has sent an invalid block: {}", node, e),
         }
     }
 
@@ -97,37 +235,39 @@ async fn process_blocks(data: &Data, net_client: &NetIO) -> Option<bool> {
         .expect("Couldn't lock blockchain for writing")
         .drain(0..blocks_to_process);
 
+    // replace the tree, it also keeps the new side branches
+    *data.tree.write().expect("Couldn't lock tree for writing") = state.tree;
+
     if modified {
         debug!("New blocks updated the state, updating it!");
-        // replace the blockchain
-        *data
-            .blockchain
-            .write()
-            .expect("Couldn't lock blockchain for writing") = blockchain;
-        // replace the store
-        *data.store.write().expect("Couldn't lock store for writing") = store.clone();
+        let median_time = state.blockchain.median_time_past(&data.settings);
         // replace the difficulty
         *data
             .difficulty
             .write()
-            .expect("Couldn't lock difficulty for writing") = difficulty;
-
-        // update mem_store
+            .expect("Couldn't lock difficulty for writing") =
+            state.blockchain.difficulty(&data.settings);
+        // replace the blockchain
         *data
-            .mem_store
+            .blockchain
             .write()
-            .expect("Couldn't lock mem_store for writing") = store;
-        // push mem_transactions back to incoming transactions
-        // since they need to be reverified
-        data.i_transactions
+            .expect("Couldn't lock blockchain for writing") = state.blockchain;
+        // replace the store
+        *data.store.write().expect("Couldn't lock store for writing") = state.store.clone();
+
+        // verify the mempool against the new store, this drops
+        // transactions which were mined or conflict with the new blocks
+        // transactions of replaced blocks are verified again before the others,
+        // since mempool transactions can spend their outputs
+        data.mempool
             .write()
-            .expect("Couldn't lock i_transactions for writing")
-            .append(
-                &mut data
-                    .mem_transactions
-                    .write()
-                    .expect("Couldn't lock mem_transactions for writing"),
-            );
+            .expect("Couldn't lock mempool for writing")
+            .reset(state.store, median_time, state.orphaned, current_time());
+
+        // commit the new state, so it survives a crash
+        if let Err(e) = data.commit() {
+            error!("Couldn't commit the new blocks: {}", e);
+        }
     }
 
     Some(modified)
@@ -136,74 +276,76 @@ async fn process_blocks(data: &Data, net_client: &NetIO) -> Option<bool> {
 fn proces_transactions(data: &Data, net_client: &NetIO) -> Option<()> {
     // store state
     let i_transactions = data.i_transactions.try_read().ok()?.clone();
-
     let i_transactions_len = i_transactions.len();
+
+    let mut mempool = data.mempool.try_read().ok()?.clone();
+
+    // remove stale transactions
+    let expired = mempool.expire(current_time());
+    if expired > 0 {
+        debug!("{} transactions expired!", expired);
+    }
+
     if i_transactions_len == 0 {
         debug!("No transactions to process!");
-        return Some(());
+    } else {
+        info!("Processing {} transactions!", i_transactions_len);
     }
-    info!("Processing {} transactions!", i_transactions_len);
-
-    let mut mem_transactions = data.mem_transactions.try_read().ok()?.clone();
-    let mut mem_store = data.mem_store.try_read().ok()?.clone();
 
     // process all transaction
     for tx in i_transactions {
-        // check if transaction is valid
-        if utils::is_valid_tx(&tx, &mem_store) {
-            debug!("New valid transaction found!");
-            // broadcast transaction
-            if net_client.b_transaction(&tx).is_err() {
-                error!("Node failed to broadcast transaction!");
+        // check if transaction can be mined
+        if let Err(e) = utils::check_tx_limits(&tx, &data.settings) {
+            debug!("Transaction not added to the mempool: {}", e);
+            continue;
+        }
+
+        // check if transaction is valid and add it to the mempool
+        match mempool.add(tx.clone(), current_time()) {
+            Ok(_) => {
+                debug!("New valid transaction found!");
+                // broadcast transaction
+                if net_client.b_transaction(&tx).is_err() {
+                    error!("Node failed to broadcast transaction!");
+                }
             }
-            // add transaction to the mem store
-            utils::add_tx_to_store(&tx, &mut mem_store);
-            // add transaction the the mem transaction
-            mem_transactions.push(tx);
-        } else {
-            debug!("Invalid transaction found!")
+            Err(e) => debug!("Transaction not added to the mempool: {}", e),
         }
     }
 
     // update state
-    debug!("New transactions updated the state, updating it!");
     data.i_transactions
         .write()
         .expect("Couldn't lock i_transactions for writing")
         .drain(0..i_transactions_len);
     *data
-        .mem_transactions
-        .write()
-        .expect("Couldn't lock mem_transactions for writing") = mem_transactions.clone();
-    *data
-        .mem_store
+        .mempool
         .write()
-        .expect("Couldn't lock mem_store for writing") = mem_store.clone();
+        .expect("Couldn't lock mempool for writing") = mempool;
 
     Some(())
 }
 
-fn prepare_transactions(data: &Data, transactions: &mut Vec<Transaction>) -> Option<()> {
+// claims the reward and the fees of the other transactions
+fn coinbase_transaction(data: &Data, fees: u128) -> Option<Transaction> {
     // get block height
     let block_height = data.blockchain.try_read().ok()?.height();
-    // calculate reward
-    let reward = utils::calculate_mining_reward(block_height, &data.settings);
     // create lock
     let lock = ecdsa::create_lock_with_addr(&data.config.address);
-    // create coinbase transaction
-    let coinbase_tx = Transaction::new_coinbase(block_height, reward, lock);
-
-    // add coinbase transaction
-    transactions.push(coinbase_tx);
 
-    Some(())
+    // create coinbase transaction
+    Coinbase::new(block_height, fees, &data.settings)
+        .pay_remaining(lock)
+        .build()
+        .map_err(|e| error!("Can't mine, the coinbase is invalid: {}", e))
+        .ok()
 }
 
 fn add_block(data: &Data, block: Block) -> Option<()> {
     let block_height = data.blockchain.try_read().ok()?.height();
     debug!("Adding new block from miner!");
 
-    let port = data.config.port;
+    let port = data.config.port();
     let self_addr = format!("http://127.0.0.1:{}", port);
 
     data.i_blocks
@@ -216,10 +358,7 @@ fn add_block(data: &Data, block: Block) -> Option<()> {
 
 fn handle_mining(
     data: &Data,
-    mining_data: &(
-        Shared<Option<([u8; 32], [u8; 32], Vec<Transaction>)>>,
-        Shared<Option<Block>>,
-    ),
+    mining_data: &MiningData,
     state_modified: bool,
     net_client: &NetIO,
 ) -> Option<()> {
@@ -242,7 +381,10 @@ fn handle_mining(
 
     // check if miner finished
     if let Some(b) = block {
-        info!("Miner has found a new block with the nonce: {}!", b.nonce);
+        info!(
+            "Miner has found a new block with the nonce: {}!",
+            b.header.nonce
+        );
         // broadcast block
         let block_height = data.blockchain.try_read().ok()?.height();
         if net_client.b_block(&b, block_height).is_err() {
@@ -276,14 +418,36 @@ fn handle_mining(
         let difficulty = blockchain.adjust_difficulty(difficulty, &data.settings);
         // get the previous has (also handle if this will be the first block to be mined)
         let previous = if blockchain.height() == 0 {
-            [0x00_u8; 32]
+            data.settings.network.genesis_previous()
         } else {
             blockchain
                 .at(-1)
                 .hash(None)
                 .expect("Block in the blockchain couldn't be hashed!")
         };
-        let transactions = data.mem_transactions.try_read().ok()?.clone();
+        // leave space for the header and the coinbase transaction
+        let reserved = Block::new(
+            0,
+            previous,
+            difficulty,
+            vec![coinbase_transaction(data, 0)?],
+        )
+        .ok()?
+        .size()
+        .ok()?;
+        let mempool = data.mempool.try_read().ok()?;
+        let mut transactions = mempool.template(
+            data.settings.max_block_txs.saturating_sub(1),
+            data.settings.max_block_size.saturating_sub(reserved),
+        );
+
+        // the coinbase comes first and claims the fees of the transactions
+        let fees = transactions
+            .iter()
+            .filter_map(|tx| mempool.get(&tx.hash().ok()?))
+            .map(|entry| entry.fee)
+            .sum();
+        transactions.insert(0, coinbase_transaction(data, fees)?);
 
         info!("Starting miner at block_height={}!", blockchain.height());
 
@@ -300,12 +464,7 @@ fn handle_mining(
     None
 }
 
-fn is_miner_running(
-    mining_data: &(
-        Shared<Option<([u8; 32], [u8; 32], Vec<Transaction>)>>,
-        Shared<Option<Block>>,
-    ),
-) -> Option<bool> {
+fn is_miner_running(mining_data: &MiningData) -> Option<bool> {
     let has_task = mining_data.0.try_read().ok()?.is_some();
 
     let has_output = mining_data.1.try_read().ok()?.is_some();
@@ -313,14 +472,7 @@ fn is_miner_running(
     Some(has_task && !has_output)
 }
 
-fn mine(
-    data: &Data,
-    mining_data: &(
-        Shared<Option<([u8; 32], [u8; 32], Vec<Transaction>)>>,
-        Shared<Option<Block>>,
-    ),
-    rng: &mut OsRng,
-) -> Option<()> {
+fn mine(data: &Data, mining_data: &MiningData, rng: &mut OsRng) -> Option<()> {
     // get task
     let task = mining_data.0.try_read().ok()?.clone();
 
@@ -334,25 +486,28 @@ fn mine(
     // read task parameters
     let difficulty = task.0;
     let previous = task.1;
-    let mut transactions = task.2;
+    let transactions = task.2;
 
     let mut running = *data.running.try_read().ok()?;
 
-    // setup for coinbase transactions
-    prepare_transactions(&data, &mut transactions);
-
     // nonce to bruteforce
     let mut nonce = rng.gen::<u128>();
     // setup block
-    let mut block = Block {
+    let mut block = match Block::new(
         // set timestamp
-        timestamp: current_time(),
+        current_time(),
         // set previous hash
         previous,
-        // nonce is not found yet
-        nonce,
+        // set difficulty the hash has to satisfy
+        difficulty,
         // transactions to be inlcuded in the block
         transactions,
+    ) {
+        Ok(block) => block,
+        Err(_) => {
+            error!("Something went wront in miner: Couldn't create block!");
+            return None;
+        }
     };
 
     while running {
@@ -384,7 +539,7 @@ fn mine(
     // block was found
 
     // set nonce
-    block.nonce = nonce;
+    block.header.nonce = nonce;
     // set block
     *mining_data
         .1
@@ -394,14 +549,8 @@ fn mine(
     None
 }
 
-pub async fn start_miner(
-    data: Data,
-    mining_data: (
-        Shared<Option<([u8; 32], [u8; 32], Vec<Transaction>)>>,
-        Shared<Option<Block>>,
-    ),
-) {
-    let mut running = if let Some(r) = data.running.try_read().ok() {
+pub async fn start_miner(data: Data, mining_data: MiningData) {
+    let mut running = if let Ok(r) = data.running.try_read() {
         *r
     } else {
         true
@@ -415,7 +564,7 @@ pub async fn start_miner(
         std::thread::sleep(std::time::Duration::from_millis(100));
 
         // update running
-        if let Some(r) = data.running.try_read().ok() {
+        if let Ok(r) = data.running.try_read() {
             running = *r;
         } else {
             break;
@@ -426,14 +575,8 @@ pub async fn start_miner(
     info!("Shutting down miner thread!");
 }
 
-pub async fn start(
-    data: Data,
-    mining_data: (
-        Shared<Option<([u8; 32], [u8; 32], Vec<Transaction>)>>,
-        Shared<Option<Block>>,
-    ),
-) {
-    let mut running = if let Some(r) = data.running.try_read().ok() {
+pub async fn start(data: Data, mining_data: MiningData) {
+    let mut running = if let Ok(r) = data.running.try_read() {
         *r
     } else {
         true
@@ -461,7 +604,7 @@ pub async fn start(
         std::thread::sleep(std::time::Duration::from_millis(100));
 
         // update running
-        if let Some(r) = data.running.try_read().ok() {
+        if let Ok(r) = data.running.try_read() {
             running = *r;
         } else {
             break;
@@ -475,3 +618,148 @@ pub async fn start(
         error!("The config couldn't be stored!");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use macc_lib::{
+        blockchain::{Block, BlockHeader, Blockchain, TxStore},
+        blocktree::BlockTree,
+        ecdsa::{create_rng, create_secp, Client},
+    };
+
+    use super::{common_height, sync_blocks, ChainState, Peer};
+    use crate::testing::{extend, settings};
+
+    // node serving a fixed chain, it can claim to be higher than it is
+    struct FakePeer {
+        blocks: Vec<Block>,
+        claimed_height: Option<usize>,
+        // number of block requests
+        requests: Cell<usize>,
+    }
+
+    impl FakePeer {
+        fn new(chain: &Blockchain) -> Self {
+            Self {
+                blocks: chain
+                    .slice(0, chain.height())
+                    .expect("UNREACHABLE!")
+                    .to_vec(),
+                claimed_height: None,
+                requests: Cell::new(0),
+            }
+        }
+
+        fn range(&self, start: usize, stop: usize) -> &[Block] {
+            let stop = stop.min(self.blocks.len());
+            &self.blocks[start.min(stop)..stop]
+        }
+    }
+
+    impl Peer for FakePeer {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        async fn height(&self) -> Option<usize> {
+            Some(self.claimed_height.unwrap_or(self.blocks.len()))
+        }
+
+        async fn headers(&self, start: usize, stop: usize) -> Option<Vec<BlockHeader>> {
+            Some(
+                self.range(start, stop)
+                    .iter()
+                    .map(|block| block.header.clone())
+                    .collect(),
+            )
+        }
+
+        async fn blocks(&self, start: usize, stop: usize) -> Option<Vec<Block>> {
+            self.requests.set(self.requests.get() + 1);
+
+            Some(self.range(start, stop).to_vec())
+        }
+    }
+
+    fn chain_state(chain: &Blockchain, store: &TxStore) -> ChainState {
+        ChainState {
+            tree: BlockTree::from_chain(chain),
+            blockchain: chain.clone(),
+            store: store.clone(),
+            orphaned: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_common_height() {
+        let secp = create_secp();
+        let mut rng = create_rng().expect("Couldn't create rng!");
+        let alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        let settings = settings();
+        let mut store = TxStore::new_empty();
+        let mut ours = Blockchain::new(&mut store, &settings);
+        extend(&mut ours, &mut store, 1, &alice);
+        let (mut theirs, mut their_store) = (ours.clone(), store.clone());
+        extend(&mut ours, &mut store, 3, &alice);
+
+        // the node extends our chain
+        let mut longer = ours.clone();
+        extend(&mut longer, &mut store.clone(), 2, &alice);
+        let peer = FakePeer::new(&longer);
+        assert_eq!(common_height(&ours, &peer, 7).await, Some(5));
+
+        // the node only has a part of our chain
+        let peer = FakePeer::new(&theirs);
+        assert_eq!(common_height(&ours, &peer, 2).await, Some(2));
+
+        // the node is on another branch
+        extend(&mut theirs, &mut their_store, 5, &bob);
+        let peer = FakePeer::new(&theirs);
+        assert_eq!(common_height(&ours, &peer, 7).await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_sync_blocks() {
+        let secp = create_secp();
+        let mut rng = create_rng().expect("Couldn't create rng!");
+        let alice = Client::new_random(&secp, &mut rng);
+
+        let settings = settings();
+        let mut store = TxStore::new_empty();
+        let mut ours = Blockchain::new(&mut store, &settings);
+        extend(&mut ours, &mut store, 1, &alice);
+        let mut theirs = ours.clone();
+        extend(&mut theirs, &mut store.clone(), 6, &alice);
+
+        // only `max_blocks` blocks are synced at once
+        let mut state = chain_state(&ours, &store);
+        let peer = FakePeer::new(&theirs);
+        assert!(sync_blocks(&settings, &mut state, &peer, 4).await);
+        assert_eq!(state.blockchain.height(), 6);
+        assert!(sync_blocks(&settings, &mut state, &peer, 4).await);
+        assert_eq!(state.blockchain.height(), 8);
+        assert!(!sync_blocks(&settings, &mut state, &peer, 4).await);
+
+        // a node claiming to be higher than it is stops at its last block
+        let mut state = chain_state(&ours, &store);
+        let peer = FakePeer {
+            claimed_height: Some(usize::MAX),
+            ..FakePeer::new(&theirs)
+        };
+        assert!(sync_blocks(&settings, &mut state, &peer, usize::MAX).await);
+        assert_eq!(state.blockchain.height(), 8);
+        assert_eq!(peer.requests.get(), 1);
+
+        // syncing stops at the first invalid block
+        let mut state = chain_state(&ours, &store);
+        let mut peer = FakePeer::new(&theirs);
+        peer.blocks[4].transactions[0].vout[0].0 += 1;
+        assert!(sync_blocks(&settings, &mut state, &peer, usize::MAX).await);
+        assert_eq!(state.blockchain.height(), 4);
+        assert_eq!(peer.requests.get(), 1);
+    }
+}
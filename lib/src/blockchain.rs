@@ -1,15 +1,27 @@
-use std::{collections::HashMap, error::Error, vec};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, vec,
+};
 
 use bitcoin_hashes::hex::{FromHex, ToHex};
+use num::BigUint;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     ecdsa::{create_secp, Client},
+    encoding::{self, Decode, DecodeError, Encode},
     hashes,
+    merkle::{self, MerkleProof},
     settings::Settings,
+    spv::TxProof,
+    target::Target,
 };
 
-use self::utils::{calculate_mining_reward, is_valid_tx};
+use self::utils::{
+    calculate_mining_reward, check_checkpoint, check_coinbase, check_tx_limits, is_valid_tx,
+    median_time_past, validate_header,
+};
 
 // logging
 // NICE-TO-HAVE: fix this
@@ -22,149 +34,758 @@ use log::debug;
 // #[cfg(test)]
 // use std::{println as debug}; // Workaround to use prinltn! for logs.
 
+// reason why a block or transaction was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    // block is older than the median time of the last blocks
+    InvalidTimestamp {
+        timestamp: u64,
+        median: u64,
+    },
+    // block is too far in the future
+    FutureTimestamp {
+        timestamp: u64,
+        max: u64,
+    },
+    // block or transaction has a format version this node doesn't know
+    UnknownVersion {
+        version: u32,
+    },
+    // block doesn't build on the last block
+    WrongPrevious,
+    // first block doesn't commit to the network of the node
+    WrongNetwork,
+    // first block isn't the genesis block of the network
+    WrongGenesis,
+    // block doesn't match the checkpoint at its height
+    CheckpointMismatch {
+        height: usize,
+    },
+    // block would replace a block below the last checkpoint
+    BelowCheckpoint {
+        height: usize,
+        checkpoint: usize,
+    },
+    // block would replace too many blocks of the active chain
+    ForkTooDeep {
+        height: usize,
+        min_height: usize,
+    },
+    // too many blocks are known at the height of the block
+    TooManyBlocks {
+        height: usize,
+        limit: usize,
+    },
+    // block hash doesn't satisfy the difficulty
+    InsufficientWork,
+    // block claims another difficulty than expected
+    WrongTarget,
+    // merkle root doesn't match the transactions
+    BadMerkleRoot,
+    // block or transaction couldn't be hashed
+    HashFailed,
+    // previous block is unknown
+    OrphanBlock,
+    // block has more transactions than allowed
+    TooManyTransactions {
+        count: usize,
+        limit: usize,
+    },
+    // serialized block is bigger than allowed
+    BlockTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    // more than one transaction without inputs in a block
+    DuplicateCoinbase,
+    // block after the genesis block doesn't start with a coinbase
+    MissingCoinbase,
+    // coinbase isn't the first transaction of the block
+    CoinbaseNotFirst {
+        index: usize,
+    },
+    // coinbase has no outputs
+    EmptyCoinbase,
+    // coinbase output isn't locked to a valid address of the network
+    MalformedCoinbaseLock {
+        output: usize,
+    },
+    // coinbase nonce isn't the block height
+    BadCoinbaseNonce {
+        nonce: u128,
+        height: usize,
+    },
+    // a transaction of the block is invalid
+    InvalidTransaction {
+        index: usize,
+        error: Box<ValidationError>,
+    },
+    // spent output doesn't exist (anymore)
+    MissingUtxo {
+        hash: [u8; 32],
+        index: usize,
+    },
+    // transaction spends a coinbase output before it matured
+    ImmatureCoinbase {
+        hash: [u8; 32],
+        index: usize,
+        // blocks since the coinbase output was created
+        depth: usize,
+        maturity: usize,
+    },
+    // transaction spends the same output more than once
+    DuplicateInput {
+        hash: [u8; 32],
+        index: usize,
+    },
+    // solution doesn't unlock the lock
+    ScriptFailure {
+        input: usize,
+        position: usize,
+        opcode: String,
+    },
+    // solution or lock is longer than allowed
+    ScriptTooLong {
+        len: usize,
+        limit: usize,
+    },
+    // transaction doesn't spend anything
+    NoInputValue,
+    // transaction creates more value than it spends
+    Overspend {
+        vin_total: u128,
+        vout_total: u128,
+    },
+    // coinbase claims more than reward + fees
+    RewardTooHigh {
+        claimed: u128,
+        allowed: u128,
+    },
+    // a block of the blockchain is invalid
+    InvalidBlock {
+        height: usize,
+        error: Box<ValidationError>,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::InvalidTimestamp { timestamp, median } => write!(
+                f,
+                "timestamp {} is older than the median time past ({})",
+                timestamp, median
+            ),
+            ValidationError::FutureTimestamp { timestamp, max } => write!(
+                f,
+                "timestamp {} is later than the allowed {}",
+                timestamp, max
+            ),
+            ValidationError::UnknownVersion { version } => write!(f, "unknown version {}", version),
+            ValidationError::WrongPrevious => write!(f, "previous hash doesn't match"),
+            ValidationError::WrongNetwork => write!(f, "block belongs to another network"),
+            ValidationError::WrongGenesis => write!(f, "block isn't the genesis block"),
+            ValidationError::CheckpointMismatch { height } => {
+                write!(f, "block doesn't match the checkpoint at height {}", height)
+            }
+            ValidationError::BelowCheckpoint { height, checkpoint } => write!(
+                f,
+                "block at height {} forks below the checkpoint at height {}",
+                height, checkpoint
+            ),
+            ValidationError::ForkTooDeep { height, min_height } => write!(
+                f,
+                "block at height {} forks too deep, the lowest allowed height is {}",
+                height, min_height
+            ),
+            ValidationError::TooManyBlocks { height, limit } => {
+                write!(f, "already {} blocks are known at height {}", limit, height)
+            }
+            ValidationError::InsufficientWork => {
+                write!(f, "block hash doesn't satisfy the difficulty")
+            }
+            ValidationError::WrongTarget => write!(f, "block has an unexpected difficulty"),
+            ValidationError::BadMerkleRoot => {
+                write!(f, "merkle root doesn't match the transactions")
+            }
+            ValidationError::HashFailed => write!(f, "couldn't be hashed"),
+            ValidationError::OrphanBlock => write!(f, "previous block is unknown"),
+            ValidationError::TooManyTransactions { count, limit } => write!(
+                f,
+                "block has {} transactions but only {} are allowed",
+                count, limit
+            ),
+            ValidationError::BlockTooLarge { size, limit } => {
+                write!(f, "block has {} bytes but only {} are allowed", size, limit)
+            }
+            ValidationError::DuplicateCoinbase => write!(f, "two coinbase transactions found"),
+            ValidationError::MissingCoinbase => {
+                write!(f, "block doesn't start with a coinbase transaction")
+            }
+            ValidationError::CoinbaseNotFirst { index } => {
+                write!(f, "coinbase is transaction {} instead of the first", index)
+            }
+            ValidationError::EmptyCoinbase => write!(f, "coinbase has no outputs"),
+            ValidationError::MalformedCoinbaseLock { output } => {
+                write!(f, "coinbase output {} has a malformed lock", output)
+            }
+            ValidationError::BadCoinbaseNonce { nonce, height } => write!(
+                f,
+                "coinbase nonce {} doesn't match the block height {}",
+                nonce, height
+            ),
+            ValidationError::InvalidTransaction { index, error } => {
+                write!(f, "transaction {} is invalid: {}", index, error)
+            }
+            ValidationError::MissingUtxo { hash, index } => {
+                write!(f, "utxo {}:{} not found", hash.to_hex(), index)
+            }
+            ValidationError::ImmatureCoinbase {
+                hash,
+                index,
+                depth,
+                maturity,
+            } => write!(
+                f,
+                "coinbase utxo {}:{} is {} blocks deep but needs {}",
+                hash.to_hex(),
+                index,
+                depth,
+                maturity
+            ),
+            ValidationError::DuplicateInput { hash, index } => {
+                write!(f, "utxo {}:{} is spent twice", hash.to_hex(), index)
+            }
+            ValidationError::ScriptFailure {
+                input,
+                position,
+                opcode,
+            } => write!(
+                f,
+                "script of input {} failed at `{}` (position {})",
+                input, opcode, position
+            ),
+            ValidationError::ScriptTooLong { len, limit } => {
+                write!(f, "script has {} bytes but only {} are allowed", len, limit)
+            }
+            ValidationError::NoInputValue => write!(f, "transaction doesn't spend anything"),
+            ValidationError::Overspend {
+                vin_total,
+                vout_total,
+            } => write!(
+                f,
+                "outputs ({}) exceed the inputs ({})",
+                vout_total, vin_total
+            ),
+            ValidationError::RewardTooHigh { claimed, allowed } => write!(
+                f,
+                "coinbase claims {} but only {} is allowed",
+                claimed, allowed
+            ),
+            ValidationError::InvalidBlock { height, error } => {
+                write!(f, "block {} is invalid: {}", height, error)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
 pub mod difficulty {
-    use std::{
-        io::{Error, ErrorKind},
-        vec,
-    };
+    use std::io::Error;
+
+    use num::BigUint;
 
+    use super::debug;
     use crate::{
-        settings::Settings,
-        utils::{arr_to_bi, bi_to_arr},
+        settings::{Retarget, Settings},
+        target::Target,
     };
 
-    // create a difficulty array
-    pub fn create(num_zeros: usize) -> Result<[u8; 32], Error> {
-        // will crash otherwise
-        if num_zeros > 32 {
-            return Err(Error::new(ErrorKind::Other, "num_zeros cannot exceed 32!"));
+    // create a target with `zero_bits` leading zero bits
+    // every zero bit doubles the work, rounded to the compact encoding
+    pub fn create(zero_bits: u32) -> Result<Target, Error> {
+        // the target would be zero otherwise
+        if zero_bits > 255 {
+            return Err(Error::other("zero_bits cannot exceed 255!"));
         }
 
-        // create zeros
-        let mut diff = vec![0x00_u8; num_zeros];
-        // fill other bytes
-        diff.append(&mut vec![0xFF_u8; 32 - num_zeros]);
-        // try to convert to array
-        diff.try_into()
-            .map_err(|_| Error::new(ErrorKind::Other, "failed to convert vec<u8> to [u8; 32]"))
+        let bi = (BigUint::from(1u8) << (256 - zero_bits)) - 1u8;
+        Ok(Target::from_biguint(&bi)
+            .expect("UNREACHABLE!")
+            .normalized())
     }
 
     // check if the difficulty is satisfied
-    // basically if the arrays were represented as a number
-    // the hash has to be lower or equal to the difficulty
-    pub fn satisfies(difficulty: &[u8; 32], hash: &[u8; 32]) -> bool {
-        for i in 0_usize..32_usize {
-            if hash[i] > difficulty[i] {
-                return false;
-            }
+    // the hash has to be lower or equal to the target
+    pub fn satisfies(difficulty: &Target, hash: &[u8; 32]) -> bool {
+        difficulty.is_satisfied_by(hash)
+    }
 
-            if difficulty[i] > hash[i] {
-                return true;
-            }
+    // difficulty of the block at `height` with the algorithm of the settings
+    // `difficulty` is the difficulty of the last block
+    // `timestamp_at(i)` and `target_at(i)` return the timestamp
+    // and difficulty of the block at height i
+    pub fn next(
+        difficulty: Target,
+        height: usize,
+        timestamp_at: impl Fn(usize) -> u64,
+        target_at: impl Fn(usize) -> Target,
+        settings: &Settings,
+    ) -> Target {
+        match settings.retarget {
+            Retarget::Windowed => windowed(difficulty, height, timestamp_at, settings),
+            Retarget::Lwma => lwma(difficulty, height, timestamp_at, target_at, settings),
         }
-        true
     }
 
-    // will adjust the difficulty to try to reach target block time
-    pub fn adjusted(current: &[u8; 32], time_interval: u64, settings: &Settings) -> [u8; 32] {
-        // calculate the current ratio
-        let ratio = ((settings.target_time * settings.adjustment_interval as u64) as f64)
-            / (time_interval as f64);
+    // the difficulty is only adjusted every `adjustment_interval` blocks
+    // based on the time the last interval took
+    pub fn windowed(
+        difficulty: Target,
+        height: usize,
+        timestamp_at: impl Fn(usize) -> u64,
+        settings: &Settings,
+    ) -> Target {
+        let interval = settings.adjustment_interval as usize;
+        if height < interval || !height.is_multiple_of(interval) {
+            return difficulty;
+        }
 
-        // convert difficulty to bigint for easier calculations
-        let mut diff_bi = arr_to_bi(current);
-        // divide first to avoid buffer overflow
-        diff_bi /= (ratio * (10u64.pow(settings.precision) as f64)) as u64;
-        diff_bi *= 10u64.pow(settings.precision);
+        // timestamps aren't strictly increasing
+        let time_interval =
+            timestamp_at(height - 1).saturating_sub(timestamp_at(height - interval));
+
+        debug!(
+            "Targeted block time: {}, Current block time: {}",
+            settings.target_time,
+            time_interval as f64 / interval as f64
+        );
 
-        bi_to_arr(&diff_bi)
+        adjusted(&difficulty, time_interval, settings)
     }
-}
 
-pub mod utils {
-    use std::error::Error;
+    // linearly weighted moving average, adjusts the difficulty every block
+    // based on the last `adjustment_interval` blocks, recent blocks weigh more
+    pub fn lwma(
+        difficulty: Target,
+        height: usize,
+        timestamp_at: impl Fn(usize) -> u64,
+        target_at: impl Fn(usize) -> Target,
+        settings: &Settings,
+    ) -> Target {
+        let n = settings.adjustment_interval as u64;
+        if (height as u64) <= n {
+            return difficulty;
+        }
 
-    use crate::ecdsa::msg_from_str;
-    use crate::hex::ToHex;
-    use crate::settings::Settings;
-    use crate::{hashes, script};
+        let mut weighted_times = BigUint::from(0u8);
+        let mut targets = BigUint::from(0u8);
 
-    use super::{debug, Transaction, TxStore};
+        for i in 1..=n {
+            let block = height - (n - i) as usize - 1;
+            // limit single solve times, so one timestamp can't move the average much
+            let solve_time = timestamp_at(block)
+                .saturating_sub(timestamp_at(block - 1))
+                .clamp(1, 6 * settings.target_time);
 
-    pub fn hash_utxou(utxou: (&[u8; 32], &usize)) -> Result<[u8; 32], Box<dyn Error>> {
-        let mut bytes: Vec<u8> = Vec::new();
-        bytes.extend(bincode::serialize(utxou.0)?);
-        bytes.extend(bincode::serialize(utxou.1)?);
+            weighted_times += BigUint::from(solve_time * i);
+            targets += target_at(block).to_biguint();
+        }
 
-        Ok(hashes::sha256(&bytes))
+        // average target * weighted solve times / weighted target time
+        let next = targets * weighted_times
+            / (BigUint::from(settings.target_time) * n * n * (n + 1) / 2u8);
+
+        limit(&difficulty, next, settings)
+    }
+
+    // expected number of hashes to find a hash satisfying the difficulty
+    pub fn work(difficulty: &Target) -> BigUint {
+        difficulty.work()
+    }
+
+    // will adjust the difficulty to try to reach target block time
+    // `time_interval` is the time the last `adjustment_interval` blocks took
+    pub fn adjusted(current: &Target, time_interval: u64, settings: &Settings) -> Target {
+        let expected = settings.target_time * settings.adjustment_interval as u64;
+
+        // a bigger target is easier to satisfy, so the target grows
+        // with the time the blocks took
+        let next = current.to_biguint() * time_interval / expected.max(1);
+
+        limit(current, next, settings)
     }
 
-    pub fn is_valid_tx(tx: &Transaction, store: &TxStore) -> bool {
+    // applies the maximum adjustment factor and the minimum difficulty
+    // the result is rounded, so it can be stored in the header
+    fn limit(current: &Target, next: BigUint, settings: &Settings) -> Target {
+        let current = current.to_biguint();
+        let factor = settings.max_adjustment.max(1);
+
+        let next = next
+            .clamp(&current / factor, &current * factor)
+            .min(settings.min_difficulty.to_biguint())
+            // a target of 0 could only be satisfied by a hash of 0
+            .max(BigUint::from(1u8));
+
+        Target::from_biguint(&next)
+            .expect("UNREACHABLE!")
+            .normalized()
+    }
+}
+
+pub mod utils {
+    use crate::ecdsa;
+    use crate::emission;
+    use crate::script;
+    use crate::settings::Settings;
+    use crate::target::Target;
+    use crate::utils::current_time;
+
+    use super::{
+        difficulty, Block, BlockHeader, BlockUndo, SpentOutput, Transaction, TxStore, Utxo,
+        UtxoView, ValidationError, BLOCK_VERSION, TX_VERSION,
+    };
+
+    // `median_time` is the median time past of the chain the store belongs to
+    pub fn is_valid_tx(
+        tx: &Transaction,
+        store: &impl UtxoView,
+        median_time: Option<u64>,
+        settings: &Settings,
+    ) -> Result<(), ValidationError> {
         let vout_total = tx.vout_total();
         let mut vin_total = 0_u128;
 
         // validate inputs
-        for (hash, index, solution) in &tx.vin {
+        for (input, (hash, index, solution)) in tx.vin.iter().enumerate() {
+            // check if utxo isn't already spent by a previous input
+            if tx.vin[..input]
+                .iter()
+                .any(|(h, i, _)| h == hash && i == index)
+            {
+                return Err(ValidationError::DuplicateInput {
+                    hash: *hash,
+                    index: *index,
+                });
+            }
+
             // check if utxo exists
-            if let Some((value, lock)) = store.get(hash, index) {
-                // check if utxou is hashable
-                if let Ok(utxou_hash) = hash_utxou((hash, index)) {
-                    let message = msg_from_str(&utxou_hash.to_hex());
-                    // validate script
-                    if script::eval(format!(
-                        "{} {} {}",
-                        solution,
-                        message.as_ref().to_hex(),
-                        lock
-                    ))
-                    .is_none()
-                    {
-                        debug!("Invalid solution!");
-                        return false;
-                    }
+            let utxo = store.get(hash, index).ok_or(ValidationError::MissingUtxo {
+                hash: *hash,
+                index: *index,
+            })?;
+
+            // coinbase outputs can vanish in a reorg, so they can only be spent
+            // once they are deep enough in the chain
+            // the transaction can be mined at the height of the store at the earliest
+            let depth = store.height().saturating_sub(utxo.height);
+            if utxo.coinbase && depth < settings.coinbase_maturity {
+                return Err(ValidationError::ImmatureCoinbase {
+                    hash: *hash,
+                    index: *index,
+                    depth,
+                    maturity: settings.coinbase_maturity,
+                });
+            }
 
-                    vin_total += value;
-                } else {
-                    debug!("Couldn't hash utxou!");
-                    return false;
+            // validate script
+            let context = script::Context::new(tx, input, settings).with_chain(
+                store.height(),
+                median_time,
+                utxo.height,
+            );
+            script::verify_text(solution, &utxo.lock, &context).map_err(|e| {
+                ValidationError::ScriptFailure {
+                    input,
+                    position: e.position,
+                    opcode: e.opcode,
                 }
-            } else {
-                debug!("UTXO not found!");
-                return false;
+            })?;
+
+            vin_total += utxo.value;
+        }
+
+        if vin_total == 0 {
+            return Err(ValidationError::NoInputValue);
+        }
+
+        if vin_total < vout_total {
+            return Err(ValidationError::Overspend {
+                vin_total,
+                vout_total,
+            });
+        }
+
+        Ok(())
+    }
+
+    // median of the last `settings.median_time_span` timestamps
+    // timestamps have to be passed from the oldest to the newest
+    pub fn median_time_past(timestamps: &[u64], settings: &Settings) -> Option<u64> {
+        let start = timestamps.len().saturating_sub(settings.median_time_span);
+        let mut last = timestamps[start..].to_vec();
+        last.sort_unstable();
+
+        last.get(last.len() / 2).copied()
+    }
+
+    // validates a header on top of the `last` header
+    // only the proof of work is validated for the first header
+    // `median_time` is the median time past of the previous blocks
+    pub fn validate_header(
+        header: &BlockHeader,
+        last: Option<&BlockHeader>,
+        median_time: Option<u64>,
+        difficulty: &Target,
+        settings: &Settings,
+    ) -> Result<(), ValidationError> {
+        if header.version != BLOCK_VERSION {
+            return Err(ValidationError::UnknownVersion {
+                version: header.version,
+            });
+        }
+
+        // validate timestamp
+        // a block can't be older than the last blocks
+        if let Some(median) = median_time {
+            if header.timestamp < median {
+                return Err(ValidationError::InvalidTimestamp {
+                    timestamp: header.timestamp,
+                    median,
+                });
             }
         }
+        // and can't be too far in the future
+        let max = current_time() + settings.max_future_drift;
+        if header.timestamp > max {
+            return Err(ValidationError::FutureTimestamp {
+                timestamp: header.timestamp,
+                max,
+            });
+        }
+
+        // validate previous
+        // the first block points to the network instead
+        if let Some(last) = last {
+            let last_hash = last.hash(None).map_err(|_| ValidationError::HashFailed)?;
+            if header.previous != last_hash {
+                return Err(ValidationError::WrongPrevious);
+            }
+        } else if header.previous != settings.network.genesis_previous() {
+            return Err(ValidationError::WrongNetwork);
+        }
+
+        // validate difficulty
+        if header.bits != difficulty.to_compact() {
+            return Err(ValidationError::WrongTarget);
+        }
+
+        // validate nonce
+        let hash = header.hash(None).map_err(|_| ValidationError::HashFailed)?;
+        if !difficulty::satisfies(difficulty, &hash) {
+            return Err(ValidationError::InsufficientWork);
+        }
 
-        vin_total > 0 && vin_total >= vout_total
+        Ok(())
     }
 
-    pub fn add_tx_to_store(tx: &Transaction, store: &mut TxStore) {
+    // checks the block hash against the genesis block and the checkpoints
+    pub fn check_checkpoint(
+        height: usize,
+        hash: &[u8; 32],
+        settings: &Settings,
+    ) -> Result<(), ValidationError> {
+        if height == 0 {
+            let genesis = Block::genesis(settings)
+                .hash(None)
+                .map_err(|_| ValidationError::HashFailed)?;
+            if hash != &genesis {
+                return Err(ValidationError::WrongGenesis);
+            }
+        }
+
+        match settings.checkpoints.iter().find(|(h, _)| *h == height) {
+            Some((_, checkpoint)) if checkpoint != hash => {
+                Err(ValidationError::CheckpointMismatch { height })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // number of blocks which can't be replaced anymore,
+    // the genesis block and all blocks up to the last checkpoint
+    pub fn final_height(settings: &Settings) -> usize {
+        settings
+            .checkpoints
+            .iter()
+            .map(|(height, _)| height + 1)
+            .max()
+            .unwrap_or(0)
+            .max(1)
+    }
+
+    // checks the nonce and outputs of the coinbase of the block at `height`
+    // the amount is checked against the fees of the block in valid_next
+    pub fn check_coinbase(
+        tx: &Transaction,
+        height: usize,
+        settings: &Settings,
+    ) -> Result<(), ValidationError> {
+        // so that every new coinbase transaction gets a new hash
+        // it is set that the nonce is the block_height
+        if tx.nonce != height as u128 {
+            return Err(ValidationError::BadCoinbaseNonce {
+                nonce: tx.nonce,
+                height,
+            });
+        }
+
+        if tx.vout.is_empty() {
+            return Err(ValidationError::EmptyCoinbase);
+        }
+
+        // new coins have to be spendable by an address of the network,
+        // either a key or a script address
+        for (output, (_, lock)) in tx.vout.iter().enumerate() {
+            let valid = ecdsa::lock_to_addr(lock)
+                .is_some_and(|address| ecdsa::is_valid_addr(address, settings.address_version))
+                || ecdsa::lock_to_script_addr(lock).is_some_and(|address| {
+                    ecdsa::is_valid_addr(address, settings.script_address_version)
+                });
+            if !valid {
+                return Err(ValidationError::MalformedCoinbaseLock { output });
+            }
+        }
+
+        Ok(())
+    }
+
+    // checks the version and the consensus limits which don't depend on the store
+    pub fn check_tx_limits(tx: &Transaction, settings: &Settings) -> Result<(), ValidationError> {
+        if tx.version != TX_VERSION {
+            return Err(ValidationError::UnknownVersion {
+                version: tx.version,
+            });
+        }
+
+        let scripts = tx
+            .vin
+            .iter()
+            .map(|(_, _, solution)| solution)
+            .chain(tx.vout.iter().map(|(_, lock)| lock));
+
+        for script in scripts {
+            if script.len() > settings.max_script_len {
+                return Err(ValidationError::ScriptTooLong {
+                    len: script.len(),
+                    limit: settings.max_script_len,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns the spent outputs which are needed to revert the transaction
+    // the new outputs are created at the height of the store
+    pub fn add_tx_to_store(tx: &Transaction, store: &mut TxStore) -> Vec<SpentOutput> {
+        let mut spent = Vec::new();
+
         // remove used transaction outputs
         for (hash, index, _) in &tx.vin {
+            if let Some(utxo) = store.get(hash, index) {
+                spent.push((*hash, *index, utxo.clone()));
+            }
             store.remove(hash, index)
         }
 
         // add new utxo's
-        for (index, utxo) in tx.vout.iter().enumerate() {
+        let hash = tx.hash().expect("Transaction couldn't be hashed!");
+        for (index, (value, lock)) in tx.vout.iter().enumerate() {
             store.set(
-                &tx.hash().expect("Transaction couldn't be hashed!"),
+                &hash,
                 index,
-                utxo.clone(),
+                Utxo {
+                    value: *value,
+                    lock: lock.clone(),
+                    height: store.height(),
+                    coinbase: tx.vin.is_empty(),
+                },
             );
         }
+
+        spent
+    }
+
+    // adds the transactions of the next block to the store
+    // returns the undo record to remove the block again
+    pub fn add_block_to_store(block: &Block, store: &mut TxStore) -> BlockUndo {
+        let undo = block
+            .transactions
+            .iter()
+            .map(|tx| add_tx_to_store(tx, store))
+            .collect();
+        store.1 += 1;
+
+        BlockUndo(undo)
+    }
+
+    // reverts the last block added with add_block_to_store
+    pub fn remove_block_from_store(block: &Block, undo: &BlockUndo, store: &mut TxStore) {
+        // revert the transactions in reverse order
+        // so outputs spent in the same block are restored correctly
+        for (index, tx) in block.transactions.iter().enumerate().rev() {
+            remove_tx_from_store(tx, undo.spent(index).unwrap_or_default(), store);
+        }
+        store.1 = store.1.saturating_sub(1);
     }
 
+    // reverts a transaction added with add_tx_to_store
+    pub fn remove_tx_from_store(tx: &Transaction, spent: &[SpentOutput], store: &mut TxStore) {
+        // remove the created utxo's
+        let hash = tx.hash().expect("Transaction couldn't be hashed!");
+        for index in 0..tx.vout.len() {
+            store.remove(&hash, &index);
+        }
+
+        // restore the spent transaction outputs
+        for (hash, index, utxo) in spent {
+            store.set(hash, *index, utxo.clone());
+        }
+    }
+
+    // see emission::block_reward
     pub fn calculate_mining_reward(block_height: usize, settings: &Settings) -> u128 {
-        // based on formula reward = floor( start_reward / 2^( floor(block_height / halving_interval) ) )
-        // https://www.desmos.com/calculator
+        emission::block_reward(block_height, settings)
+    }
 
-        let num_halvings = (block_height / settings.halvings_interval) as u32;
+    // transaction with the hash and the height of its block
+    // works on any range of blocks, like the ones returned by the server
+    pub fn find_transaction(blocks: &[Block], hash: &[u8; 32]) -> Option<(Transaction, usize)> {
+        for (index, block) in blocks.iter().enumerate() {
+            for tx in &block.transactions {
+                if let Ok(tx_hash) = tx.hash() {
+                    if &tx_hash == hash {
+                        return Some((tx.clone(), index));
+                    }
+                }
+            }
+        }
 
-        settings.start_mining_reward / 2_i32.pow(num_halvings) as u128
+        None
     }
 }
 
+// current version of the transaction format
+pub const TX_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
+    // version of the transaction format
+    pub version: u32,
+
     // unique number generated by the transaction sender to identify transaction
     pub nonce: u128,
 
@@ -187,72 +808,365 @@ pub struct Transaction {
 impl Transaction {
     pub fn new_coinbase(block_height: usize, reward: u128, lock: String) -> Self {
         Self {
+            version: TX_VERSION,
             nonce: block_height as u128,
             vin: vec![],
             vout: vec![(reward, lock)],
         }
     }
 
+    // hash of the canonical encoding
     pub fn hash(&self) -> Result<[u8; 32], Box<dyn Error>> {
-        let mut bytes: Vec<u8> = Vec::new();
-        bytes.extend(bincode::serialize(&self.nonce)?);
-        bytes.extend(bincode::serialize(&self.vin)?);
-        bytes.extend(bincode::serialize(&self.vout)?);
+        Ok(hashes::sha256(&encoding::encode(self)))
+    }
 
-        Ok(hashes::sha256(&bytes))
+    // size of the canonical encoding in bytes
+    pub fn size(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(encoding::encode(self).len())
     }
 
+    // saturates instead of overflowing on outputs which can't be valid anyway
     pub fn vout_total(&self) -> u128 {
-        self.vout.iter().map(|utxo| utxo.0).sum()
+        self.vout
+            .iter()
+            .fold(0, |total, utxo| total.saturating_add(utxo.0))
     }
 
-    pub fn vin_total(&self, store: &TxStore) -> Option<u128> {
+    pub fn vin_total(&self, store: &impl UtxoView) -> Option<u128> {
         let mut total = 0_u128;
 
         for (hash, index, _) in &self.vin {
-            total += store.get(hash, index)?.0
+            total += store.get(hash, index)?.value
         }
 
         Some(total)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block {
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.version.encode(out);
+        self.nonce.encode(out);
+        self.vin.encode(out);
+        self.vout.encode(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            version: Decode::decode(input)?,
+            nonce: Decode::decode(input)?,
+            vin: Decode::decode(input)?,
+            vout: Decode::decode(input)?,
+        })
+    }
+}
+
+// builds the coinbase transaction of the block at `height`
+#[derive(Debug, Clone)]
+pub struct Coinbase {
+    height: usize,
+    // reward and fees the coinbase can claim
+    allowed: u128,
+    outputs: Vec<(u128, String)>,
+    settings: Settings,
+}
+
+impl Coinbase {
+    // `fees` are the fees of the other transactions of the block
+    pub fn new(height: usize, fees: u128, settings: &Settings) -> Self {
+        Self {
+            height,
+            allowed: calculate_mining_reward(height, settings).saturating_add(fees),
+            outputs: Vec::new(),
+            settings: *settings,
+        }
+    }
+
+    pub fn allowed(&self) -> u128 {
+        self.allowed
+    }
+
+    // amount which isn't paid to an output yet and would be burned
+    pub fn remaining(&self) -> u128 {
+        let paid = self
+            .outputs
+            .iter()
+            .fold(0_u128, |total, (value, _)| total.saturating_add(*value));
+
+        self.allowed.saturating_sub(paid)
+    }
+
+    pub fn pay(mut self, value: u128, lock: String) -> Self {
+        self.outputs.push((value, lock));
+        self
+    }
+
+    // pays everything which isn't paid yet to the lock
+    pub fn pay_remaining(self, lock: String) -> Self {
+        let remaining = self.remaining();
+        self.pay(remaining, lock)
+    }
+
+    // fails if the coinbase wouldn't be valid in a block with the given fees
+    pub fn build(self) -> Result<Transaction, ValidationError> {
+        let tx = Transaction {
+            version: TX_VERSION,
+            nonce: self.height as u128,
+            vin: vec![],
+            vout: self.outputs,
+        };
+
+        check_coinbase(&tx, self.height, &self.settings)?;
+        if tx.vout_total() > self.allowed {
+            return Err(ValidationError::RewardTooHigh {
+                claimed: tx.vout_total(),
+                allowed: self.allowed,
+            });
+        }
+
+        Ok(tx)
+    }
+}
+
+// value created and moved by a block
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockFees {
+    // newly created coins the coinbase could claim
+    pub reward: u128,
+    // fees of the other transactions
+    pub fees: u128,
+    // amount of the coinbase outputs
+    pub claimed: u128,
+}
+
+impl BlockFees {
+    // reward and fees which the coinbase didn't claim
+    pub fn burned(&self) -> u128 {
+        self.reward
+            .saturating_add(self.fees)
+            .saturating_sub(self.claimed)
+    }
+}
+
+// current version of the block format
+pub const BLOCK_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    // version of the block format
+    pub version: u32,
+
     // when was block mined
     pub timestamp: u64,
 
     // hash of the previous block
     pub previous: [u8; 32],
 
+    // merkle root of the transaction hashes
+    pub merkle_root: [u8; 32],
+
+    // compact encoding of the target the block hash has to satisfy
+    pub bits: u32,
+
     // number with which the block satisfies the difficulty
     pub nonce: u128,
+}
+
+impl BlockHeader {
+    // the hash of the header is the hash of the block (proof of work)
+    // it is the hash of the canonical encoding with the nonce replaced
+    pub fn hash(&self, nonce: Option<u128>) -> Result<[u8; 32], Box<dyn Error>> {
+        let mut bytes: Vec<u8> = Vec::new();
+        self.encode_with_nonce(nonce.unwrap_or(self.nonce), &mut bytes);
+
+        Ok(hashes::sha256(&bytes))
+    }
+
+    // None if the bits aren't a valid compact target
+    pub fn target(&self) -> Option<Target> {
+        Target::from_compact(self.bits)
+    }
+
+    // expected number of hashes needed to produce the header
+    // invalid targets don't add any work
+    pub fn work(&self) -> BigUint {
+        self.target().map(Target::work).unwrap_or_default()
+    }
+
+    fn encode_with_nonce(&self, nonce: u128, out: &mut Vec<u8>) {
+        self.version.encode(out);
+        self.timestamp.encode(out);
+        self.previous.encode(out);
+        self.merkle_root.encode(out);
+        self.bits.encode(out);
+        nonce.encode(out);
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_with_nonce(self.nonce, out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            version: Decode::decode(input)?,
+            timestamp: Decode::decode(input)?,
+            previous: Decode::decode(input)?,
+            merkle_root: Decode::decode(input)?,
+            bits: Decode::decode(input)?,
+            nonce: Decode::decode(input)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Block {
+    pub header: BlockHeader,
 
     // transactions included in the block
     pub transactions: Vec<Transaction>,
 }
 
 impl Block {
+    // first block of the network, every chain has to start with it
+    // its coinbase pays nothing, so there are no coins to spend
+    pub fn genesis(settings: &Settings) -> Self {
+        let coinbase =
+            Transaction::new_coinbase(0, 0, format!("macc {} genesis", settings.network));
+
+        let mut block = Self::new(
+            settings.genesis_timestamp,
+            settings.network.genesis_previous(),
+            settings.start_difficulty,
+            vec![coinbase],
+        )
+        .expect("UNREACHABLE!");
+        block.header.nonce = settings.genesis_nonce;
+
+        block
+    }
+
+    // creates a block with the merkle root of the transactions
+    // the nonce still has to be found
+    pub fn new(
+        timestamp: u64,
+        previous: [u8; 32],
+        target: Target,
+        transactions: Vec<Transaction>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            timestamp,
+            previous,
+            merkle_root: merkle::root(&tx_hashes(&transactions)?),
+            bits: target.to_compact(),
+            nonce: 0,
+        };
+
+        Ok(Self {
+            header,
+            transactions,
+        })
+    }
+
     pub fn hash(&self, nonce: Option<u128>) -> Result<[u8; 32], Box<dyn Error>> {
-        let nonce = nonce.unwrap_or(self.nonce);
+        self.header.hash(nonce)
+    }
 
-        let mut bytes: Vec<u8> = Vec::new();
-        bytes.extend(bincode::serialize(&self.timestamp)?);
-        bytes.extend(bincode::serialize(&self.previous)?);
-        bytes.extend(bincode::serialize(&nonce)?);
-        bytes.extend(bincode::serialize(&self.transactions)?);
+    pub fn tx_hashes(&self) -> Result<Vec<[u8; 32]>, Box<dyn Error>> {
+        tx_hashes(&self.transactions)
+    }
 
-        Ok(hashes::sha256(&bytes))
+    // size of the canonical encoding in bytes
+    pub fn size(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(encoding::encode(self).len())
+    }
+
+    // proof that the transaction at `index` is part of this block
+    pub fn merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        merkle::proof(&self.tx_hashes().ok()?, index)
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.transactions.encode(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            header: Decode::decode(input)?,
+            transactions: Decode::decode(input)?,
+        })
     }
 }
 
+fn tx_hashes(transactions: &[Transaction]) -> Result<Vec<[u8; 32]>, Box<dyn Error>> {
+    transactions.iter().map(|tx| tx.hash()).collect()
+}
+
+// transaction hash, index and the output spent by a transaction
+pub type SpentOutput = ([u8; 32], usize, Utxo);
+
+// outputs spent by the transactions of a block
+// with them the block can be removed from the store again
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockUndo(Vec<Vec<SpentOutput>>);
+
+impl BlockUndo {
+    // spent outputs of the transaction at `index`
+    pub fn spent(&self, index: usize) -> Option<&[SpentOutput]> {
+        self.0.get(index).map(|spent| spent.as_slice())
+    }
+}
+
+// Blockchain stores the vector of blocks and their undo records
 #[derive(Serialize, Deserialize, Debug, Clone)]
-// Blockchain stores only the vector of blocks
-pub struct Blockchain(Vec<Block>);
+pub struct Blockchain(Vec<Block>, Vec<BlockUndo>);
 
 impl Blockchain {
+    // blockchain with only the genesis block of the network
+    pub fn new(store: &mut TxStore, settings: &Settings) -> Self {
+        let mut blockchain = Self::new_empty();
+        blockchain.add(store, Block::genesis(settings));
+
+        blockchain
+    }
+
     pub fn new_empty() -> Self {
-        Self { 0: Vec::new() }
+        Self(Vec::new(), Vec::new())
+    }
+
+    // creates the blockchain by applying every block to an empty store
+    // to recompute the undo records, so it takes as long as syncing the chain
+    // doesn't check if the blocks are valid, use is_valid for that
+    pub fn replay(blocks: Vec<Block>) -> Self {
+        let mut blockchain = Self::new_empty();
+        let mut store = TxStore::new_empty();
+
+        for block in blocks {
+            blockchain.add(&mut store, block);
+        }
+
+        blockchain
+    }
+
+    // creates the blockchain from blocks and their already known undo records
+    // returns None if there isn't exactly one undo record per block
+    pub fn from_parts(blocks: Vec<Block>, undo: Vec<BlockUndo>) -> Option<Self> {
+        if blocks.len() != undo.len() {
+            return None;
+        }
+
+        Some(Self(blocks, undo))
     }
 
     pub fn height(&self) -> usize {
@@ -261,7 +1175,7 @@ impl Blockchain {
 
     pub fn at(&self, i: i32) -> &Block {
         let idx: usize = if i < 0 {
-            self.height() - i.abs() as usize
+            self.height() - i.unsigned_abs() as usize
         } else {
             i as usize
         };
@@ -280,157 +1194,337 @@ impl Blockchain {
         &self,
         block: &Block,
         store: &TxStore,
-        difficulty: &[u8; 32],
+        difficulty: &Target,
         settings: &Settings,
-    ) -> Option<bool> {
-        // validate timestamp
-        // doesn't need validation if first block
-        if !self.0.is_empty() && block.timestamp < (self.0.last()?).timestamp {
-            debug!("Invalid timestamp!");
-            return Some(false);
+    ) -> Result<(), ValidationError> {
+        let header = &block.header;
+
+        // validate timestamp, previous and nonce
+        validate_header(
+            header,
+            self.0.last().map(|last| &last.header),
+            self.median_time_past(settings),
+            difficulty,
+            settings,
+        )?;
+
+        // the genesis block and checkpoints are fixed
+        let hash = header.hash(None).map_err(|_| ValidationError::HashFailed)?;
+        check_checkpoint(self.height(), &hash, settings)?;
+
+        // validate size limits
+        if block.transactions.len() > settings.max_block_txs {
+            return Err(ValidationError::TooManyTransactions {
+                count: block.transactions.len(),
+                limit: settings.max_block_txs,
+            });
         }
-
-        // validate previous
-        // doesn't need validation if first block
-        if !self.0.is_empty() && block.previous != (self.0.last()?).hash(None).ok()? {
-            debug!("Invalid previous hash!");
-            return Some(false);
+        let size = block.size().map_err(|_| ValidationError::HashFailed)?;
+        if size > settings.max_block_size {
+            return Err(ValidationError::BlockTooLarge {
+                size,
+                limit: settings.max_block_size,
+            });
         }
 
-        // validate nonce
-        if !difficulty::satisfies(difficulty, &block.hash(None).ok()?) {
-            debug!("Invalid nonce!");
-            return Some(false);
+        // validate merkle root
+        let tx_hashes = block.tx_hashes().map_err(|_| ValidationError::HashFailed)?;
+        if header.merkle_root != merkle::root(&tx_hashes) {
+            return Err(ValidationError::BadMerkleRoot);
         }
 
         // validate transaction
-        let mut coinbase_tx: Option<Transaction> = None;
+        // every transaction is applied to an overlay of the store
+        // so outputs can't be spent twice in the same block
+        let mut view = StoreOverlay::new(store);
+        let median_time = self.median_time_past(settings);
+        let mut coinbase_tx: Option<&Transaction> = None;
         let mining_reward = calculate_mining_reward(self.height(), settings);
         let mut fees = 0_u128;
 
-        for tx in &block.transactions {
+        for (index, tx) in block.transactions.iter().enumerate() {
+            check_tx_limits(tx, settings).map_err(|e| ValidationError::InvalidTransaction {
+                index,
+                error: Box::new(e),
+            })?;
+
             // possible coinbase transaction
             if tx.vin.is_empty() {
                 // check if there wasn't already a coinbase transaction
                 if coinbase_tx.is_some() {
-                    debug!("Two coinbase transactions found!");
-                    return Some(false);
+                    return Err(ValidationError::DuplicateCoinbase);
+                }
+                if index != 0 {
+                    return Err(ValidationError::CoinbaseNotFirst { index });
                 }
 
-                // so that every new coinbase transaction gets a new hash
-                // it is set that the nonce is the block_height
-                if tx.nonce != self.height() as u128 {
-                    debug!("Invalid coinbase nonce!");
-                    return Some(false);
+                // the genesis block is fixed by its hash
+                if self.height() > 0 {
+                    check_coinbase(tx, self.height(), settings)?;
                 }
 
                 // valid coinbase transaction found
-                coinbase_tx = Some(tx.clone());
+                coinbase_tx = Some(tx);
                 continue;
             }
 
             // check if transaction valid
-            if !is_valid_tx(tx, store) {
-                debug!("Invalid transaction!");
-                return Some(false);
-            }
+            is_valid_tx(tx, &view, median_time, settings).map_err(|e| {
+                ValidationError::InvalidTransaction {
+                    index,
+                    error: Box::new(e),
+                }
+            })?;
 
             // add fees
-            fees += tx.vin_total(store)? - tx.vout_total();
+            // UNREACHABLE: all inputs exist since the transaction is valid
+            fees += tx.vin_total(&view).expect("UNREACHABLE!") - tx.vout_total();
+
+            // spend the inputs for the following transactions
+            view.add_tx(tx);
+        }
+
+        // every block but the genesis block starts with its coinbase
+        // a coinbase at another position was rejected already
+        if coinbase_tx.is_none() && self.height() > 0 {
+            return Err(ValidationError::MissingCoinbase);
         }
 
         // check if reward isn't too high
+        // whatever the coinbase doesn't claim is burned
+        let allowed = mining_reward.saturating_add(fees);
         if let Some(tx) = coinbase_tx {
-            if tx.vout_total() > mining_reward + fees {
-                debug!("Invalid coinbase transaction reward!");
-                return Some(false);
+            if tx.vout_total() > allowed {
+                return Err(ValidationError::RewardTooHigh {
+                    claimed: tx.vout_total(),
+                    allowed,
+                });
             }
         }
 
-        Some(true)
+        Ok(())
     }
 
     // adds a block to the blockchain
     // doesn't check if the block is valid,
     // so run valid_next(block) first
     pub fn add(&mut self, store: &mut TxStore, block: Block) {
-        for tx in &block.transactions {
-            utils::add_tx_to_store(tx, store);
-        }
+        let undo = utils::add_block_to_store(&block, store);
 
         self.0.push(block);
+        self.1.push(undo);
     }
 
-    pub fn adjust_difficulty(&self, difficulty: [u8; 32], settings: &Settings) -> [u8; 32] {
-        if (self.height() as u32) < settings.adjustment_interval
-            || (self.height() as u32) % settings.adjustment_interval != 0
-        {
-            return difficulty;
+    // undo record of the block at `height`
+    pub fn undo(&self, height: usize) -> Option<&BlockUndo> {
+        self.1.get(height)
+    }
+
+    // removes the last block and reverts its transactions in the store
+    // the store has to be the one the block was added to
+    pub fn disconnect_tip(&mut self, store: &mut TxStore) -> Option<Block> {
+        let block = self.0.pop()?;
+        let undo = self.1.pop().expect("UNREACHABLE!");
+        utils::remove_block_from_store(&block, &undo, store);
+
+        Some(block)
+    }
+
+    // reward, fees and claimed amount of the block at `height`
+    // the fees are taken from the undo records, so they are exact
+    pub fn fees(&self, height: usize, settings: &Settings) -> Option<BlockFees> {
+        let block = self.0.get(height)?;
+        let undo = self.1.get(height)?;
+
+        let mut fees = BlockFees {
+            reward: calculate_mining_reward(height, settings),
+            ..BlockFees::default()
+        };
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            if tx.vin.is_empty() {
+                fees.claimed = fees.claimed.saturating_add(tx.vout_total());
+                continue;
+            }
+
+            let vin_total = undo
+                .spent(index)?
+                .iter()
+                .fold(0_u128, |total, (_, _, utxo)| {
+                    total.saturating_add(utxo.value)
+                });
+            fees.fees = fees
+                .fees
+                .saturating_add(vin_total.saturating_sub(tx.vout_total()));
         }
 
-        let time_interval =
-            self.at(-1).timestamp - self.at(-(settings.adjustment_interval as i32)).timestamp;
+        Some(fees)
+    }
 
-        debug!(
-            "Targeted block time: {}, Current block time: {}",
-            settings.target_time,
-            time_interval as f64 / settings.adjustment_interval as f64
-        );
+    // median timestamp of the last blocks, None if there are no blocks
+    pub fn median_time_past(&self, settings: &Settings) -> Option<u64> {
+        let start = self.height().saturating_sub(settings.median_time_span);
+        let timestamps: Vec<u64> = self.0[start..]
+            .iter()
+            .map(|block| block.header.timestamp)
+            .collect();
+
+        median_time_past(&timestamps, settings)
+    }
 
-        difficulty::adjusted(&difficulty, time_interval, settings)
+    // expected number of hashes needed to produce the whole chain
+    // the chain with the most work is the best chain
+    pub fn total_work(&self) -> BigUint {
+        self.0.iter().map(|block| block.header.work()).sum()
     }
 
-    pub fn is_valid(&self, settings: &Settings) -> Option<(TxStore, [u8; 32])> {
+    // estimated hashes per second over the last `blocks` blocks
+    // None if there are less than two blocks or no time passed
+    pub fn hashrate(&self, blocks: usize) -> Option<BigUint> {
+        let start = self.height().saturating_sub(blocks);
+        let last = self.0.get(start..)?;
+        if last.len() < 2 {
+            return None;
+        }
+
+        // the work of the first block was done before its timestamp
+        let work: BigUint = last[1..].iter().map(|block| block.header.work()).sum();
+        let time = last[last.len() - 1]
+            .header
+            .timestamp
+            .checked_sub(last[0].header.timestamp)
+            .filter(|time| *time > 0)?;
+
+        Some(work / time)
+    }
+
+    // difficulty of the last block
+    pub fn difficulty(&self, settings: &Settings) -> Target {
+        self.0
+            .last()
+            .and_then(|block| block.header.target())
+            .unwrap_or(settings.start_difficulty)
+    }
+
+    // difficulty the next block has to satisfy
+    pub fn next_difficulty(&self, settings: &Settings) -> Target {
+        self.adjust_difficulty(self.difficulty(settings), settings)
+    }
+
+    pub fn adjust_difficulty(&self, difficulty: Target, settings: &Settings) -> Target {
+        difficulty::next(
+            difficulty,
+            self.height(),
+            |i| self.0[i].header.timestamp,
+            // the targets of added blocks are validated
+            |i| self.0[i].header.target().unwrap_or(settings.min_difficulty),
+            settings,
+        )
+    }
+
+    pub fn is_valid(&self, settings: &Settings) -> Result<(TxStore, Target), ValidationError> {
         let mut blockchain = Blockchain::new_empty();
         let mut store = TxStore::new_empty();
         let mut difficulty = settings.start_difficulty;
 
-        for block in &self.0 {
+        for (height, block) in self.0.iter().enumerate() {
             // adjust difficulty
             difficulty = blockchain.adjust_difficulty(difficulty, settings);
 
             // if next block isn't valid, whole blockchain isn't valid
-            if !blockchain.valid_next(block, &store, &difficulty, settings)? {
-                return None;
-            }
+            blockchain
+                .valid_next(block, &store, &difficulty, settings)
+                .map_err(|e| ValidationError::InvalidBlock {
+                    height,
+                    error: Box::new(e),
+                })?;
 
             // add block to blockchain
             blockchain.add(&mut store, block.clone());
         }
 
-        Some((store, difficulty))
+        Ok((store, difficulty))
     }
 
-    pub fn get_transaction(&self, hash: &[u8; 32]) -> Option<(Transaction, usize)> {
-        for (index, block) in self.0.iter().enumerate() {
-            for tx in &block.transactions {
-                if let Ok(tx_hash) = tx.hash() {
-                    if &tx_hash == hash {
-                        return Some((tx.clone(), index));
-                    }
-                }
+    pub fn headers(&self, start: usize, stop: usize) -> Option<Vec<BlockHeader>> {
+        Some(
+            self.slice(start, stop)?
+                .iter()
+                .map(|block| block.header.clone())
+                .collect(),
+        )
+    }
+
+    // proof that the transaction is part of the blockchain
+    // which can be checked with only the block headers
+    pub fn tx_proof(&self, hash: &[u8; 32]) -> Option<TxProof> {
+        for (height, block) in self.0.iter().enumerate() {
+            let tx_hashes = block.tx_hashes().ok()?;
+
+            if let Some(index) = tx_hashes.iter().position(|tx_hash| tx_hash == hash) {
+                return Some(TxProof {
+                    height,
+                    proof: merkle::proof(&tx_hashes, index)?,
+                });
             }
         }
 
         None
     }
+
+    pub fn get_transaction(&self, hash: &[u8; 32]) -> Option<(Transaction, usize)> {
+        utils::find_transaction(&self.0, hash)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TxStore(HashMap<String, HashMap<usize, (u128, String)>>);
+// balance and a vector of (tx hash, index, value) of the owned utxos
+pub type Owned = (u128, Vec<(String, usize, u128)>);
+
+// unspent transaction output
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    pub value: u128,
+    pub lock: String,
+    // height of the block which created the output
+    pub height: usize,
+    // created by a coinbase transaction
+    pub coinbase: bool,
+}
+
+impl Utxo {
+    // output which isn't from a coinbase transaction
+    pub fn new(value: u128, lock: String, height: usize) -> Self {
+        Self {
+            value,
+            lock,
+            height,
+            coinbase: false,
+        }
+    }
+}
+
+// TxStore stores the utxos after the blocks up to its height
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxStore(HashMap<String, HashMap<usize, Utxo>>, usize);
 
 impl TxStore {
     pub fn new_empty() -> Self {
-        TxStore { 0: HashMap::new() }
+        TxStore(HashMap::new(), 0)
     }
 
-    pub fn get(&self, hash: &[u8; 32], index: &usize) -> Option<&(u128, String)> {
+    // number of blocks applied to the store
+    // which is the height of the next block
+    pub fn height(&self) -> usize {
+        self.1
+    }
+
+    pub fn get(&self, hash: &[u8; 32], index: &usize) -> Option<&Utxo> {
         let key = hash.to_hex();
 
         self.0.get(&key)?.get(index)
     }
 
-    pub fn set(&mut self, hash: &[u8; 32], index: usize, utxo: (u128, String)) {
+    pub fn set(&mut self, hash: &[u8; 32], index: usize, utxo: Utxo) {
         let key = hash.to_hex();
 
         if self.0.get_mut(&key).is_none() {
@@ -455,7 +1549,7 @@ impl TxStore {
         }
     }
 
-    pub fn get_owned(&self, sk_key: String) -> Option<(u128, Vec<(String, usize, u128)>)> {
+    pub fn get_owned(&self, sk_key: String, settings: &Settings) -> Option<Owned> {
         let secp = create_secp();
         let mut client = Client::from_sk_key(sk_key).ok()?;
 
@@ -463,11 +1557,13 @@ impl TxStore {
         let mut balance: u128 = 0;
 
         for (tx_hash, utxos) in &self.0 {
-            for (index, (value, _lock)) in utxos {
+            for (index, Utxo { value, .. }) in utxos {
                 let tx_hash_vec = Vec::from_hex(tx_hash).ok()?.try_into().ok()?;
                 let tx = client.create_transaction(&secp, vec![(tx_hash_vec, *index)], vec![])?;
 
-                if is_valid_tx(&tx, self) {
+                // the store has no timestamps, so outputs locked
+                // until a time are never counted
+                if is_valid_tx(&tx, self, None, settings).is_ok() {
                     balance += value;
                     transactions.push((tx_hash.clone(), *index, *value));
                 }
@@ -477,12 +1573,12 @@ impl TxStore {
         Some((balance, transactions))
     }
 
-    pub fn get_owned_fast(&self, address: String) -> Option<(u128, Vec<(String, usize, u128)>)> {
+    pub fn get_owned_fast(&self, address: String) -> Option<Owned> {
         let mut transactions: Vec<(String, usize, u128)> = Vec::new();
         let mut balance: u128 = 0;
 
         for (tx_hash, utxos) in &self.0 {
-            for (index, (value, lock)) in utxos {
+            for (index, Utxo { value, lock, .. }) in utxos {
                 if lock.contains(&address) {
                     balance += value;
                     transactions.push((tx_hash.clone(), *index, *value));
@@ -493,3 +1589,80 @@ impl TxStore {
         Some((balance, transactions))
     }
 }
+
+// read access to the utxos of a chain
+pub trait UtxoView {
+    fn get(&self, hash: &[u8; 32], index: &usize) -> Option<&Utxo>;
+
+    // height of the next block
+    fn height(&self) -> usize;
+}
+
+impl UtxoView for TxStore {
+    fn get(&self, hash: &[u8; 32], index: &usize) -> Option<&Utxo> {
+        TxStore::get(self, hash, index)
+    }
+
+    fn height(&self) -> usize {
+        TxStore::height(self)
+    }
+}
+
+// changes of transactions on top of a store which isn't modified,
+// only the added and spent outputs are kept
+#[derive(Debug, Clone)]
+pub struct StoreOverlay<'a> {
+    store: &'a TxStore,
+    added: HashMap<([u8; 32], usize), Utxo>,
+    spent: HashSet<([u8; 32], usize)>,
+}
+
+impl<'a> StoreOverlay<'a> {
+    pub fn new(store: &'a TxStore) -> Self {
+        Self {
+            store,
+            added: HashMap::new(),
+            spent: HashSet::new(),
+        }
+    }
+
+    // same as `utils::add_tx_to_store`
+    pub fn add_tx(&mut self, tx: &Transaction) {
+        for (hash, index, _) in &tx.vin {
+            // outputs created in the overlay only have to be forgotten
+            if self.added.remove(&(*hash, *index)).is_none() {
+                self.spent.insert((*hash, *index));
+            }
+        }
+
+        let hash = tx.hash().expect("Transaction couldn't be hashed!");
+        for (index, (value, lock)) in tx.vout.iter().enumerate() {
+            self.added.insert(
+                (hash, index),
+                Utxo {
+                    value: *value,
+                    lock: lock.clone(),
+                    height: self.store.height(),
+                    coinbase: tx.vin.is_empty(),
+                },
+            );
+        }
+    }
+}
+
+impl UtxoView for StoreOverlay<'_> {
+    fn get(&self, hash: &[u8; 32], index: &usize) -> Option<&Utxo> {
+        if let Some(utxo) = self.added.get(&(*hash, *index)) {
+            return Some(utxo);
+        }
+        if self.spent.contains(&(*hash, *index)) {
+            return None;
+        }
+
+        self.store.get(hash, index)
+    }
+
+    fn height(&self) -> usize {
+        self.store.height()
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+
+use num::BigUint;
+
+use crate::blockchain::{
+    difficulty, utils, Block, BlockHeader, Blockchain, Transaction, TxStore, ValidationError,
+};
+use crate::merkle;
+use crate::settings::Settings;
+use crate::target::Target;
+
+// block with its position in the tree
+#[derive(Debug, Clone)]
+struct Node {
+    header: BlockHeader,
+    // only kept for side branches, the blocks of the active chain are in the chain
+    block: Option<Block>,
+    height: usize,
+    // accumulated work of the branch up to and including this block
+    work: BigUint,
+}
+
+// blocks removed from and added to the active chain
+#[derive(Debug, Clone, Default)]
+pub struct Reorg {
+    pub disconnected: Vec<Block>,
+    pub connected: Vec<Block>,
+}
+
+impl Reorg {
+    // transactions of the disconnected blocks which aren't part of the new chain
+    // these have to be returned to the mempool
+    pub fn orphaned_transactions(&self) -> Vec<Transaction> {
+        let connected: Vec<[u8; 32]> = self
+            .connected
+            .iter()
+            .flat_map(|block| block.tx_hashes().unwrap_or_default())
+            .collect();
+
+        self.disconnected
+            .iter()
+            .flat_map(|block| block.transactions.iter())
+            // coinbase transactions are only valid in their own block
+            .filter(|tx| !tx.vin.is_empty())
+            .filter(|tx| match tx.hash() {
+                Ok(hash) => !connected.contains(&hash),
+                Err(_) => false,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+// keeps track of all known branches and follows the one with the most work
+//
+// the tree doesn't own the active chain, it has to be passed in
+// and the tree has to be the only one modifying it
+#[derive(Debug, Clone, Default)]
+pub struct BlockTree {
+    nodes: HashMap<[u8; 32], Node>,
+    // hashes of the blocks building on a block
+    children: HashMap<[u8; 32], Vec<[u8; 32]>>,
+    // number of blocks at a height
+    heights: HashMap<usize, usize>,
+    // hash of the last block of the active chain
+    tip: Option<[u8; 32]>,
+    // side branches building on the active chain below this height are removed
+    pruned_height: usize,
+}
+
+impl BlockTree {
+    pub fn new_empty() -> Self {
+        Self::default()
+    }
+
+    // creates the tree from an already validated chain
+    pub fn from_chain(chain: &Blockchain) -> Self {
+        let mut tree = Self::new_empty();
+
+        for height in 0..chain.height() {
+            let header = chain.at(height as i32).header.clone();
+            let hash = header.hash(None).expect("Block couldn't be hashed!");
+
+            tree.insert_node(hash, header, None, height);
+            tree.tip = Some(hash);
+        }
+
+        tree
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    // block of a side branch, the blocks of the active chain are only in the chain
+    pub fn side_block(&self, hash: &[u8; 32]) -> Option<&Block> {
+        self.nodes.get(hash).and_then(|node| node.block.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // accumulated work of the active chain
+    pub fn tip_work(&self) -> BigUint {
+        self.tip
+            .and_then(|tip| self.nodes.get(&tip))
+            .map(|node| node.work.clone())
+            .unwrap_or_default()
+    }
+
+    fn insert_node(
+        &mut self,
+        hash: [u8; 32],
+        header: BlockHeader,
+        block: Option<Block>,
+        height: usize,
+    ) {
+        let parent_work = match self.nodes.get(&header.previous) {
+            Some(parent) if height > 0 => parent.work.clone(),
+            _ => BigUint::default(),
+        };
+        let work = parent_work + header.work();
+
+        if height > 0 {
+            self.children.entry(header.previous).or_default().push(hash);
+        }
+        *self.heights.entry(height).or_default() += 1;
+        self.nodes.insert(
+            hash,
+            Node {
+                header,
+                block,
+                height,
+                work,
+            },
+        );
+    }
+
+    // keeps the block of a node which left the active chain
+    // and drops it once the node is part of it again
+    fn set_block(&mut self, hash: &[u8; 32], block: Option<Block>) {
+        if let Some(node) = self.nodes.get_mut(hash) {
+            node.block = block;
+        }
+    }
+
+    // checks if the block is part of the active chain
+    fn in_chain(&self, chain: &Blockchain, hash: &[u8; 32]) -> bool {
+        match self.nodes.get(hash) {
+            Some(node) if node.height < chain.height() => {
+                chain.at(node.height as i32).hash(None).ok().as_ref() == Some(hash)
+            }
+            _ => false,
+        }
+    }
+
+    // removes the block and all blocks building on it
+    fn remove_branch(&mut self, hash: &[u8; 32]) {
+        if let Some(node) = self.nodes.get(hash) {
+            let previous = node.header.previous;
+            if let Some(siblings) = self.children.get_mut(&previous) {
+                siblings.retain(|sibling| sibling != hash);
+            }
+        }
+
+        let mut to_remove = vec![*hash];
+
+        while let Some(hash) = to_remove.pop() {
+            if let Some(node) = self.nodes.remove(&hash) {
+                if let Some(count) = self.heights.get_mut(&node.height) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.heights.remove(&node.height);
+                    }
+                }
+            }
+            to_remove.extend(self.children.remove(&hash).unwrap_or_default());
+        }
+    }
+
+    // removes the side branches forking off too deep in the active chain,
+    // they can't become active anymore
+    fn prune(&mut self, chain: &Blockchain, settings: &Settings) {
+        // blocks from `min_height` on can still be replaced,
+        // so the branches building on the block below them are kept
+        let min_height = chain.height().saturating_sub(settings.max_fork_depth);
+        let limit = min_height.saturating_sub(1);
+
+        for height in self.pruned_height.min(limit)..limit {
+            let hash = chain
+                .at(height as i32)
+                .hash(None)
+                .expect("Block couldn't be hashed!");
+
+            let stale: Vec<[u8; 32]> = self
+                .children
+                .get(&hash)
+                .into_iter()
+                .flatten()
+                .filter(|child| !self.in_chain(chain, child))
+                .copied()
+                .collect();
+            for child in stale {
+                self.remove_branch(&child);
+            }
+        }
+
+        self.pruned_height = limit;
+    }
+
+    // difficulty of the block building on `parent` in the branch of `parent`
+    // the same as the next difficulty of the chain, if the branch was active
+    fn next_difficulty(&self, parent: &[u8; 32], settings: &Settings) -> Target {
+        // the last headers of the branch, the newest first
+        // enough for both retarget algorithms
+        let span = settings.adjustment_interval as usize + 1;
+        let mut headers: Vec<&BlockHeader> = Vec::new();
+        let mut current = parent;
+
+        while let Some(node) = self.nodes.get(current) {
+            headers.push(&node.header);
+            if headers.len() == span || node.height == 0 {
+                break;
+            }
+            current = &node.header.previous;
+        }
+
+        let height = self.nodes[parent].height + 1;
+        let at = |i: usize| headers[height - 1 - i];
+
+        difficulty::next(
+            headers[0].target().unwrap_or(settings.start_difficulty),
+            height,
+            |i| at(i).timestamp,
+            |i| at(i).target().unwrap_or(settings.min_difficulty),
+            settings,
+        )
+    }
+
+    // last block of the heaviest branch building on the block
+    fn heaviest_descendant(&self, hash: &[u8; 32]) -> [u8; 32] {
+        let mut best = *hash;
+        let mut to_visit = vec![*hash];
+
+        while let Some(hash) = to_visit.pop() {
+            if self.nodes[&hash].work > self.nodes[&best].work {
+                best = hash;
+            }
+
+            to_visit.extend(self.children.get(&hash).into_iter().flatten());
+        }
+
+        best
+    }
+
+    // adds a block to the tree, if its branch has more work than the
+    // active chain, the chain and store are reorganized to the branch
+    //
+    // returns the reorganization if the active chain changed
+    pub fn insert(
+        &mut self,
+        block: Block,
+        chain: &mut Blockchain,
+        store: &mut TxStore,
+        settings: &Settings,
+    ) -> Result<Option<Reorg>, ValidationError> {
+        let hash = block.hash(None).map_err(|_| ValidationError::HashFailed)?;
+
+        // already known, but it might have been too far in the future
+        // to be connected the last time
+        if let Some(node) = self.nodes.get(&hash) {
+            if node.work <= self.tip_work() || self.in_chain(chain, &hash) {
+                return Ok(None);
+            }
+            let tip = self.heaviest_descendant(&hash);
+            return self.reorganize(tip, chain, store, settings).map(Some);
+        }
+
+        // the block has to build on a known block
+        // only the first block of a chain has no previous block
+        let height = if let Some(parent) = self.nodes.get(&block.header.previous) {
+            parent.height + 1
+        } else if block.header.previous == settings.network.genesis_previous() {
+            0
+        } else {
+            return Err(ValidationError::OrphanBlock);
+        };
+
+        // blocks up to the last checkpoint are final once the chain passed it
+        let checkpoint = utils::final_height(settings);
+        if height < checkpoint && chain.height() >= checkpoint {
+            return Err(ValidationError::BelowCheckpoint {
+                height,
+                checkpoint: checkpoint - 1,
+            });
+        }
+
+        // side branches can't replace too many blocks of the active chain
+        let min_height = chain.height().saturating_sub(settings.max_fork_depth);
+        if height < min_height {
+            return Err(ValidationError::ForkTooDeep { height, min_height });
+        }
+
+        // and only a limited number of branches can build on the same height
+        let count = self.heights.get(&height).copied().unwrap_or_default();
+        if count >= settings.max_blocks_per_height {
+            return Err(ValidationError::TooManyBlocks {
+                height,
+                limit: settings.max_blocks_per_height,
+            });
+        }
+
+        // the claimed work has to be done before storing the block
+        // the rest is validated once the block is connected
+        // the difficulty has to be the one of its branch,
+        // so side blocks cost as much work as the blocks of the chain
+        let difficulty = if height == 0 {
+            settings.start_difficulty
+        } else {
+            self.next_difficulty(&block.header.previous, settings)
+        };
+        if block.header.bits != difficulty.to_compact() {
+            return Err(ValidationError::WrongTarget);
+        }
+        if !difficulty::satisfies(&difficulty, &hash) {
+            return Err(ValidationError::InsufficientWork);
+        }
+        // and the transactions have to be the ones of the header,
+        // otherwise a changed copy could take the place of the real block
+        let tx_hashes = block.tx_hashes().map_err(|_| ValidationError::HashFailed)?;
+        if block.header.merkle_root != merkle::root(&tx_hashes) {
+            return Err(ValidationError::BadMerkleRoot);
+        }
+
+        self.insert_node(hash, block.header.clone(), Some(block), height);
+
+        if self.nodes[&hash].work <= self.tip_work() {
+            // side branch without enough work
+            return Ok(None);
+        }
+
+        self.reorganize(hash, chain, store, settings).map(Some)
+    }
+
+    // makes the branch ending in `tip` the active chain
+    // if a block is invalid, only the blocks before it are connected
+    // as long as they have more work than the old branch
+    fn reorganize(
+        &mut self,
+        tip: [u8; 32],
+        chain: &mut Blockchain,
+        store: &mut TxStore,
+        settings: &Settings,
+    ) -> Result<Reorg, ValidationError> {
+        // walk back until the branch meets the active chain
+        let mut branch: Vec<[u8; 32]> = Vec::new();
+        let mut current = tip;
+
+        while !self.in_chain(chain, &current) {
+            branch.push(current);
+
+            let node = &self.nodes[&current];
+            if node.height == 0 {
+                break;
+            }
+            current = node.header.previous;
+        }
+        branch.reverse();
+
+        // height at which the branch forks off
+        let fork_height = self.nodes[&branch[0]].height;
+        let min_height = chain.height().saturating_sub(settings.max_fork_depth);
+        if fork_height < min_height {
+            return Err(ValidationError::ForkTooDeep {
+                height: fork_height,
+                min_height,
+            });
+        }
+
+        let mut reorg = Reorg::default();
+
+        // disconnect the blocks of the old branch
+        while chain.height() > fork_height {
+            let block = chain.disconnect_tip(store).expect("UNREACHABLE!");
+            let hash = block.hash(None).map_err(|_| ValidationError::HashFailed)?;
+            self.set_block(&hash, Some(block.clone()));
+            reorg.disconnected.insert(0, block);
+        }
+
+        // connect the blocks of the new branch
+        // UNREACHABLE: blocks outside of the active chain are kept
+        for hash in branch {
+            let block = self.nodes[&hash].block.clone().expect("UNREACHABLE!");
+            let difficulty = chain.next_difficulty(settings);
+
+            if let Err(e) = chain.valid_next(&block, store, &difficulty, settings) {
+                // the branch is invalid, forget about it
+                // a block from the future can become valid later, so it's kept
+                if !matches!(e, ValidationError::FutureTimestamp { .. }) {
+                    self.remove_branch(&hash);
+                }
+
+                // the valid part of the branch stays if it has more work than the old branch
+                if let Some(last) = reorg.connected.last() {
+                    let last = last.hash(None).map_err(|_| ValidationError::HashFailed)?;
+                    if self.nodes[&last].work > self.tip_work() {
+                        self.tip = Some(last);
+                        self.prune(chain, settings);
+
+                        return Ok(reorg);
+                    }
+                }
+
+                // otherwise restore the old branch
+                for block in reorg.connected.iter().rev() {
+                    let hash = block.hash(None).map_err(|_| ValidationError::HashFailed)?;
+                    chain.disconnect_tip(store);
+                    self.set_block(&hash, Some(block.clone()));
+                }
+                for block in reorg.disconnected {
+                    let hash = block.hash(None).map_err(|_| ValidationError::HashFailed)?;
+                    chain.add(store, block);
+                    self.set_block(&hash, None);
+                }
+
+                return Err(e);
+            }
+
+            chain.add(store, block.clone());
+            self.set_block(&hash, None);
+            reorg.connected.push(block);
+        }
+
+        self.tip = Some(tip);
+        self.prune(chain, settings);
+
+        Ok(reorg)
+    }
+}
@@ -9,11 +9,14 @@ use serde::ser::SerializeStruct;
 use serde::Serialize;
 use std::error::Error;
 use std::fmt;
+use std::sync::OnceLock;
 
-use crate::blockchain::utils::hash_utxou;
-use crate::blockchain::Transaction;
+use crate::blockchain::{Owned, Transaction, TX_VERSION};
 use crate::hashes;
 use crate::hex::ToHex;
+use crate::script::{self, Instruction, Script};
+use crate::settings::Settings;
+use crate::sighash::{sighash, SigHashType};
 
 // wrapper functions for creating Secp and Rng
 
@@ -21,6 +24,13 @@ pub fn create_secp() -> Secp256k1<All> {
     Secp256k1::new()
 }
 
+// creating a context is expensive, so validation shares this one
+pub fn shared_secp() -> &'static Secp256k1<All> {
+    static SECP: OnceLock<Secp256k1<All>> = OnceLock::new();
+
+    SECP.get_or_init(create_secp)
+}
+
 pub fn create_rng() -> Result<OsRng, rand::Error> {
     rand::rngs::OsRng::new()
 }
@@ -30,6 +40,10 @@ pub fn create_rng() -> Result<OsRng, rand::Error> {
 pub struct Client {
     pub pb_key: PublicKey,
     pub sk_key: SecretKey,
+    // versions of the addresses the client pays to, not serialized
+    // since the same keys can be used on every network
+    pub address_version: u8,
+    pub script_address_version: u8,
 
     nonce: u128,
 }
@@ -153,7 +167,7 @@ impl<'de> Deserialize<'de> for Client {
             }
         }
 
-        const FIELDS: &'static [&'static str] = &["sk_key", "pb_key", "nonce"];
+        const FIELDS: &[&str] = &["sk_key", "pb_key", "nonce"];
         deserializer.deserialize_struct("Client", FIELDS, ClientVisitor)
     }
 }
@@ -163,6 +177,8 @@ impl Client {
         Ok(Self {
             sk_key: sk_key_from_bytes(&Vec::from_hex(&sk_key)?)?,
             pb_key: pb_key_from_bytes(&Vec::from_hex(&pb_key)?)?,
+            address_version: MAINNET_ADDRESS_VERSION,
+            script_address_version: MAINNET_SCRIPT_ADDRESS_VERSION,
             nonce,
         })
     }
@@ -176,6 +192,8 @@ impl Client {
         Ok(Self {
             sk_key,
             pb_key,
+            address_version: MAINNET_ADDRESS_VERSION,
+            script_address_version: MAINNET_SCRIPT_ADDRESS_VERSION,
             nonce: 0,
         })
     }
@@ -186,6 +204,8 @@ impl Client {
         Self {
             sk_key,
             pb_key: pk_key,
+            address_version: MAINNET_ADDRESS_VERSION,
+            script_address_version: MAINNET_SCRIPT_ADDRESS_VERSION,
             nonce: 0,
         }
     }
@@ -203,7 +223,7 @@ impl Client {
         let vout: Vec<(u128, String)> = output
             .iter()
             .map(|(amount, receiver)| {
-                let addr = pb_key_to_addr(&receiver.serialize());
+                let addr = pb_key_to_addr_with_version(&receiver.serialize(), self.address_version);
 
                 (*amount, addr)
             })
@@ -218,41 +238,40 @@ impl Client {
         input: Vec<([u8; 32], usize)>,
         output: Vec<(u128, String)>,
     ) -> Option<Transaction> {
-        // for each input create a solution
-        let vin: Vec<([u8; 32], usize, String)> = input
-            .iter()
-            .filter_map(|(hash, index)| {
-                let message = hash_utxou((hash, index)).ok()?.to_hex();
-                let solution = create_solution(secp, self, &msg_from_str(&message));
-
-                Some((*hash, *index, solution))
-            })
-            .collect();
-
-        // if they aren't equally big that means
-        // that the hash_utxou failed somewhere meaning
-        // that one of the inputs is invalid
-        //  -> invalid inputs -> can't create transaction
-        if vin.len() != input.len() {
-            return None;
-        }
-
         let vout: Vec<(u128, String)> = output
             .iter()
             .map(|(amount, receiver)| {
-                let lock = create_lock_with_addr(receiver);
+                // script hash addresses have their own lock
+                let lock = if is_valid_addr(receiver, self.script_address_version) {
+                    create_script_lock(receiver)
+                } else {
+                    create_lock_with_addr(receiver)
+                };
 
                 (*amount, lock)
             })
             .collect();
 
-        // create transaction
-        let tx = Transaction {
+        // create transaction without solutions
+        // since the signatures commit to the whole transaction
+        let mut tx = Transaction {
+            version: TX_VERSION,
             nonce: self.nonce,
-            vin,
+            vin: input
+                .iter()
+                .map(|(hash, index)| (*hash, *index, String::new()))
+                .collect(),
 
             vout,
         };
+
+        // for each input create a solution
+        for index in 0..tx.vin.len() {
+            // if the sighash can't be created the input is invalid
+            //  -> invalid inputs -> can't create transaction
+            tx.vin[index].2 = sign_input(secp, self, &tx, index, SigHashType::ALL).ok()?;
+        }
+
         // update nonce
         self.nonce += 1;
         // return transaction
@@ -281,14 +300,49 @@ pub fn sk_key_from_bytes(bytes: &[u8]) -> Result<SecretKey, Box<dyn Error>> {
     Ok(SecretKey::from_slice(bytes)?)
 }
 
+// version byte of mainnet addresses
+pub const MAINNET_ADDRESS_VERSION: u8 = 0x00;
+
 pub fn pb_key_to_addr(pk: &[u8]) -> String {
-    let mut address = hashes::ripemd160(&hashes::sha256(pk).to_vec());
-    // prepend 0x00
-    address.insert(0, 0u8);
+    pb_key_to_addr_with_version(pk, MAINNET_ADDRESS_VERSION)
+}
+
+pub fn pb_key_to_addr_with_version(pk: &[u8], version: u8) -> String {
+    // encode in base58
+    bs58::encode(&pb_key_to_addr_bytes(pk, version)).into_string()
+}
+
+// address before the base58 encoding
+pub fn pb_key_to_addr_bytes(pk: &[u8], version: u8) -> Vec<u8> {
+    hash_to_addr_bytes(pk, version)
+}
+
+// version byte of mainnet script hash addresses
+pub const MAINNET_SCRIPT_ADDRESS_VERSION: u8 = 0x05;
+
+// address of a pay to script hash lock, the script is compiled
+pub fn script_to_addr(script: &[u8]) -> String {
+    script_to_addr_with_version(script, MAINNET_SCRIPT_ADDRESS_VERSION)
+}
+
+pub fn script_to_addr_with_version(script: &[u8], version: u8) -> String {
+    bs58::encode(&script_to_addr_bytes(script, version)).into_string()
+}
+
+// script hash addresses only differ from the ones of public keys
+// by their version, so they can't be confused
+pub fn script_to_addr_bytes(script: &[u8], version: u8) -> Vec<u8> {
+    hash_to_addr_bytes(script, version)
+}
+
+fn hash_to_addr_bytes(data: &[u8], version: u8) -> Vec<u8> {
+    let mut address = hashes::hash160(data);
+    // prepend the version of the network
+    address.insert(0, version);
     // append checksum
     address.append(&mut hashes::checksum(&address));
-    // encode in base58
-    bs58::encode(&address).into_string()
+
+    address
 }
 
 pub fn valid_signature(
@@ -303,7 +357,7 @@ pub fn valid_signature(
 // NOTE: this is just my standart, the script technically allows for more complex locks
 pub fn create_lock(pb_key: &PublicKey) -> String {
     // my address
-    let my_addr = pb_key_to_addr(&pb_key.serialize().to_vec());
+    let my_addr = pb_key_to_addr(&pb_key.serialize());
     // standart lock format
     format!("verify_sig to_addr {} eq", my_addr)
 }
@@ -313,26 +367,210 @@ pub fn create_lock_with_addr(address: &String) -> String {
     format!("verify_sig to_addr {} eq", address)
 }
 
+// pay to script hash lock, the solution ends with the script which is run
+// after its address is checked
+pub fn create_script_lock(address: &str) -> String {
+    format!("to_script_addr {} eq", address)
+}
+
+// address of a pay to script hash lock
+pub fn lock_to_script_addr(lock: &str) -> Option<&str> {
+    match lock.split(' ').collect::<Vec<&str>>()[..] {
+        ["to_script_addr", address, "eq"] => Some(address),
+        _ => None,
+    }
+}
+
+// solution of a pay to script hash lock, the solution of the script
+// followed by the script itself
+pub fn create_script_solution(solution: &str, script: &Script) -> String {
+    let script = Instruction::Push(script.as_bytes()).to_string();
+
+    if solution.is_empty() {
+        script
+    } else {
+        format!("{} {}", solution, script)
+    }
+}
+
+// standart lock which can't be spent before the block height or the timestamp,
+// numbers below `script::LOCK_TIME_THRESHOLD` are heights
+pub fn create_lock_until(address: &str, lock_time: u64) -> String {
+    format!(
+        "{} check_lock_time verify_sig to_addr {} eq",
+        number_to_token(lock_time),
+        address
+    )
+}
+
+// standart lock which can't be spent before the output is `blocks` deep in the chain
+pub fn create_lock_after(address: &str, blocks: u64) -> String {
+    format!(
+        "{} check_age verify_sig to_addr {} eq",
+        number_to_token(blocks),
+        address
+    )
+}
+
+fn number_to_token(number: u64) -> String {
+    Instruction::Push(&script::encode_number(number)).to_string()
+}
+
+// address of a lock in the standart format
+pub fn lock_to_addr(lock: &str) -> Option<&str> {
+    match lock.split(' ').collect::<Vec<&str>>()[..] {
+        ["verify_sig", "to_addr", address, "eq"] => Some(address),
+        _ => None,
+    }
+}
+
+// m-of-n lock, the solution needs signatures of `required` of the keys
+// in the order of the keys, `None` if the numbers can't be satisfied
+pub fn create_multisig_lock(required: usize, pb_keys: &[PublicKey]) -> Option<String> {
+    if required == 0 || required > pb_keys.len() || pb_keys.len() > script::MAX_MULTISIG_KEYS {
+        return None;
+    }
+
+    let pb_keys_hex = pb_keys
+        .iter()
+        .map(|pb_key| pb_key.serialize().to_hex())
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    Some(format!(
+        "{:02x} {} {:02x} check_multisig",
+        required,
+        pb_keys_hex,
+        pb_keys.len()
+    ))
+}
+
+// number of required signatures and the keys of an m-of-n lock
+pub fn multisig_lock_keys(lock: &str) -> Option<(usize, Vec<PublicKey>)> {
+    let tokens = lock.split(' ').collect::<Vec<&str>>();
+
+    match tokens[..] {
+        [required, ref pb_keys @ .., keys_len, "check_multisig"] => {
+            let required = u8::from_str_radix(required, 16).ok()? as usize;
+            let keys_len = u8::from_str_radix(keys_len, 16).ok()? as usize;
+            let pb_keys = pb_keys
+                .iter()
+                .map(|pb_key| pb_key_from_bytes(&Vec::from_hex(pb_key).ok()?).ok())
+                .collect::<Option<Vec<PublicKey>>>()?;
+
+            (pb_keys.len() == keys_len && required > 0 && required <= keys_len)
+                .then_some((required, pb_keys))
+        }
+        _ => None,
+    }
+}
+
+// checks the encoding, checksum and version of an address
+pub fn is_valid_addr(address: &str, version: u8) -> bool {
+    addr_to_bytes(address).is_some_and(|bytes| bytes[0] == version)
+}
+
+// decodes an address with a valid checksum, the version isn't checked
+pub fn addr_to_bytes(address: &str) -> Option<Vec<u8>> {
+    let bytes = bs58::decode(address).into_vec().ok()?;
+
+    is_addr_bytes(&bytes).then_some(bytes)
+}
+
+// version (1 byte), hash (20 bytes) and a valid checksum (4 bytes)
+pub fn is_addr_bytes(bytes: &[u8]) -> bool {
+    bytes.len() == 25 && hashes::checksum(&bytes[..21]) == bytes[21..]
+}
+
 // NOTE: this is just my standart, the script technically allows for more complex locks
-pub fn create_solution(secp: &Secp256k1<All>, client: &Client, message: &Message) -> String {
+pub fn create_solution(
+    secp: &Secp256k1<All>,
+    client: &Client,
+    message: &Message,
+    sighash_type: SigHashType,
+) -> String {
     // save public key as hex
     let pb_key_hex = client.pb_key.serialize().to_hex();
-    // create signature
-    let sig = client.sign(secp, message);
-    // save signature as hex
-    let sig_hex = sig.serialize_compact().to_hex();
+    let sig_hex = signature_hex(secp, client, message, sighash_type);
 
     // standart solution format for the lock
     format!("{} {}", pb_key_hex, sig_hex)
 }
 
+// signature with the sighash type appended as hex
+fn signature_hex(
+    secp: &Secp256k1<All>,
+    client: &Client,
+    message: &Message,
+    sighash_type: SigHashType,
+) -> String {
+    let sig = client.sign(secp, message);
+    let mut sig_bytes = sig.serialize_compact().to_vec();
+    sig_bytes.push(sighash_type.to_byte());
+
+    sig_bytes.to_hex()
+}
+
+// creates the solution for the input at `index` of the transaction
+pub fn sign_input(
+    secp: &Secp256k1<All>,
+    client: &Client,
+    tx: &Transaction,
+    index: usize,
+    sighash_type: SigHashType,
+) -> Result<String, Box<dyn Error>> {
+    let message = msg_from_bytes(&sighash(tx, index, sighash_type)?)?;
+
+    Ok(create_solution(secp, client, &message, sighash_type))
+}
+
+// signature of one of the keys of an m-of-n lock for the input at `index`,
+// the partial solutions are combined with `combine_multisig_solution`
+pub fn sign_multisig_input(
+    secp: &Secp256k1<All>,
+    client: &Client,
+    tx: &Transaction,
+    index: usize,
+    sighash_type: SigHashType,
+) -> Result<(PublicKey, String), Box<dyn Error>> {
+    let message = msg_from_bytes(&sighash(tx, index, sighash_type)?)?;
+
+    Ok((
+        client.pb_key,
+        signature_hex(secp, client, &message, sighash_type),
+    ))
+}
+
+// solution of an m-of-n lock from the partial solutions of the signers,
+// `None` if there are signatures of less than m keys of the lock
+pub fn combine_multisig_solution(lock: &str, partials: &[(PublicKey, String)]) -> Option<String> {
+    let (required, pb_keys) = multisig_lock_keys(lock)?;
+
+    // the signatures have to be in the order of the keys
+    let signatures = pb_keys
+        .iter()
+        .filter_map(|pb_key| {
+            partials
+                .iter()
+                .find(|(signer, _)| signer == pb_key)
+                .map(|(_, sig_hex)| sig_hex.as_str())
+        })
+        .take(required)
+        .collect::<Vec<&str>>();
+
+    (signatures.len() == required).then(|| signatures.join(" "))
+}
+
 pub fn generate_transaction(
-    owned: (u128, Vec<(String, usize, u128)>),
+    owned: Owned,
     sk_key: String,
     addr: String,
     amount: u128,
+    settings: &Settings,
 ) -> Option<Transaction> {
     let mut client = Client::from_sk_key(sk_key).ok()?;
+    client.address_version = settings.address_version;
+    client.script_address_version = settings.script_address_version;
 
     if amount > owned.0 {
         return None;
@@ -359,7 +597,10 @@ pub fn generate_transaction(
     output.push((amount, addr));
     // send change to self
     if change > 0 {
-        output.push((change, pb_key_to_addr(&client.pb_key.serialize())));
+        output.push((
+            change,
+            pb_key_to_addr_with_version(&client.pb_key.serialize(), client.address_version),
+        ));
     }
 
     let secp = create_secp();
@@ -0,0 +1,88 @@
+use crate::settings::Settings;
+
+// number of halvings before the block at `height`
+// an interval of 0 never halves
+fn halvings(height: usize, settings: &Settings) -> u32 {
+    let halvings = height.checked_div(settings.halvings_interval).unwrap_or(0);
+    u32::try_from(halvings).unwrap_or(u32::MAX)
+}
+
+// blocks with the same reward, all blocks if the reward never halves
+fn era_length(settings: &Settings) -> usize {
+    match settings.halvings_interval {
+        0 => usize::MAX,
+        interval => interval,
+    }
+}
+
+// reward halved `halvings` times, zero once all bits are shifted out
+fn halved(halvings: u32, settings: &Settings) -> u128 {
+    settings
+        .start_mining_reward
+        .checked_shr(halvings)
+        .unwrap_or(0)
+}
+
+// the reward never drops below the tail emission
+fn with_tail(reward: u128, settings: &Settings) -> u128 {
+    reward.max(settings.tail_emission.unwrap_or(0))
+}
+
+// maximum amount the coinbase of the block at `height` can claim without fees
+// the genesis block doesn't create any coins
+pub fn block_reward(height: usize, settings: &Settings) -> u128 {
+    if height == 0 {
+        return 0;
+    }
+
+    with_tail(halved(halvings(height, settings), settings), settings)
+}
+
+// maximum amount of coins created by the blocks below `height`
+pub fn supply(height: usize, settings: &Settings) -> u128 {
+    let mut supply = 0_u128;
+    // the genesis block doesn't count
+    let mut start = 1;
+
+    // one era of blocks with the same reward at a time
+    while start < height {
+        let era = halvings(start, settings);
+        let end = (era as usize)
+            .saturating_add(1)
+            .saturating_mul(era_length(settings))
+            .min(height);
+        let reward = block_reward(start, settings);
+
+        // nothing is created anymore
+        if reward == 0 {
+            break;
+        }
+
+        // with a tail emission every following block has the same reward
+        if reward == with_tail(0, settings) {
+            let blocks = (height - start) as u128;
+            return supply.saturating_add(blocks.saturating_mul(reward));
+        }
+
+        let blocks = (end - start) as u128;
+        supply = supply.saturating_add(blocks.saturating_mul(reward));
+        start = end;
+    }
+
+    supply
+}
+
+// amount of coins there will ever be, None if the emission never stops
+pub fn max_supply(settings: &Settings) -> Option<u128> {
+    if settings.tail_emission.unwrap_or(0) > 0 {
+        return None;
+    }
+    // without halvings the start reward is paid forever
+    if settings.halvings_interval == 0 && settings.start_mining_reward > 0 {
+        return None;
+    }
+
+    // the reward is zero after 128 halvings at the latest
+    let last = era_length(settings).saturating_mul(129);
+    Some(supply(last, settings))
+}
@@ -0,0 +1,222 @@
+// canonical binary encoding of the consensus data
+//
+// hashes and sizes are defined on this encoding instead of a serialization
+// library, so other clients can reproduce them (see docs/encoding.md):
+//  - u8, u32, u64 and u128 as little-endian bytes of their size
+//  - usize (indices and lengths) as u64
+//  - bool as one byte, 0 or 1
+//  - fixed size byte arrays as their bytes
+//  - strings as the u64 byte length followed by the utf-8 bytes
+//  - sequences as the u64 number of items followed by the items
+//  - tuples and structs as their fields in order
+
+use std::{error::Error, fmt};
+
+// reason why bytes couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    // input ended in the middle of a value
+    UnexpectedEnd,
+    // string isn't valid utf-8
+    InvalidUtf8,
+    // bool other than 0 or 1
+    InvalidBool(u8),
+    // number doesn't fit into usize
+    Overflow,
+    // input has bytes left after the value
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidUtf8 => write!(f, "string isn't valid utf-8"),
+            DecodeError::InvalidBool(byte) => write!(f, "invalid bool {}", byte),
+            DecodeError::Overflow => write!(f, "number doesn't fit into usize"),
+            DecodeError::TrailingBytes(len) => write!(f, "{} bytes left after the value", len),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+pub trait Encode {
+    // appends the encoding to `out`
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    // reads the value from the start of `input` and advances it
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+pub fn encode<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.encode(&mut out);
+    out
+}
+
+// all bytes have to belong to the value
+pub fn decode<T: Decode>(mut bytes: &[u8]) -> Result<T, DecodeError> {
+    let value = T::decode(&mut bytes)?;
+
+    if !bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes(bytes.len()));
+    }
+
+    Ok(value)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+
+    Ok(bytes)
+}
+
+macro_rules! impl_int {
+    ($($int:ty),*) => {$(
+        impl Encode for $int {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend(self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $int {
+            fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                let bytes = take(input, std::mem::size_of::<$int>())?;
+                Ok(<$int>::from_le_bytes(bytes.try_into().expect("UNREACHABLE!")))
+            }
+        }
+    )*};
+}
+
+impl_int!(u8, u32, u64, u128);
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        usize::try_from(u64::decode(input)?).map_err(|_| DecodeError::Overflow)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(DecodeError::InvalidBool(byte)),
+        }
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self);
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(input, N)?.try_into().expect("UNREACHABLE!"))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode(input)?;
+        let bytes = take(input, len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode(input)?;
+        // every item has at least one byte, so the length can't be trusted further
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+        self.2.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?, C::decode(input)?))
+    }
+}
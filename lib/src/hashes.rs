@@ -11,12 +11,17 @@ pub fn ripemd160(data: &[u8]) -> Vec<u8> {
     ripemd160::Hash::hash(data).to_vec()
 }
 
+// ripemd160 of the sha256, the hash in addresses
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    ripemd160(&sha256(data))
+}
+
 // Will return the first 4 bytes of the double hash in hex format
 pub fn checksum(data: &[u8]) -> Vec<u8> {
     // hash 1
     let hash = sha256(data);
     // hash 2
-    let hash = sha256(&hash.to_vec());
+    let hash = sha256(&hash);
 
     hash[0..4].to_vec()
 }
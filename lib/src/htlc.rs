@@ -0,0 +1,96 @@
+// hashed timelock contracts
+//
+// the receiver can claim the coins with the preimage of a hash,
+// after the lock time the sender can take them back instead:
+//
+//   if
+//       size 20 eq sha256 <hash> eq verify_sig to_addr <receiver> eq
+//   else
+//       <lock time> check_lock_time verify_sig to_addr <sender> eq
+//   endif
+//
+// for an atomic swap both parties lock their coins with the same hash,
+// once the secret is revealed by a claim on one chain it can be used
+// to claim on the other chain
+//
+// the secret has a fixed size, otherwise a secret which is too big for
+// the script limits of one chain could only be used to claim on the other
+
+use std::error::Error;
+
+use secp256k1::{All, Secp256k1};
+
+use crate::blockchain::Transaction;
+use crate::ecdsa::{self, Client};
+use crate::hashes;
+use crate::hex::ToHex;
+use crate::script::{self, Instruction, Script};
+use crate::sighash::SigHashType;
+
+// size of the secret in bytes
+pub const SECRET_LEN: usize = 32;
+
+// hash the secret is locked with
+pub fn hash_secret(secret: &[u8]) -> [u8; 32] {
+    hashes::sha256(secret)
+}
+
+// lock time is a height or a timestamp like in `check_lock_time`
+pub fn create_lock(hash: &[u8; 32], receiver: &str, sender: &str, lock_time: u64) -> String {
+    format!(
+        concat!(
+            "if size {} eq sha256 {} eq verify_sig to_addr {} eq ",
+            "else {} check_lock_time verify_sig to_addr {} eq endif"
+        ),
+        Instruction::Push(&script::encode_number(SECRET_LEN as u64)),
+        hash.to_hex(),
+        receiver,
+        Instruction::Push(&script::encode_number(lock_time)),
+        sender
+    )
+}
+
+// solution of the receiver for the input at `index`
+pub fn create_claim_solution(
+    secp: &Secp256k1<All>,
+    client: &Client,
+    tx: &Transaction,
+    index: usize,
+    secret: &[u8],
+    sighash_type: SigHashType,
+) -> Result<String, Box<dyn Error>> {
+    if secret.len() != SECRET_LEN {
+        return Err(format!("the secret has to be {} bytes long", SECRET_LEN).into());
+    }
+    let solution = ecdsa::sign_input(secp, client, tx, index, sighash_type)?;
+
+    Ok(format!("{} {} 01", solution, Instruction::Push(secret)))
+}
+
+// solution of the sender for the input at `index`, only valid after the lock time
+pub fn create_refund_solution(
+    secp: &Secp256k1<All>,
+    client: &Client,
+    tx: &Transaction,
+    index: usize,
+    sighash_type: SigHashType,
+) -> Result<String, Box<dyn Error>> {
+    let solution = ecdsa::sign_input(secp, client, tx, index, sighash_type)?;
+
+    Ok(format!("{} 0", solution))
+}
+
+// secret revealed by a claim solution
+pub fn secret_from_solution(solution: &str) -> Option<Vec<u8>> {
+    let solution = Script::assemble(solution).ok()?;
+    let instructions = solution
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+        .ok()?;
+
+    match instructions[..] {
+        // public key, signature, secret and the condition
+        [_, _, Instruction::Push(secret), Instruction::Push([1])] => Some(secret.to_vec()),
+        _ => None,
+    }
+}
@@ -5,8 +5,17 @@ pub use secp256k1::{All, PublicKey, Secp256k1, SecretKey};
 
 // module exports
 pub mod blockchain;
+pub mod blocktree;
 pub mod ecdsa;
+pub mod emission;
+pub mod encoding;
 pub mod hashes;
+pub mod htlc;
+pub mod mempool;
+pub mod merkle;
 pub mod script;
 pub mod settings;
+pub mod sighash;
+pub mod spv;
+pub mod target;
 pub mod utils;
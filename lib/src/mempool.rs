@@ -0,0 +1,331 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+use crate::{
+    blockchain::{utils, SpentOutput, Transaction, TxStore, ValidationError},
+    settings::Settings,
+};
+
+// reason why a transaction isn't accepted into the mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    // the transaction is already in the mempool
+    AlreadyKnown,
+    // the transaction isn't valid on top of the chain and the mempool
+    Invalid(ValidationError),
+    // the mempool is full and the fee rate of the transaction is too low
+    FeeRateTooLow,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::AlreadyKnown => write!(f, "transaction is already known"),
+            MempoolError::Invalid(error) => write!(f, "{}", error),
+            MempoolError::FeeRateTooLow => {
+                write!(f, "mempool is full and the fee rate is too low")
+            }
+        }
+    }
+}
+
+impl Error for MempoolError {}
+
+// verified transaction waiting to be mined
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub fee: u128,
+    // serialized size in bytes
+    pub size: usize,
+    // time the transaction arrived
+    pub time: u64,
+    // mempool transactions whose outputs are spent by this transaction
+    pub parents: Vec<[u8; 32]>,
+
+    // outputs spent from the store, needed to remove the transaction again
+    spent: Vec<SpentOutput>,
+    // order of arrival, parents always arrive before their children
+    sequence: u64,
+}
+
+impl MempoolEntry {
+    // compares the fee per byte without rounding
+    pub fn cmp_fee_rate(&self, other: &Self) -> Ordering {
+        cmp_ratio(self.fee, self.size as u128, other.fee, other.size as u128)
+    }
+}
+
+// compares a / b with c / d without overflowing
+// by comparing the integer parts and then the inverted remainders
+fn cmp_ratio(a: u128, b: u128, c: u128, d: u128) -> Ordering {
+    // an empty transaction can't exist, treat it as having the highest rate
+    match (b, d) {
+        (0, 0) => return Ordering::Equal,
+        (0, _) => return Ordering::Greater,
+        (_, 0) => return Ordering::Less,
+        _ => {}
+    }
+
+    match (a / b).cmp(&(c / d)) {
+        Ordering::Equal => {}
+        ordering => return ordering,
+    }
+
+    match (a % b, c % d) {
+        (0, 0) => Ordering::Equal,
+        (0, _) => Ordering::Less,
+        (_, 0) => Ordering::Greater,
+        // r1 / b < r2 / d <=> d / r2 < b / r1
+        (r1, r2) => cmp_ratio(d, r2, b, r1),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    entries: HashMap<[u8; 32], MempoolEntry>,
+    // store of the chain with the mempool transactions applied
+    store: TxStore,
+    // median time past of the chain, for timelocks
+    median_time: Option<u64>,
+    // sum of the sizes of all entries
+    size: usize,
+
+    settings: Settings,
+
+    sequence: u64,
+}
+
+impl Mempool {
+    pub fn new(store: TxStore, median_time: Option<u64>, settings: &Settings) -> Self {
+        Self {
+            entries: HashMap::new(),
+            store,
+            median_time,
+            size: 0,
+            settings: *settings,
+            sequence: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&MempoolEntry> {
+        self.entries.get(hash)
+    }
+
+    // store with all outputs of the mempool transactions
+    // and without the outputs they spend
+    pub fn store(&self) -> &TxStore {
+        &self.store
+    }
+
+    pub fn median_time(&self) -> Option<u64> {
+        self.median_time
+    }
+
+    // transactions in the order they arrived
+    pub fn transactions(&self) -> Vec<Transaction> {
+        let mut entries: Vec<&MempoolEntry> = self.entries.values().collect();
+        entries.sort_by_key(|entry| entry.sequence);
+
+        entries.into_iter().map(|entry| entry.tx.clone()).collect()
+    }
+
+    // verifies the transaction and adds it to the mempool
+    // if the mempool is full the transactions with the lowest fee rate are evicted
+    pub fn add(&mut self, tx: Transaction, time: u64) -> Result<[u8; 32], MempoolError> {
+        let hash = tx
+            .hash()
+            .map_err(|_| MempoolError::Invalid(ValidationError::HashFailed))?;
+
+        if self.contains(&hash) {
+            return Err(MempoolError::AlreadyKnown);
+        }
+
+        utils::check_tx_limits(&tx, &self.settings).map_err(MempoolError::Invalid)?;
+        utils::is_valid_tx(&tx, &self.store, self.median_time, &self.settings)
+            .map_err(MempoolError::Invalid)?;
+
+        // UNREACHABLE: all inputs exist since the transaction is valid
+        let fee = tx.vin_total(&self.store).expect("UNREACHABLE!") - tx.vout_total();
+        let size = tx
+            .size()
+            .map_err(|_| MempoolError::Invalid(ValidationError::HashFailed))?;
+
+        let mut parents: Vec<[u8; 32]> = Vec::new();
+        for (parent, _, _) in &tx.vin {
+            if self.contains(parent) && !parents.contains(parent) {
+                parents.push(*parent);
+            }
+        }
+
+        let spent = utils::add_tx_to_store(&tx, &mut self.store);
+
+        self.sequence += 1;
+        self.size += size;
+        self.entries.insert(
+            hash,
+            MempoolEntry {
+                tx,
+                fee,
+                size,
+                time,
+                parents,
+                spent,
+                sequence: self.sequence,
+            },
+        );
+
+        // evict the lowest fee rate, the newest first on equal fee rates
+        while self.size > self.settings.mempool_max_size {
+            let lowest = *self
+                .entries
+                .iter()
+                .min_by(|(_, a), (_, b)| a.cmp_fee_rate(b).then(b.sequence.cmp(&a.sequence)))
+                .expect("UNREACHABLE!")
+                .0;
+
+            self.remove(&lowest);
+        }
+
+        if !self.contains(&hash) {
+            return Err(MempoolError::FeeRateTooLow);
+        }
+
+        Ok(hash)
+    }
+
+    // removes the transaction and all transactions depending on it
+    // returns the removed transactions
+    pub fn remove(&mut self, hash: &[u8; 32]) -> Vec<Transaction> {
+        // collect the transaction and its descendants
+        let mut to_remove: Vec<[u8; 32]> = Vec::new();
+        let mut to_visit = vec![*hash];
+
+        while let Some(hash) = to_visit.pop() {
+            if !self.contains(&hash) || to_remove.contains(&hash) {
+                continue;
+            }
+            to_remove.push(hash);
+
+            to_visit.extend(
+                self.entries
+                    .iter()
+                    .filter(|(_, entry)| entry.parents.contains(&hash))
+                    .map(|(child, _)| *child),
+            );
+        }
+
+        // children have to be reverted before their parents
+        to_remove.sort_by_key(|hash| std::cmp::Reverse(self.entries[hash].sequence));
+
+        let mut removed = Vec::new();
+        for hash in to_remove {
+            let entry = self.entries.remove(&hash).expect("UNREACHABLE!");
+
+            utils::remove_tx_from_store(&entry.tx, &entry.spent, &mut self.store);
+            self.size -= entry.size;
+
+            removed.push(entry.tx);
+        }
+
+        removed
+    }
+
+    // removes the transactions which are older than the expiry
+    // returns the number of removed transactions
+    pub fn expire(&mut self, now: u64) -> usize {
+        let expired: Vec<[u8; 32]> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.time + self.settings.mempool_expiry < now)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        expired.iter().map(|hash| self.remove(hash).len()).sum()
+    }
+
+    // verifies all transactions again on top of a new store
+    // e.g. after a block was added, transactions which were mined
+    // or conflict with the new chain are dropped
+    //
+    // `orphaned` are the transactions of disconnected blocks in block order,
+    // they're added first with `time`, since the entries can spend their outputs
+    pub fn reset(
+        &mut self,
+        store: TxStore,
+        median_time: Option<u64>,
+        orphaned: Vec<Transaction>,
+        time: u64,
+    ) {
+        let mut entries: Vec<MempoolEntry> = self.entries.drain().map(|(_, entry)| entry).collect();
+        entries.sort_by_key(|entry| entry.sequence);
+
+        self.store = store;
+        self.median_time = median_time;
+        self.size = 0;
+
+        for tx in orphaned {
+            // invalid transactions are dropped
+            let _ = self.add(tx, time);
+        }
+        for entry in entries {
+            // invalid transactions are dropped
+            let _ = self.add(entry.tx, entry.time);
+        }
+    }
+
+    // transactions for the next block, highest fee rate first
+    // a transaction is only selected after all its parents
+    // at most `max_txs` transactions with `max_size` bytes in total are selected
+    pub fn template(&self, max_txs: usize, max_size: usize) -> Vec<Transaction> {
+        let mut remaining: Vec<(&[u8; 32], &MempoolEntry)> = self.entries.iter().collect();
+        let mut included: HashSet<[u8; 32]> = HashSet::new();
+        let mut transactions = Vec::new();
+        let mut size = 0;
+
+        while transactions.len() < max_txs {
+            let best = remaining
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, entry))| {
+                    size + entry.size <= max_size
+                        && entry.parents.iter().all(|parent| included.contains(parent))
+                })
+                .max_by(|(_, (_, a)), (_, (_, b))| {
+                    a.cmp_fee_rate(b).then(b.sequence.cmp(&a.sequence))
+                })
+                .map(|(index, _)| index);
+
+            let (hash, entry) = match best {
+                Some(index) => remaining.swap_remove(index),
+                None => break,
+            };
+
+            included.insert(*hash);
+            size += entry.size;
+            transactions.push(entry.tx.clone());
+        }
+
+        transactions
+    }
+}
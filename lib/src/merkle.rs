@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hashes;
+
+// proof that a hash is part of a merkle tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    // position of the hash in the leaves
+    pub index: usize,
+    // number of leaves, the shape of the tree depends on it
+    pub leaves: usize,
+    // sibling hashes from the bottom to the top of the tree
+    pub siblings: Vec<[u8; 32]>,
+}
+
+// leaves and inner nodes are hashed with different prefixes,
+// so an inner node can't be passed off as a leaf or the other way around
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+fn hash_leaf(hash: &[u8; 32]) -> [u8; 32] {
+    let mut bytes = vec![LEAF_TAG];
+    bytes.extend(hash);
+
+    hashes::sha256(&bytes)
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut bytes = vec![NODE_TAG];
+    bytes.extend(left);
+    bytes.extend(right);
+
+    hashes::sha256(&bytes)
+}
+
+// computes the next level of the tree
+// if the level has an odd number of hashes, the last one is moved up as it is
+// instead of being paired with itself, so no two lists of hashes share a root
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_pair(left, right),
+            _ => pair[0],
+        })
+        .collect()
+}
+
+// merkle root of the hashes, the root of no hashes is all zeros
+pub fn root(hashes: &[[u8; 32]]) -> [u8; 32] {
+    if hashes.is_empty() {
+        return [0u8; 32];
+    }
+
+    let mut level: Vec<[u8; 32]> = hashes.iter().map(hash_leaf).collect();
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level[0]
+}
+
+// creates the proof for the hash at `index`
+pub fn proof(hashes: &[[u8; 32]], index: usize) -> Option<MerkleProof> {
+    if index >= hashes.len() {
+        return None;
+    }
+
+    let mut siblings: Vec<[u8; 32]> = Vec::new();
+    let mut level: Vec<[u8; 32]> = hashes.iter().map(hash_leaf).collect();
+    let mut i = index;
+
+    while level.len() > 1 {
+        // the last odd hash has no sibling
+        if let Some(sibling) = level.get(i ^ 1) {
+            siblings.push(*sibling);
+        }
+
+        level = next_level(&level);
+        i /= 2;
+    }
+
+    Some(MerkleProof {
+        index,
+        leaves: hashes.len(),
+        siblings,
+    })
+}
+
+// checks if the proof leads from the hash to the root
+pub fn verify(hash: &[u8; 32], proof: &MerkleProof, root: &[u8; 32]) -> bool {
+    if proof.index >= proof.leaves {
+        return false;
+    }
+
+    let mut current = hash_leaf(hash);
+    let mut siblings = proof.siblings.iter();
+    let mut i = proof.index;
+    let mut len = proof.leaves;
+
+    while len > 1 {
+        if i ^ 1 < len {
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return false,
+            };
+            current = if i.is_multiple_of(2) {
+                hash_pair(&current, sibling)
+            } else {
+                hash_pair(sibling, &current)
+            };
+        }
+
+        i /= 2;
+        len = len.div_ceil(2);
+    }
+
+    // every sibling has to be used by the proof
+    siblings.next().is_none() && &current == root
+}
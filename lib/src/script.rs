@@ -1,70 +1,614 @@
+use std::error::Error;
+use std::fmt;
+
 use secp256k1::{All, Secp256k1};
 
-use crate::ecdsa;
-use crate::hex::FromHex;
+use crate::blockchain::Transaction;
+use crate::hex::{FromHex, ToHex};
+use crate::settings::Settings;
+use crate::{ecdsa, hashes, sighash};
+
+// scripts are a sequence of instructions, an instruction is either
+// an opcode or a push of data onto the stack:
+//  - 0x00:        push of no bytes
+//  - 0x01..=0x4b: push of the next 1 to 75 bytes
+//  - 0x4c:        push of the next n bytes, n is the next byte
+//  - 0x4d:        push of the next n bytes, n are the next 2 bytes (little-endian)
+//  - everything else is an opcode
+const PUSH_EMPTY: u8 = 0x00;
+const MAX_DIRECT_PUSH: u8 = 0x4b;
+const PUSH_DATA_1: u8 = 0x4c;
+const PUSH_DATA_2: u8 = 0x4d;
+
+// most public keys a `check_multisig` can check
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+// lock times below are block heights, the others unix timestamps
+pub const LOCK_TIME_THRESHOLD: u64 = 500_000_000;
+
+// numbers are pushed as little-endian bytes without trailing zeros,
+// so 0 is the empty push
+const MAX_NUMBER_LEN: usize = 8;
+
+// the text syntax of an empty push, other data is written in hex
+// or as an address if it is one
+const EMPTY_TOKEN: &str = "0";
+
+macro_rules! opcodes {
+    ($($(#[$doc:meta])* $name:ident = $byte:literal, $text:literal;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Opcode {
+            $($(#[$doc])* $name,)*
+        }
+
+        impl Opcode {
+            pub fn to_byte(self) -> u8 {
+                match self {
+                    $(Opcode::$name => $byte,)*
+                }
+            }
+
+            pub fn from_byte(byte: u8) -> Option<Self> {
+                match byte {
+                    $($byte => Some(Opcode::$name),)*
+                    _ => None,
+                }
+            }
+
+            // name in the text syntax
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Opcode::$name => $text,)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name.to_lowercase().as_str() {
+                    $($text => Some(Opcode::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
+    // pops two items, fails if they aren't equal
+    Eq = 0x88, "eq";
+    // pops a public key and pushes its address
+    ToAddr = 0xc0, "to_addr";
+    // pops a script and pushes its script hash address
+    ToScriptAddr = 0xc1, "to_script_addr";
+    // pops a signature and a public key, fails if the signature isn't valid
+    // for the sighash of the input, pushes the public key back
+    VerifySig = 0xad, "verify_sig";
+    // pops n, n public keys, m and m signatures, fails unless the signatures
+    // are valid for m of the keys in the same order
+    CheckMultisig = 0xae, "check_multisig";
+    // pops a height or timestamp, fails until the chain reached it
+    CheckLockTime = 0xb1, "check_lock_time";
+    // pops a number of blocks, fails until the spent output is that deep in the chain
+    CheckAge = 0xb2, "check_age";
+    // pushes the length of the top item without popping it
+    Size = 0x82, "size";
+    // pops an item and pushes its hash
+    Sha256 = 0xa8, "sha256";
+    Ripemd160 = 0xa6, "ripemd160";
+    Hash160 = 0xa9, "hash160";
+    // pops the condition, the instructions until `else` or `endif` only run if
+    // it isn't empty or zeros, the ones between `else` and `endif` otherwise
+    If = 0x63, "if";
+    Else = 0x67, "else";
+    EndIf = 0x68, "endif";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    Op(Opcode),
+    Push(&'a [u8]),
+}
+
+// compiled script
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script(Vec<u8>);
+
+impl Script {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    // the bytes aren't checked until the script is run or disassembled
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push_opcode(mut self, opcode: Opcode) -> Self {
+        self.0.push(opcode.to_byte());
+        self
+    }
+
+    // uses the shortest push for the data
+    // panics if the data is longer than 65535 bytes
+    pub fn push_data(mut self, data: &[u8]) -> Self {
+        match data.len() {
+            0 => self.0.push(PUSH_EMPTY),
+            len if len <= MAX_DIRECT_PUSH as usize => self.0.push(len as u8),
+            len if len <= u8::MAX as usize => self.0.extend([PUSH_DATA_1, len as u8]),
+            len => {
+                let len = u16::try_from(len).expect("Data is too long for a push!");
+                self.0.push(PUSH_DATA_2);
+                self.0.extend(len.to_le_bytes());
+            }
+        }
+        self.0.extend(data);
+        self
+    }
+
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            bytes: &self.0,
+            position: 0,
+        }
+    }
+
+    // compiles the text syntax, e.g. `verify_sig to_addr <address> eq`
+    // every token has to be an opcode, hex data, an address or `0`
+    pub fn assemble(text: &str) -> Result<Self, ScriptError> {
+        let mut script = Self::new();
+
+        for (position, token) in text.split_whitespace().enumerate() {
+            script = if let Some(opcode) = Opcode::from_name(token) {
+                script.push_opcode(opcode)
+            } else if token == EMPTY_TOKEN {
+                script.push_data(&[])
+            } else if let Some(data) =
+                token_to_data(token).filter(|data| data.len() <= u16::MAX as usize)
+            {
+                script.push_data(&data)
+            } else {
+                return Err(ScriptError {
+                    position,
+                    opcode: token.to_string(),
+                });
+            };
+        }
+
+        Ok(script)
+    }
+
+    // lock in the form `to_script_addr <script address> eq`
+    pub fn is_script_hash(&self) -> bool {
+        matches!(
+            self.instructions().collect::<Result<Vec<Instruction>, _>>().as_deref(),
+            Ok([
+                Instruction::Op(Opcode::ToScriptAddr),
+                Instruction::Push(address),
+                Instruction::Op(Opcode::Eq),
+            ]) if ecdsa::is_addr_bytes(address)
+        )
+    }
+
+    // text syntax of the script, fails if a push is cut off
+    pub fn disassemble(&self) -> Result<String, ScriptError> {
+        let tokens = self
+            .instructions()
+            .map(|instruction| instruction.map(|instruction| instruction.to_string()))
+            .collect::<Result<Vec<String>, ScriptError>>()?;
+
+        Ok(tokens.join(" "))
+    }
+}
+
+fn token_to_data(token: &str) -> Option<Vec<u8>> {
+    if token.len().is_multiple_of(2) {
+        if let Ok(data) = Vec::from_hex(token) {
+            return Some(data);
+        }
+    }
+
+    ecdsa::addr_to_bytes(token)
+}
+
+impl fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Op(opcode) => write!(f, "{}", opcode.name()),
+            Instruction::Push([]) => write!(f, "{}", EMPTY_TOKEN),
+            Instruction::Push(data) if ecdsa::is_addr_bytes(data) => {
+                write!(f, "{}", bs58::encode(data).into_string())
+            }
+            Instruction::Push(data) => write!(f, "{}", data.to_hex()),
+        }
+    }
+}
+
+// iterator over the instructions of a script
+// returns an error and stops at an unknown opcode or a cut off push
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Instructions<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+
+        let (data, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(data)
+    }
+
+    fn next_instruction(&mut self) -> Option<Instruction<'a>> {
+        let byte = self.take(1)?[0];
+
+        let len = match byte {
+            PUSH_EMPTY..=MAX_DIRECT_PUSH => byte as usize,
+            PUSH_DATA_1 => self.take(1)?[0] as usize,
+            PUSH_DATA_2 => u16::from_le_bytes(self.take(2)?.try_into().ok()?) as usize,
+            _ => return Opcode::from_byte(byte).map(Instruction::Op),
+        };
+
+        self.take(len).map(Instruction::Push)
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, ScriptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        let position = self.position;
+        let start = self.bytes;
+        self.position += 1;
 
-fn op_eq(stack: &mut Vec<String>) -> Option<bool> {
+        match self.next_instruction() {
+            Some(instruction) => Some(Ok(instruction)),
+            None => {
+                // the rest of the script can't be read anymore
+                self.bytes = &[];
+                Some(Err(ScriptError {
+                    position,
+                    opcode: format!("invalid({})", start.to_hex()),
+                }))
+            }
+        }
+    }
+}
+
+// the instruction at which a script failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    // position of the instruction, the instructions of the lock
+    // are counted after the ones of the solution
+    pub position: usize,
+    pub opcode: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` failed at position {}", self.opcode, self.position)
+    }
+}
+
+impl Error for ScriptError {}
+
+// everything the opcodes can access besides the stack
+pub struct Context<'a> {
+    pub secp: &'a Secp256k1<All>,
+    // versions of the addresses `to_addr` and `to_script_addr` create
+    pub address_version: u8,
+    pub script_address_version: u8,
+    // the transaction and its input which is unlocked
+    pub tx: &'a Transaction,
+    pub input: usize,
+    // height of the block the transaction is in and the median time
+    // of the blocks before it, None if there are no blocks
+    pub height: usize,
+    pub median_time: Option<u64>,
+    // height of the block of the output the input spends
+    pub utxo_height: usize,
+}
+
+impl<'a> Context<'a> {
+    // uses the shared secp context and the address versions of the network
+    pub fn new(tx: &'a Transaction, input: usize, settings: &Settings) -> Self {
+        Self {
+            secp: ecdsa::shared_secp(),
+            address_version: settings.address_version,
+            script_address_version: settings.script_address_version,
+            tx,
+            input,
+            height: 0,
+            median_time: None,
+            utxo_height: 0,
+        }
+    }
+
+    // state of the chain the transaction is verified against
+    pub fn with_chain(
+        mut self,
+        height: usize,
+        median_time: Option<u64>,
+        utxo_height: usize,
+    ) -> Self {
+        self.height = height;
+        self.median_time = median_time;
+        self.utxo_height = utxo_height;
+        self
+    }
+}
+
+// shortest bytes of a number
+pub fn encode_number(number: u64) -> Vec<u8> {
+    let mut bytes = number.to_le_bytes().to_vec();
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    bytes
+}
+
+pub type Stack = Vec<Vec<u8>>;
+
+fn op_eq(stack: &mut Stack) -> Option<bool> {
     let val1 = stack.pop()?;
     let val2 = stack.pop()?;
 
     Some(val1 == val2)
 }
 
-fn to_addr(stack: &mut Vec<String>) -> Option<()> {
-    // get the public key in hex format
+fn to_addr(stack: &mut Stack, context: &Context) -> Option<()> {
     let pb_key = stack.pop()?;
-    // convert to array of bytes
-    let pk_bytes = Vec::from_hex(&pb_key).ok()?;
-    // convert to address
-    let addr = ecdsa::pb_key_to_addr(&pk_bytes);
-    // push onto the stack
-    stack.push(addr);
+    stack.push(ecdsa::pb_key_to_addr_bytes(
+        &pb_key,
+        context.address_version,
+    ));
+
+    Some(())
+}
+
+fn to_script_addr(stack: &mut Stack, context: &Context) -> Option<()> {
+    let script = stack.pop()?;
+    stack.push(ecdsa::script_to_addr_bytes(
+        &script,
+        context.script_address_version,
+    ));
 
     Some(())
 }
 
-fn verify_signature(stack: &mut Vec<String>, secp: &Secp256k1<All>) -> Option<bool> {
-    // pop data from stack
-    let msg_str = stack.pop()?;
-    let sig_str = stack.pop()?;
-    let pb_key_str = stack.pop()?;
+// pops the top `len` items, the top one is last
+fn pop_items(stack: &mut Stack, len: usize) -> Option<Stack> {
+    let start = stack.len().checked_sub(len)?;
+
+    Some(stack.split_off(start))
+}
+
+fn pop_number(stack: &mut Stack) -> Option<u64> {
+    let bytes = stack.pop()?;
+    if bytes.len() > MAX_NUMBER_LEN {
+        return None;
+    }
+
+    let mut number = [0; MAX_NUMBER_LEN];
+    number[..bytes.len()].copy_from_slice(&bytes);
+
+    Some(u64::from_le_bytes(number))
+}
+
+fn check_signature(sig_bytes: &[u8], pk_bytes: &[u8], context: &Context) -> Option<bool> {
+    // the last byte of the signature is the sighash type
+    let (sig_bytes, sighash_type) = sighash::split_signature(sig_bytes)?;
+    let sig = ecdsa::sig_from_bytes(sig_bytes).ok()?;
+    let pb_key = ecdsa::pb_key_from_bytes(pk_bytes).ok()?;
+
+    // recreate the signed message
+    let hash = sighash::sighash(context.tx, context.input, sighash_type).ok()?;
+    let msg = ecdsa::msg_from_bytes(&hash).ok()?;
 
-    // parse data
-    let msg_bytes = Vec::from_hex(&msg_str).ok()?;
-    let msg = ecdsa::msg_from_bytes(&msg_bytes).ok()?;
+    Some(ecdsa::valid_signature(context.secp, &msg, &sig, &pb_key))
+}
 
-    let sig_bytes = Vec::from_hex(&sig_str).ok()?;
-    let sig = ecdsa::sig_from_bytes(&sig_bytes).ok()?;
+fn verify_signature(stack: &mut Stack, context: &Context) -> Option<bool> {
+    let sig_bytes = stack.pop()?;
+    let pk_bytes = stack.pop()?;
 
-    let pk_bytes = Vec::from_hex(&pb_key_str).ok()?;
-    let pb_key = ecdsa::pb_key_from_bytes(&pk_bytes).ok()?;
+    let valid = check_signature(&sig_bytes, &pk_bytes, context)?;
 
     // push the public key back onto the stack
-    stack.push(pb_key_str);
+    stack.push(pk_bytes);
 
-    Some(ecdsa::valid_signature(secp, &msg, &sig, &pb_key))
+    Some(valid)
 }
 
-pub fn eval(script: String) -> Option<Vec<String>> {
-    let mut stack: Vec<String> = Vec::new();
-    let secp = ecdsa::create_secp();
+fn check_multisig(stack: &mut Stack, context: &Context) -> Option<bool> {
+    let keys_len = usize::try_from(pop_number(stack)?).ok()?;
+    if keys_len == 0 || keys_len > MAX_MULTISIG_KEYS {
+        return None;
+    }
+    let pb_keys = pop_items(stack, keys_len)?;
 
-    for val in script.split(' ') {
-        if val.is_empty() {
-            continue;
-        }
-        if !match val.to_lowercase().as_str() {
-            "eq" => op_eq(&mut stack).unwrap_or(false),
-            "to_addr" => to_addr(&mut stack).is_some(),
-            "verify_sig" => verify_signature(&mut stack, &secp).unwrap_or(false),
-            _ => {
-                stack.push(val.to_string());
+    let required = usize::try_from(pop_number(stack)?).ok()?;
+    if required == 0 || required > keys_len {
+        return None;
+    }
+    let signatures = pop_items(stack, required)?;
+
+    // every signature has to match a later key than the one before,
+    // so a key can't be counted twice
+    let mut pb_keys = pb_keys.iter();
+    Some(
+        signatures.iter().all(|sig| {
+            pb_keys.any(|pb_key| check_signature(sig, pb_key, context).unwrap_or(false))
+        }),
+    )
+}
+
+fn size(stack: &mut Stack) -> Option<()> {
+    let len = stack.last()?.len();
+    stack.push(encode_number(len as u64));
+
+    Some(())
+}
+
+// replaces the top item with its hash
+fn hash_top(stack: &mut Stack, hash: fn(&[u8]) -> Vec<u8>) -> Option<()> {
+    let data = stack.pop()?;
+    stack.push(hash(&data));
+
+    Some(())
+}
+
+// empty items and zeros are false
+fn pop_bool(stack: &mut Stack) -> Option<bool> {
+    Some(stack.pop()?.iter().any(|byte| *byte != 0))
+}
+
+fn check_lock_time(stack: &mut Stack, context: &Context) -> Option<bool> {
+    let lock_time = pop_number(stack)?;
+
+    if lock_time < LOCK_TIME_THRESHOLD {
+        Some(context.height as u64 >= lock_time)
+    } else {
+        Some(context.median_time? >= lock_time)
+    }
+}
+
+fn check_age(stack: &mut Stack, context: &Context) -> Option<bool> {
+    let blocks = pop_number(stack)?;
+    let depth = context.height.saturating_sub(context.utxo_height);
+
+    Some(depth as u64 >= blocks)
+}
+
+// runs the script on the stack, `offset` is added to the positions of errors
+pub fn eval(
+    script: &Script,
+    stack: &mut Stack,
+    context: &Context,
+    offset: usize,
+) -> Result<(), ScriptError> {
+    // the branches the instruction is in with the position of their `if`,
+    // instructions only run if every branch is taken
+    let mut branches: Vec<(usize, bool)> = Vec::new();
+
+    for (position, instruction) in script.instructions().enumerate() {
+        let instruction = instruction.map_err(|e| ScriptError {
+            position: e.position + offset,
+            ..e
+        })?;
+        let running = branches.iter().all(|(_, taken)| *taken);
+
+        if !match instruction {
+            Instruction::Op(Opcode::If) => {
+                // the condition is only popped if the branch runs
+                let taken = if running {
+                    pop_bool(stack)
+                } else {
+                    Some(false)
+                };
+                taken
+                    .map(|taken| branches.push((position, taken)))
+                    .is_some()
+            }
+            Instruction::Op(Opcode::Else) => match branches.last_mut() {
+                Some((_, taken)) => {
+                    *taken = !*taken;
+                    true
+                }
+                None => false,
+            },
+            Instruction::Op(Opcode::EndIf) => branches.pop().is_some(),
+            _ if !running => true,
+            Instruction::Push(data) => {
+                stack.push(data.to_vec());
                 true
             }
+            Instruction::Op(Opcode::Eq) => op_eq(stack).unwrap_or(false),
+            Instruction::Op(Opcode::ToAddr) => to_addr(stack, context).is_some(),
+            Instruction::Op(Opcode::ToScriptAddr) => to_script_addr(stack, context).is_some(),
+            Instruction::Op(Opcode::VerifySig) => verify_signature(stack, context).unwrap_or(false),
+            Instruction::Op(Opcode::CheckMultisig) => {
+                check_multisig(stack, context).unwrap_or(false)
+            }
+            Instruction::Op(Opcode::CheckLockTime) => {
+                check_lock_time(stack, context).unwrap_or(false)
+            }
+            Instruction::Op(Opcode::CheckAge) => check_age(stack, context).unwrap_or(false),
+            Instruction::Op(Opcode::Size) => size(stack).is_some(),
+            Instruction::Op(Opcode::Sha256) => {
+                hash_top(stack, |data| hashes::sha256(data).to_vec()).is_some()
+            }
+            Instruction::Op(Opcode::Ripemd160) => hash_top(stack, hashes::ripemd160).is_some(),
+            Instruction::Op(Opcode::Hash160) => hash_top(stack, hashes::hash160).is_some(),
         } {
-            log::debug!("Ivalid Script  at {}: {}", val, script);
-            return None;
+            return Err(ScriptError {
+                position: position + offset,
+                opcode: instruction.to_string(),
+            });
+        }
+    }
+
+    // every `if` needs an `endif`
+    if let Some((position, _)) = branches.first() {
+        return Err(ScriptError {
+            position: position + offset,
+            opcode: Opcode::If.name().to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// runs the solution and then the lock on the same stack
+// with a script hash lock the script at the top of the stack after the solution
+// is run next, the positions of its instructions are counted after the lock
+pub fn verify(solution: &Script, lock: &Script, context: &Context) -> Result<(), ScriptError> {
+    let mut stack = Stack::new();
+
+    eval(solution, &mut stack, context, 0)?;
+    let offset = solution.instructions().count();
+    let script = if lock.is_script_hash() {
+        stack.last().cloned().map(Script::from_bytes)
+    } else {
+        None
+    };
+    eval(lock, &mut stack, context, offset)?;
+
+    match script {
+        Some(script) => {
+            let offset = offset + lock.instructions().count();
+            eval(&script, &mut stack, context, offset)
         }
+        None => Ok(()),
     }
-    Some(stack)
+}
+
+// verifies a solution and a lock in the text syntax
+pub fn verify_text(solution: &str, lock: &str, context: &Context) -> Result<(), ScriptError> {
+    let solution = Script::assemble(solution)?;
+    let offset = solution.instructions().count();
+    let lock = Script::assemble(lock).map_err(|e| ScriptError {
+        position: e.position + offset,
+        ..e
+    })?;
+
+    verify(&solution, &lock, context)
 }
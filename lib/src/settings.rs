@@ -1,74 +1,278 @@
-use crate::blockchain::difficulty;
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blockchain::difficulty, hashes, target::Target};
+
+// independent chains with their own parameters
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    // public test network, coins have no value
+    Testnet,
+    // local network with trivial difficulty for testing
+    Regtest,
+}
+
+impl Network {
+    // the first block of a chain has to point to this hash,
+    // so every block commits to the network it was mined for
+    // mainnet keeps the zero hash of the chains from before the networks
+    pub fn genesis_previous(&self) -> [u8; 32] {
+        match self {
+            Network::Mainnet => [0u8; 32],
+            _ => hashes::sha256(format!("macc-{}", self).as_bytes()),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Network::Mainnet => write!(f, "mainnet"),
+            Network::Testnet => write!(f, "testnet"),
+            Network::Regtest => write!(f, "regtest"),
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format!("unknown network `{}`", s)),
+        }
+    }
+}
+
+// algorithm used to adjust the difficulty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retarget {
+    // every `adjustment_interval` blocks based on the time of the last interval
+    Windowed,
+    // every block based on a weighted average of the last `adjustment_interval` blocks
+    Lwma,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Settings {
+    // network settings
+    pub network: Network,
+    pub address_version: u8,        // first byte of the addresses
+    pub script_address_version: u8, // first byte of the script hash addresses
+    pub default_port: u16,
+    // the genesis block is built from these and the start difficulty
+    pub genesis_timestamp: u64,
+    pub genesis_nonce: u128,
+    // (height, hash) of blocks every chain of the network has to contain
+    pub checkpoints: &'static [(usize, [u8; 32])],
+    // side branches can replace at most this many blocks of the active chain
+    pub max_fork_depth: usize,
+    // blocks kept at one height, including the one of the active chain
+    pub max_blocks_per_height: usize,
+
     // difficulty settings
     pub target_time: u64,
     pub adjustment_interval: u32,
-    pub precision: u32,
+    pub retarget: Retarget,
+    // maximum factor the difficulty can change by in one adjustment
+    pub max_adjustment: u32,
+    // easiest difficulty allowed
+    pub min_difficulty: Target,
+
+    // timestamp settings
+    pub median_time_span: usize, // number of blocks
+    pub max_future_drift: u64,   // in seconds
 
     // mining settings
     pub halvings_interval: usize,
     pub start_mining_reward: u128,
+    // minimum reward once the halvings drop below it, None to halve down to zero
+    pub tail_emission: Option<u128>,
+    // blocks until coinbase outputs can be spent
+    pub coinbase_maturity: usize,
+
+    // consensus limits
+    pub max_block_txs: usize,
+    pub max_block_size: usize, // in bytes
+    pub max_script_len: usize, // in bytes, per solution or lock
+
+    // mempool settings
+    pub mempool_max_size: usize, // in bytes
+    pub mempool_expiry: u64,     // in seconds
 
     // general settings
     pub crypto_precision: u32,
-    pub start_difficulty: [u8; 32],
+    pub start_difficulty: Target,
 }
 
 impl Settings {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        network: Network,
+        address_version: u8,
+        script_address_version: u8,
+        default_port: u16,
+        genesis_timestamp: u64,
+        genesis_nonce: u128,
+        checkpoints: &'static [(usize, [u8; 32])],
+        max_fork_depth: usize,
+        max_blocks_per_height: usize,
         target_time: u64,
         adjustment_interval: u32,
-        precision: u32,
+        retarget: Retarget,
+        max_adjustment: u32,
+        min_difficulty: Target,
+        median_time_span: usize,
+        max_future_drift: u64,
         halvings_interval: usize,
         start_mining_reward: u128,
+        tail_emission: Option<u128>,
+        coinbase_maturity: usize,
+        max_block_txs: usize,
+        max_block_size: usize,
+        max_script_len: usize,
+        mempool_max_size: usize,
+        mempool_expiry: u64,
         crypto_precision: u32,
-        start_difficulty: [u8; 32],
+        start_difficulty: Target,
     ) -> Self {
         Self {
+            network,
+            address_version,
+            script_address_version,
+            default_port,
+            genesis_timestamp,
+            genesis_nonce,
+            checkpoints,
+            max_fork_depth,
+            max_blocks_per_height,
+
             target_time,
             adjustment_interval,
-            precision,
+            retarget,
+            max_adjustment,
+            min_difficulty,
+
+            median_time_span,
+            max_future_drift,
 
             halvings_interval,
             start_mining_reward,
+            tail_emission,
+            coinbase_maturity,
+
+            max_block_txs,
+            max_block_size,
+            max_script_len,
+
+            mempool_max_size,
+            mempool_expiry,
 
             crypto_precision,
             start_difficulty,
         }
     }
-}
 
-impl Default for Settings {
-    fn default() -> Self {
+    pub fn for_network(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::mainnet(),
+            Network::Testnet => Self::testnet(),
+            Network::Regtest => Self::regtest(),
+        }
+    }
+
+    pub fn mainnet() -> Self {
         let crypto_precision: u32 = 3;
 
         Self {
+            network: Network::Mainnet,
+            address_version: 0x00,
+            script_address_version: 0x05,
+            default_port: 8033,
+            genesis_timestamp: 1_792_281_600,
+            genesis_nonce: 298,
+            checkpoints: &[],
+            max_fork_depth: 100,
+            max_blocks_per_height: 8,
+
             target_time: 2,
             adjustment_interval: 30,
-            precision: 5,
+            retarget: Retarget::Windowed,
+            max_adjustment: 4,
+            min_difficulty: difficulty::create(8).expect("UNREACHABLE"),
+
+            median_time_span: 11,
+            max_future_drift: 60,
 
             halvings_interval: 43_200,
             start_mining_reward: 3000_u128 * 10_u32.pow(crypto_precision) as u128,
+            tail_emission: None,
+            coinbase_maturity: 100,
+
+            max_block_txs: 1000,
+            max_block_size: 1_000_000,
+            max_script_len: 10_000,
+
+            mempool_max_size: 5_000_000,
+            mempool_expiry: 60 * 60 * 24,
 
             crypto_precision,
-            start_difficulty: difficulty::create(1).expect("UNREACHABLE"),
+            start_difficulty: difficulty::create(8).expect("UNREACHABLE"),
         }
     }
-}
 
-// general settings
+    pub fn testnet() -> Self {
+        let mainnet = Self::mainnet();
+
+        Self {
+            network: Network::Testnet,
+            address_version: 0x6f,
+            script_address_version: 0xc4,
+            default_port: 18033,
+            genesis_nonce: 322,
+
+            retarget: Retarget::Lwma,
+
+            halvings_interval: 10_000,
+            start_mining_reward: 100_u128 * 10_u32.pow(mainnet.crypto_precision) as u128,
 
-pub static CC_PRECISION: u32 = 3;
+            ..mainnet
+        }
+    }
+
+    pub fn regtest() -> Self {
+        let mainnet = Self::mainnet();
+        // every hash satisfies the difficulty
+        let trivial = difficulty::create(0).expect("UNREACHABLE");
+
+        Self {
+            network: Network::Regtest,
+            address_version: 0x6f,
+            script_address_version: 0xc4,
+            default_port: 28033,
+            genesis_nonce: 0,
+
+            target_time: 1,
+            min_difficulty: trivial,
+
+            halvings_interval: 150,
 
-// mining settings
-pub static BLOCK_TX_LIMIT: usize = 1000;
+            start_difficulty: trivial,
 
-pub static START_MINING_REWARD: u128 = 100 * 10_i32.pow(CC_PRECISION) as u128;
-pub static HALVINGS_INTERVAL: usize = 10_000;
+            ..mainnet
+        }
+    }
+}
 
-// difficulty settings
-pub static BLOCK_TIME_TARGET: u64 = 2; // in seconds
-pub static DIFFICULTY_ADJUSTMENT_INTERVAL: usize = 30;
-pub static DIFFICULTY_ADJUSTMENT_PRECISION: u32 = 5;
+impl Default for Settings {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
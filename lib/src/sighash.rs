@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::io;
+
+use crate::blockchain::Transaction;
+use crate::encoding::Encode;
+use crate::hashes;
+
+// which outputs a signature commits to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigHashMode {
+    // every output
+    All,
+    // no output, anyone can change where the coins go
+    None,
+    // only the output with the same index as the signed input
+    Single,
+}
+
+// the sighash type is appended as the last byte to every signature
+// so the validator knows which message has to be reconstructed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigHashType {
+    pub mode: SigHashMode,
+    // if set, the signature only commits to its own input
+    // so other people can add inputs (e.g. crowdfunding)
+    pub anyone_can_pay: bool,
+}
+
+impl SigHashType {
+    pub const ALL: SigHashType = SigHashType {
+        mode: SigHashMode::All,
+        anyone_can_pay: false,
+    };
+
+    pub const ANYONE_CAN_PAY_FLAG: u8 = 0x80;
+
+    pub fn new(mode: SigHashMode, anyone_can_pay: bool) -> Self {
+        Self {
+            mode,
+            anyone_can_pay,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        let mode = match self.mode {
+            SigHashMode::All => 0x01,
+            SigHashMode::None => 0x02,
+            SigHashMode::Single => 0x03,
+        };
+
+        if self.anyone_can_pay {
+            mode | Self::ANYONE_CAN_PAY_FLAG
+        } else {
+            mode
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        let mode = match byte & !Self::ANYONE_CAN_PAY_FLAG {
+            0x01 => SigHashMode::All,
+            0x02 => SigHashMode::None,
+            0x03 => SigHashMode::Single,
+            _ => return None,
+        };
+
+        Some(Self::new(mode, byte & Self::ANYONE_CAN_PAY_FLAG != 0))
+    }
+}
+
+// length of a compact signature without the sighash byte
+pub const SIGNATURE_LEN: usize = 64;
+
+// splits a signature into the compact signature and its sighash type
+pub fn split_signature(bytes: &[u8]) -> Option<(&[u8], SigHashType)> {
+    if bytes.len() != SIGNATURE_LEN + 1 {
+        return None;
+    }
+
+    let sighash_type = SigHashType::from_byte(bytes[SIGNATURE_LEN])?;
+
+    Some((&bytes[..SIGNATURE_LEN], sighash_type))
+}
+
+// hash of the parts of the transaction the signature of input `input` commits to
+// in the canonical encoding:
+//   sighash type (u8), input, version, nonce, outpoints (hash, index), outputs
+//
+// the solutions are never part of the hash since they contain the signatures
+pub fn sighash(
+    tx: &Transaction,
+    input: usize,
+    sighash_type: SigHashType,
+) -> Result<[u8; 32], Box<dyn Error>> {
+    if input >= tx.vin.len() {
+        return Err(Box::new(io::Error::other("input index out of range")));
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.push(sighash_type.to_byte());
+    input.encode(&mut bytes);
+    tx.version.encode(&mut bytes);
+    tx.nonce.encode(&mut bytes);
+
+    // inputs
+    let outpoints: Vec<(&[u8; 32], &usize)> = if sighash_type.anyone_can_pay {
+        let (hash, index, _) = &tx.vin[input];
+        vec![(hash, index)]
+    } else {
+        tx.vin
+            .iter()
+            .map(|(hash, index, _)| (hash, index))
+            .collect()
+    };
+    outpoints.encode(&mut bytes);
+
+    // outputs
+    let outputs: Vec<&(u128, String)> = match sighash_type.mode {
+        SigHashMode::All => tx.vout.iter().collect(),
+        SigHashMode::None => vec![],
+        SigHashMode::Single => vec![tx
+            .vout
+            .get(input)
+            .ok_or_else(|| io::Error::other("sighash single without a matching output"))?],
+    };
+    outputs.encode(&mut bytes);
+
+    Ok(hashes::sha256(&bytes))
+}
@@ -0,0 +1,124 @@
+use num::BigUint;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blockchain::{
+        difficulty,
+        utils::{check_checkpoint, median_time_past, validate_header},
+        BlockHeader, ValidationError,
+    },
+    merkle::{self, MerkleProof},
+    settings::Settings,
+    target::Target,
+};
+
+// proof that a transaction is included in the block at `height`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxProof {
+    pub height: usize,
+    pub proof: MerkleProof,
+}
+
+// light client which only keeps the block headers
+// and trusts the chain with valid proof of work
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeaderChain(Vec<BlockHeader>);
+
+impl HeaderChain {
+    pub fn new_empty() -> Self {
+        Self(Vec::new())
+    }
+
+    // validates the headers from the first block on
+    pub fn from_headers(
+        headers: Vec<BlockHeader>,
+        settings: &Settings,
+    ) -> Result<Self, ValidationError> {
+        let mut chain = Self::new_empty();
+
+        for (height, header) in headers.into_iter().enumerate() {
+            chain
+                .add(header, settings)
+                .map_err(|e| ValidationError::InvalidBlock {
+                    height,
+                    error: Box::new(e),
+                })?;
+        }
+
+        Ok(chain)
+    }
+
+    pub fn height(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn at(&self, height: usize) -> Option<&BlockHeader> {
+        self.0.get(height)
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.0.last()
+    }
+
+    // expected number of hashes needed to produce the headers
+    pub fn total_work(&self) -> BigUint {
+        self.0.iter().map(BlockHeader::work).sum()
+    }
+
+    // median timestamp of the last headers, None if there are no headers
+    pub fn median_time_past(&self, settings: &Settings) -> Option<u64> {
+        let start = self.height().saturating_sub(settings.median_time_span);
+        let timestamps: Vec<u64> = self.0[start..]
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+
+        median_time_past(&timestamps, settings)
+    }
+
+    // difficulty the next header has to satisfy
+    pub fn next_difficulty(&self, settings: &Settings) -> Target {
+        let difficulty = self
+            .tip()
+            .and_then(BlockHeader::target)
+            .unwrap_or(settings.start_difficulty);
+
+        difficulty::next(
+            difficulty,
+            self.height(),
+            |i| self.0[i].timestamp,
+            |i| self.0[i].target().unwrap_or(settings.min_difficulty),
+            settings,
+        )
+    }
+
+    // validates the linkage and work of the header and appends it
+    pub fn add(&mut self, header: BlockHeader, settings: &Settings) -> Result<(), ValidationError> {
+        validate_header(
+            &header,
+            self.tip(),
+            self.median_time_past(settings),
+            &self.next_difficulty(settings),
+            settings,
+        )?;
+        let hash = header.hash(None).map_err(|_| ValidationError::HashFailed)?;
+        check_checkpoint(self.height(), &hash, settings)?;
+
+        self.0.push(header);
+
+        Ok(())
+    }
+
+    // checks if the transaction is part of the header chain
+    pub fn verify_tx(&self, hash: &[u8; 32], proof: &TxProof) -> bool {
+        match self.at(proof.height) {
+            Some(header) => merkle::verify(hash, &proof.proof, &header.merkle_root),
+            None => false,
+        }
+    }
+
+    // number of blocks on top of and including the block at `height`
+    pub fn confirmations(&self, height: usize) -> usize {
+        self.height().saturating_sub(height)
+    }
+}
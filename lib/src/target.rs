@@ -0,0 +1,108 @@
+use num::{BigUint, Zero};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{arr_to_bi, bi_to_arr};
+
+// a hash satisfies the target if it is lower or equal to it
+// serialized as the 32 big endian bytes of the number
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Target([u8; 32]);
+
+impl Target {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        self.0
+    }
+
+    // None if the number doesn't fit into 256 bits
+    pub fn from_biguint(bi: &BigUint) -> Option<Self> {
+        if bi.bits() > 256 {
+            return None;
+        }
+
+        Some(Self(bi_to_arr(bi)))
+    }
+
+    pub fn to_biguint(self) -> BigUint {
+        arr_to_bi(&self.0)
+    }
+
+    // decodes the compact 4 byte encoding:
+    // the highest byte is the length of the number in bytes
+    // and the lower 3 bytes are its most significant bytes
+    // None if the sign bit is set, the number doesn't fit into 256 bits
+    // or the encoding isn't the one of `to_compact`,
+    // so every target has exactly one encoding
+    pub fn from_compact(bits: u32) -> Option<Self> {
+        let size = bits >> 24;
+        let word = bits & 0x007f_ffff;
+
+        if bits & 0x0080_0000 != 0 && word != 0 {
+            return None;
+        }
+
+        let bi = if size <= 3 {
+            BigUint::from(word >> (8 * (3 - size)))
+        } else {
+            BigUint::from(word) << (8 * (size - 3))
+        };
+
+        Self::from_biguint(&bi).filter(|target| target.to_compact() == bits)
+    }
+
+    // encodes the target in 4 bytes, only the 3 most significant bytes are kept
+    pub fn to_compact(self) -> u32 {
+        let bi = self.to_biguint();
+        if bi.is_zero() {
+            return 0;
+        }
+
+        let mut size = bi.bits().div_ceil(8) as u32;
+        let mut word = if size <= 3 {
+            let low = bi.to_u32_digits().first().copied().unwrap_or_default();
+            low << (8 * (3 - size))
+        } else {
+            let high: BigUint = bi >> (8 * (size - 3));
+            high.to_u32_digits().first().copied().unwrap_or_default()
+        };
+
+        // the highest bit of the word is the sign bit
+        if word & 0x0080_0000 != 0 {
+            word >>= 8;
+            size += 1;
+        }
+
+        word | (size << 24)
+    }
+
+    // rounds the target to the precision of the compact encoding
+    pub fn normalized(self) -> Self {
+        Self::from_compact(self.to_compact()).expect("UNREACHABLE!")
+    }
+
+    // expected number of hashes to find a hash satisfying the target
+    // calculated as 2^256 / (target + 1)
+    pub fn work(self) -> BigUint {
+        (BigUint::from(1u8) << 256) / (self.to_biguint() + 1u8)
+    }
+
+    pub fn is_satisfied_by(self, hash: &[u8; 32]) -> bool {
+        // big endian byte arrays compare like the numbers they represent
+        hash <= &self.0
+    }
+}
+
+impl From<[u8; 32]> for Target {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Target> for [u8; 32] {
+    fn from(target: Target) -> Self {
+        target.0
+    }
+}
@@ -5,6 +5,7 @@ use num::BigUint;
 
 use crate::blockchain::difficulty;
 use crate::blockchain::Block;
+use crate::target::Target;
 
 // returns current unix time
 pub fn current_time() -> u64 {
@@ -15,7 +16,7 @@ pub fn current_time() -> u64 {
 }
 
 // bruteforces a nonce until the difficulty is satisfied
-pub fn find_nonce(block: &Block, difficulty: &[u8; 32]) -> Result<u128, Box<dyn Error>> {
+pub fn find_nonce(block: &Block, difficulty: &Target) -> Result<u128, Box<dyn Error>> {
     let mut nonce = 0;
 
     while !difficulty::satisfies(difficulty, &block.hash(Some(nonce))?) {
@@ -7,18 +7,39 @@ mod tests {
     use macc_lib::blockchain::*;
     use macc_lib::ecdsa::*;
     use macc_lib::hex::ToHex;
-    use macc_lib::settings::Settings;
+    use macc_lib::script;
+    use macc_lib::settings::{Network, Settings};
+    use macc_lib::sighash::{SigHashMode, SigHashType};
     use macc_lib::utils::*;
 
+    // next block paying its reward to `lock`, regtest blocks need no work
+    fn mine(
+        bc: &Blockchain,
+        mut transactions: Vec<Transaction>,
+        lock: &str,
+        settings: &Settings,
+    ) -> Result<Block, Box<dyn Error>> {
+        let reward = utils::calculate_mining_reward(bc.height(), settings);
+        transactions.insert(
+            0,
+            Transaction::new_coinbase(bc.height(), reward, lock.to_string()),
+        );
+        let diff = bc.next_difficulty(settings);
+        Block::new(current_time(), bc.at(-1).hash(None)?, diff, transactions)
+    }
+
     #[test]
     fn test_blockchain_full() -> Result<(), Box<dyn Error>> {
-        // init settings
-        let settings = Settings::default();
+        // init settings, coinbase outputs can be spent in the next block
+        let settings = Settings {
+            coinbase_maturity: 1,
+            ..Settings::default()
+        };
 
-        // create blockchain
-        let mut bc = Blockchain::new_empty();
         // create store
         let mut store = TxStore::new_empty();
+        // create blockchain, it starts with the genesis block
+        let mut bc = Blockchain::new(&mut store, &settings);
         // create start difficulty
         let mut diff = settings.start_difficulty;
 
@@ -29,41 +50,32 @@ mod tests {
         let mut miner_client = Client::new_random(&secp, &mut rng);
         let my_client = Client::new_random(&secp, &mut rng);
 
-        // create a block
-        let mut b = Block {
-            timestamp: current_time(),
-
-            // the first block can have any previous hash
-            // it is simply ignored
-            previous: [0u8; 32],
-
-            // nonce is not found yet
-            nonce: 0,
-
-            // no transactions
-            transactions: vec![],
-        };
-
         // create coinbase transaction
         let reward = utils::calculate_mining_reward(bc.height(), &settings);
         let lock = create_lock(&miner_client.pb_key);
         let cb_tx = Transaction::new_coinbase(bc.height(), reward, lock);
 
         let cb_hash = cb_tx.hash()?;
-        // add coinbase transaction to the block
-        b.transactions.push(cb_tx);
-
-        // find & set nonce for block
-        b.nonce = find_nonce(&b, &diff)?;
-
-        // check if block can be added
 
         // calculate difficulty for the block
         diff = bc.adjust_difficulty(diff, &settings);
-        // check if block can be added
-        let can_be_added = bc.valid_next(&b, &store, &diff, &settings).unwrap_or(false);
 
-        assert!(can_be_added);
+        // create a block
+        let mut b = Block::new(
+            current_time(),
+            // hash of the genesis block
+            bc.at(-1).hash(None)?,
+            // difficulty the block hash has to satisfy
+            diff,
+            // add coinbase transaction to the block
+            vec![cb_tx],
+        )?;
+
+        // find & set nonce for block
+        b.header.nonce = find_nonce(&b, &diff)?;
+
+        // check if block can be added
+        bc.valid_next(&b, &store, &diff, &settings)?;
 
         // add block
 
@@ -71,29 +83,15 @@ mod tests {
 
         // create new block and send a transaction
 
-        // create the block
-        let mut b = Block {
-            timestamp: current_time(),
-
-            // hash of the previous transaction
-            // NOTE: could also use `b.hash(None)?`
-            previous: bc.at(-1).hash(None)?,
-
-            // nonce is not found yet
-            nonce: 0,
-
-            // will manually add new transactions
-            transactions: vec![],
+        // every block starts with a coinbase transaction
+        // the rewards of the following blocks go to another miner
+        let other_miner = Client::new_random(&secp, &mut rng);
+        let coinbase = |bc: &Blockchain| {
+            let reward = utils::calculate_mining_reward(bc.height(), &settings);
+            let lock = create_lock(&other_miner.pb_key);
+            Transaction::new_coinbase(bc.height(), reward, lock)
         };
 
-        // NOTE: technically a coinbase transaction is not needed
-        // create coinbase transaction
-        // let reward = utils::calculate_mining_reward(bc.height(), &settings);
-        // let lock = create_lock(&miner_client.pb_key);
-        // let cb_tx = Transaction::new_coinbase(0, reward, lock);
-        // add coinbase transaction to the block
-        // b.transactions.push(cb_tx);
-
         // create our own transaction in which the miner sends
         // half of his previous mining reward to our address
         // and the other half back to himself
@@ -111,12 +109,11 @@ mod tests {
             .create_transaction(&secp, input, output)
             .expect("Couldn't create the transaction!");
         // the code below would do this manually
-        // create the "message" which is the hash of the utxo we want to unlock
-        // let message_str = utils::hash_utxou((&cb_hash, &0))?.to_hex();
-        // create the message object
-        // let message = msg_from_str(&message_str);
+        // create the "message" which is the sighash of the input we want to unlock
+        // NOTE: it is computed over the transaction below with an empty solution
+        // let message = msg_from_bytes(&sighash::sighash(&tx, 0, SigHashType::ALL)?)?;
         // create the solution
-        // let miner_solution = create_solution(&secp, &miner_client, &message);
+        // let miner_solution = create_solution(&secp, &miner_client, &message, SigHashType::ALL);
         // create new lock (using the address of 'my_client')
         // let my_client_lock = create_lock(&my_client.pb_key);
         // create new lock (using the address of 'miner_client')
@@ -136,20 +133,25 @@ mod tests {
         // vout: vec![(reward / 2, my_client_lock), (reward - (reward / 2), miner_client_lock)]
         // };
         let tx_hash = tx.hash()?;
-        // add tx to block
-        b.transactions.push(tx);
-
-        // find & set nonce for block
-        b.nonce = find_nonce(&b, &diff)?;
-
-        // check if block can be added
 
         // calculate difficulty for the block
         diff = bc.adjust_difficulty(diff, &settings);
-        // check if block can be added
-        let can_be_added = bc.valid_next(&b, &store, &diff, &settings).unwrap_or(false);
 
-        assert!(can_be_added);
+        // create the block
+        let mut b = Block::new(
+            current_time(),
+            // hash of the previous block
+            bc.at(-1).hash(None)?,
+            diff,
+            // add coinbase and tx to block
+            vec![coinbase(&bc), tx],
+        )?;
+
+        // find & set nonce for block
+        b.header.nonce = find_nonce(&b, &diff)?;
+
+        // check if block can be added
+        bc.valid_next(&b, &store, &diff, &settings)?;
 
         // add block
 
@@ -157,23 +159,23 @@ mod tests {
 
         assert!(bc.get_transaction(&tx_hash).is_some());
 
-        assert!(bc.is_valid(&settings).is_some());
+        assert!(bc.is_valid(&settings).is_ok());
 
         let (balance, _txs) = store
-            .get_owned(my_client.sk_key.serialize_secret().to_hex())
+            .get_owned(my_client.sk_key.serialize_secret().to_hex(), &settings)
             .expect("Couldn't get owned transactions");
 
-        assert_eq!(balance, 50000);
+        assert_eq!(balance, reward / 2);
 
         let (balance, utxos) = store
-            .get_owned(miner_client.sk_key.serialize_secret().to_hex())
+            .get_owned(miner_client.sk_key.serialize_secret().to_hex(), &settings)
             .expect("Couldn't get owned transactions");
 
         let (f_balance, _) = store
             .get_owned_fast(pb_key_to_addr(&miner_client.pb_key.serialize()))
             .expect("Couldn't get owned fast!");
 
-        assert_eq!(balance, 50000);
+        assert_eq!(balance, reward - (reward / 2));
         assert_eq!(balance, f_balance);
 
         let tx = generate_transaction(
@@ -181,26 +183,20 @@ mod tests {
             miner_client.sk_key.serialize_secret().to_hex(),
             pb_key_to_addr(&my_client.pb_key.serialize()),
             1000,
+            &settings,
         )
         .expect("Couldnt generate transaction!");
 
-        let mut b = Block {
-            timestamp: current_time(),
-            previous: bc.at(-1).hash(None)?,
-            nonce: 0,
-            transactions: vec![tx],
-        };
-        // find & set nonce for block
-        b.nonce = find_nonce(&b, &diff)?;
-
-        // check if block can be added
-
         // calculate difficulty for the block
         diff = bc.adjust_difficulty(diff, &settings);
-        // check if block can be added
-        let can_be_added = bc.valid_next(&b, &store, &diff, &settings).unwrap_or(false);
 
-        assert!(can_be_added);
+        let cb_tx = coinbase(&bc);
+        let mut b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, vec![cb_tx, tx])?;
+        // find & set nonce for block
+        b.header.nonce = find_nonce(&b, &diff)?;
+
+        // check if block can be added
+        bc.valid_next(&b, &store, &diff, &settings)?;
 
         bc.add(&mut store, b);
 
@@ -208,7 +204,1076 @@ mod tests {
             .get_owned_fast(pb_key_to_addr(&miner_client.pb_key.serialize()))
             .expect("Couldn't get owned fast!");
 
-        assert_eq!(bal, 50000 - 1000);
+        assert_eq!(bal, reward - (reward / 2) - 1000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_commits_to_transaction() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+        let thief = Client::new_random(&secp, &mut rng);
+
+        // give alice and bob an utxo each
+        let mut store = TxStore::new_empty();
+        store.set(
+            &[1u8; 32],
+            0,
+            Utxo::new(1000, create_lock(&alice.pb_key), 0),
+        );
+        store.set(&[2u8; 32], 0, Utxo::new(500, create_lock(&bob.pb_key), 0));
+
+        // alice sends her coins to bob
+        let tx = alice
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(1000, bob.pb_key)])
+            .expect("Couldn't create the transaction!");
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_ok());
+
+        // a relaying node rewrites the output to itself
+        let mut stolen = tx.clone();
+        stolen.vout[0].1 = create_lock(&thief.pb_key);
+        assert!(matches!(
+            utils::is_valid_tx(&stolen, &store, None, &settings),
+            Err(ValidationError::ScriptFailure { input: 0, .. })
+        ));
+
+        // crowdfunding: alice only signs her input and the output
+        // so bob can add his own input later
+        let sighash_type = SigHashType::new(SigHashMode::All, true);
+        let mut tx = Transaction {
+            version: TX_VERSION,
+            nonce: 0,
+            vin: vec![([1u8; 32], 0, String::new())],
+            vout: vec![(1500, create_lock(&thief.pb_key))],
+        };
+        tx.vin[0].2 = sign_input(&secp, &alice, &tx, 0, sighash_type)?;
+
+        tx.vin.push(([2u8; 32], 0, String::new()));
+        tx.vin[1].2 = sign_input(&secp, &bob, &tx, 1, sighash_type)?;
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_ok());
+
+        // changing the output still invalidates alice's signature
+        tx.vout[0].0 = 1400;
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_err());
+
+        // single: alice only commits to the output with the same index
+        let sighash_type = SigHashType::new(SigHashMode::Single, false);
+        let mut tx = Transaction {
+            version: TX_VERSION,
+            nonce: 0,
+            vin: vec![([1u8; 32], 0, String::new())],
+            vout: vec![(800, create_lock(&alice.pb_key))],
+        };
+        tx.vin[0].2 = sign_input(&secp, &alice, &tx, 0, sighash_type)?;
+        tx.vout.push((200, create_lock(&bob.pb_key)));
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_ok());
+
+        tx.vout[0].0 = 700;
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation_errors() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut client = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        store.set(
+            &[1u8; 32],
+            0,
+            Utxo::new(1000, create_lock(&client.pb_key), 0),
+        );
+
+        // spending an unknown utxo
+        let tx = client
+            .create_transaction(&secp, vec![([2u8; 32], 0)], vec![(1000, client.pb_key)])
+            .expect("Couldn't create the transaction!");
+        assert_eq!(
+            utils::is_valid_tx(&tx, &store, None, &settings),
+            Err(ValidationError::MissingUtxo {
+                hash: [2u8; 32],
+                index: 0
+            })
+        );
+
+        // spending more than the input
+        let tx = client
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(1001, client.pb_key)])
+            .expect("Couldn't create the transaction!");
+        assert_eq!(
+            utils::is_valid_tx(&tx, &store, None, &settings),
+            Err(ValidationError::Overspend {
+                vin_total: 1000,
+                vout_total: 1001
+            })
+        );
+
+        // mine a block on top of the genesis block
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let reward = utils::calculate_mining_reward(bc.height(), &settings);
+        let cb_tx = Transaction::new_coinbase(bc.height(), reward, create_lock(&client.pb_key));
+        let mut b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, vec![cb_tx])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+        bc.add(&mut store, b);
+
+        // blocks without a coinbase, empty or with other transactions first
+        let tx = client
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(1000, client.pb_key)])
+            .expect("Couldn't create the transaction!");
+        for transactions in [vec![], vec![tx]] {
+            let mut b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, transactions)?;
+            b.header.nonce = find_nonce(&b, &diff)?;
+            assert_eq!(
+                bc.valid_next(&b, &store, &diff, &settings),
+                Err(ValidationError::MissingCoinbase)
+            );
+        }
+
+        // block from the past
+        let mut b = Block::new(
+            bc.at(-1).header.timestamp - 1,
+            bc.at(-1).hash(None)?,
+            diff,
+            vec![],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert!(matches!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::InvalidTimestamp { .. })
+        ));
+
+        // block not building on the last block
+        let mut b = Block::new(current_time(), [0u8; 32], diff, vec![])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::WrongPrevious)
+        );
+
+        // coinbase claiming too much
+        let reward = utils::calculate_mining_reward(bc.height(), &settings);
+        let mut b = Block::new(
+            current_time(),
+            bc.at(-1).hash(None)?,
+            diff,
+            vec![Transaction::new_coinbase(
+                bc.height(),
+                reward + 1,
+                create_lock(&client.pb_key),
+            )],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::RewardTooHigh {
+                claimed: reward + 1,
+                allowed: reward
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp_rules() -> Result<(), Box<dyn Error>> {
+        let now = current_time();
+
+        // retarget every 5 blocks with the median of the last 3 blocks
+        // and allow the difficulty to get easier than the start difficulty
+        // the genesis block is recent, so the first interval isn't stretched
+        let mut settings = Settings {
+            adjustment_interval: 5,
+            median_time_span: 3,
+            min_difficulty: difficulty::create(0)?,
+            genesis_timestamp: now - 10,
+            ..Settings::default()
+        };
+        settings.genesis_nonce =
+            find_nonce(&Block::genesis(&settings), &settings.start_difficulty)?;
+        let diff = settings.start_difficulty;
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let miner = Client::new_random(&secp, &mut rng);
+
+        let mine = |bc: &Blockchain, timestamp: u64| -> Result<Block, Box<dyn Error>> {
+            let reward = utils::calculate_mining_reward(bc.height(), &settings);
+            let cb_tx = Transaction::new_coinbase(bc.height(), reward, create_lock(&miner.pb_key));
+            let mut b = Block::new(timestamp, bc.at(-1).hash(None)?, diff, vec![cb_tx])?;
+            b.header.nonce = find_nonce(&b, &diff)?;
+            Ok(b)
+        };
+
+        for timestamp in [now - 7, now - 5, now - 6] {
+            let b = mine(&bc, timestamp)?;
+            bc.valid_next(&b, &store, &diff, &settings)?;
+            bc.add(&mut store, b);
+        }
+        // the median of the last blocks is used, not the newest timestamp
+        assert_eq!(bc.median_time_past(&settings), Some(now - 6));
+
+        // a block older than the median would make the interval
+        // look shorter and raise the difficulty for the others
+        let b = mine(&bc, now - 7)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::InvalidTimestamp {
+                timestamp: now - 7,
+                median: now - 6
+            })
+        );
+
+        // a block far in the future would make the interval look longer
+        // and lower the difficulty of the next blocks
+        let future = now + settings.max_future_drift + 1000;
+        let b = mine(&bc, future)?;
+        assert!(matches!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::FutureTimestamp { timestamp, .. }) if timestamp == future
+        ));
+        let mut manipulated = bc.clone();
+        manipulated.add(&mut store.clone(), b);
+
+        // within the allowed drift the block is valid
+        let b = mine(&bc, now + settings.max_future_drift)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+
+        // the honest chain keeps its difficulty
+        let b = mine(&bc, now)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+        bc.add(&mut store, b);
+        assert_eq!(bc.next_difficulty(&settings), diff);
+        assert!(
+            difficulty::work(&manipulated.next_difficulty(&settings))
+                < difficulty::work(&bc.next_difficulty(&settings))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_consensus_limits() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut client = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        store.set(
+            &[1u8; 32],
+            0,
+            Utxo::new(1000, create_lock(&client.pb_key), 0),
+        );
+
+        let bc = Blockchain::new(&mut store, &settings);
+        let reward = utils::calculate_mining_reward(1, &settings);
+        let cb_tx = Transaction::new_coinbase(1, reward, create_lock(&client.pb_key));
+        let tx = client
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(1000, client.pb_key)])
+            .expect("Couldn't create the transaction!");
+
+        let previous = bc.at(-1).hash(None)?;
+        let mut b = Block::new(current_time(), previous, diff, vec![cb_tx, tx.clone()])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+
+        // too many transactions
+        let limited = Settings {
+            max_block_txs: 1,
+            ..settings
+        };
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &limited),
+            Err(ValidationError::TooManyTransactions { count: 2, limit: 1 })
+        );
+
+        // too many bytes
+        let limited = Settings {
+            max_block_size: b.size()? - 1,
+            ..settings
+        };
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &limited),
+            Err(ValidationError::BlockTooLarge {
+                size: b.size()?,
+                limit: b.size()? - 1
+            })
+        );
+
+        // too long solution
+        let limited = Settings {
+            max_script_len: tx.vin[0].2.len() - 1,
+            ..settings
+        };
+        assert!(matches!(
+            bc.valid_next(&b, &store, &diff, &limited),
+            Err(ValidationError::InvalidTransaction { index: 1, error })
+                if matches!(*error, ValidationError::ScriptTooLong { .. })
+        ));
+        assert!(utils::check_tx_limits(&tx, &settings).is_ok());
+
+        // unknown versions
+        let future = Transaction {
+            version: TX_VERSION + 1,
+            ..tx
+        };
+        assert_eq!(
+            utils::check_tx_limits(&future, &settings),
+            Err(ValidationError::UnknownVersion {
+                version: TX_VERSION + 1
+            })
+        );
+        b.header.version = BLOCK_VERSION + 1;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::UnknownVersion {
+                version: BLOCK_VERSION + 1
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_double_spend_in_block() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let mut bob = Client::new_random(&secp, &mut rng);
+        let thief = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        store.set(
+            &[1u8; 32],
+            0,
+            Utxo::new(1000, create_lock(&alice.pb_key), 0),
+        );
+
+        let bc = Blockchain::new(&mut store, &settings);
+        let previous = bc.at(-1).hash(None)?;
+        let reward = utils::calculate_mining_reward(bc.height(), &settings);
+        let cb_tx = Transaction::new_coinbase(bc.height(), reward, create_lock(&thief.pb_key));
+
+        // alice spends the same utxo in two transactions
+        let to_bob = alice
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(1000, bob.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let to_thief = alice
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(1000, thief.pb_key)])
+            .expect("Couldn't create the transaction!");
+
+        // each of them is valid on its own
+        assert!(utils::is_valid_tx(&to_bob, &store, None, &settings).is_ok());
+        assert!(utils::is_valid_tx(&to_thief, &store, None, &settings).is_ok());
+
+        // but not both in the same block
+        let mut b = Block::new(
+            current_time(),
+            previous,
+            diff,
+            vec![cb_tx.clone(), to_bob.clone(), to_thief],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::InvalidTransaction {
+                index: 2,
+                error: Box::new(ValidationError::MissingUtxo {
+                    hash: [1u8; 32],
+                    index: 0
+                })
+            })
+        );
+
+        // spending an output created earlier in the same block is fine
+        let chained = bob
+            .create_transaction(&secp, vec![(to_bob.hash()?, 0)], vec![(1000, thief.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let mut b = Block::new(
+            current_time(),
+            previous,
+            diff,
+            vec![cb_tx, to_bob.clone(), chained.clone()],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+
+        // the overlay sees the same outputs as the store would
+        // without changing the store
+        let before = store.clone();
+        let mut view = StoreOverlay::new(&store);
+        let mut expected = store.clone();
+        for tx in [&to_bob, &chained] {
+            view.add_tx(tx);
+            utils::add_tx_to_store(tx, &mut expected);
+        }
+        for (hash, index) in [([1u8; 32], 0), (to_bob.hash()?, 0), (chained.hash()?, 0)] {
+            assert_eq!(
+                UtxoView::get(&view, &hash, &index),
+                expected.get(&hash, &index)
+            );
+        }
+        assert_eq!(store, before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disconnect_tip() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            coinbase_maturity: 1,
+            ..Settings::default()
+        };
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let mut bob = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut stores = vec![store.clone()];
+
+        // first block only has the coinbase of alice
+        let reward = utils::calculate_mining_reward(1, &settings);
+        let cb_tx = Transaction::new_coinbase(1, reward, create_lock(&alice.pb_key));
+        let cb_hash = cb_tx.hash()?;
+        let previous = bc.at(-1).hash(None)?;
+        let mut b = Block::new(current_time(), previous, diff, vec![cb_tx])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+        bc.add(&mut store, b);
+        stores.push(store.clone());
+
+        // second block spends the coinbase and an output created in the same block
+        let to_bob = alice
+            .create_transaction(
+                &secp,
+                vec![(cb_hash, 0)],
+                vec![(reward / 2, bob.pb_key), (reward / 2, alice.pb_key)],
+            )
+            .expect("Couldn't create the transaction!");
+        let to_alice = bob
+            .create_transaction(
+                &secp,
+                vec![(to_bob.hash()?, 0)],
+                vec![(reward / 2, alice.pb_key)],
+            )
+            .expect("Couldn't create the transaction!");
+        let cb_tx = Transaction::new_coinbase(2, reward, create_lock(&bob.pb_key));
+        let diff = bc.adjust_difficulty(diff, &settings);
+        let mut b = Block::new(
+            current_time(),
+            bc.at(-1).hash(None)?,
+            diff,
+            vec![cb_tx, to_bob, to_alice],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+        bc.add(&mut store, b.clone());
+
+        // the undo record only contains outputs which existed before the block
+        assert_eq!(
+            bc.undo(2).and_then(|undo| undo.spent(1)).map(|s| s.len()),
+            Some(1)
+        );
+        assert_eq!(
+            bc.undo(2).and_then(|undo| undo.spent(2)).map(|s| s.len()),
+            Some(1)
+        );
+
+        let undo = bc.undo(2).cloned().expect("No undo record!");
+
+        // every block adds the work of its difficulty
+        let genesis_work = difficulty::work(&settings.start_difficulty);
+        assert_eq!(
+            bc.total_work(),
+            &genesis_work * 2u8 + difficulty::work(&diff)
+        );
+
+        // the undo records are serialized with the blocks
+        let mut bc: Blockchain = bincode::deserialize(&bincode::serialize(&bc)?)?;
+        assert_eq!(bc.undo(2), Some(&undo));
+        // and can be recomputed from the blocks alone
+        let blocks = bc.slice(0, bc.height()).expect("UNREACHABLE!").to_vec();
+        let replayed = Blockchain::replay(blocks);
+        assert_eq!(replayed.undo(2), Some(&undo));
+
+        // disconnecting restores the store exactly
+        let disconnected = bc
+            .disconnect_tip(&mut store)
+            .expect("No block to disconnect!");
+        assert_eq!(disconnected.hash(None)?, b.hash(None)?);
+        assert_eq!(store, stores[1]);
+        assert_eq!(bc.height(), 2);
+        assert_eq!(bc.total_work(), &genesis_work * 2u8);
+
+        bc.disconnect_tip(&mut store)
+            .expect("No block to disconnect!");
+        assert_eq!(store, stores[0]);
+        assert_eq!(bc.height(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_input() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        store.set(
+            &[1u8; 32],
+            0,
+            Utxo::new(1000, create_lock(&alice.pb_key), 0),
+        );
+
+        // alice lists her utxo twice to double its value
+        let tx = alice
+            .create_transaction(
+                &secp,
+                vec![([1u8; 32], 0), ([1u8; 32], 0)],
+                vec![(2000, alice.pb_key)],
+            )
+            .expect("Couldn't create the transaction!");
+
+        let duplicate = ValidationError::DuplicateInput {
+            hash: [1u8; 32],
+            index: 0,
+        };
+        assert_eq!(
+            utils::is_valid_tx(&tx, &store, None, &settings),
+            Err(duplicate.clone())
+        );
+
+        // the block containing it is rejected as well
+        let bc = Blockchain::new(&mut store, &settings);
+        let mut b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, vec![tx])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::InvalidTransaction {
+                index: 0,
+                error: Box::new(duplicate)
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_networks() -> Result<(), Box<dyn Error>> {
+        // coinbase outputs can be spent in the next block
+        let mainnet = Settings {
+            coinbase_maturity: 1,
+            ..Settings::for_network(Network::Mainnet)
+        };
+        let regtest = Settings {
+            coinbase_maturity: 1,
+            ..Settings::for_network(Network::Regtest)
+        };
+        assert_eq!("regtest".parse(), Ok(Network::Regtest));
+        assert_ne!(mainnet.address_version, regtest.address_version);
+        assert_ne!(mainnet.default_port, regtest.default_port);
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut client = Client::new_random(&secp, &mut rng);
+        client.address_version = regtest.address_version;
+
+        // the genesis block commits to its network
+        let genesis = Block::genesis(&regtest);
+        let empty = Blockchain::new_empty();
+        let store = TxStore::new_empty();
+        empty.valid_next(&genesis, &store, &regtest.start_difficulty, &regtest)?;
+        assert_eq!(
+            empty.valid_next(&genesis, &store, &mainnet.start_difficulty, &mainnet),
+            Err(ValidationError::WrongNetwork)
+        );
+        let testnet = Settings::testnet();
+        assert_eq!(
+            empty.valid_next(&genesis, &store, &testnet.start_difficulty, &testnet),
+            Err(ValidationError::WrongNetwork)
+        );
+
+        // regtest blocks need no work
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &regtest);
+        let diff = bc.next_difficulty(&regtest);
+        let reward = utils::calculate_mining_reward(1, &regtest);
+        let lock = create_lock_with_addr(&pb_key_to_addr_with_version(
+            &client.pb_key.serialize(),
+            regtest.address_version,
+        ));
+        let cb_tx = Transaction::new_coinbase(1, reward, lock);
+        let cb_hash = cb_tx.hash()?;
+        let b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, vec![cb_tx])?;
+        bc.valid_next(&b, &store, &diff, &regtest)?;
+
+        // addresses of one network can't be spent on another
+        bc.add(&mut store, b);
+        let tx = client
+            .create_transaction(&secp, vec![(cb_hash, 0)], vec![(reward, client.pb_key)])
+            .expect("Couldn't create the transaction!");
+        assert!(utils::is_valid_tx(&tx, &store, None, &regtest).is_ok());
+        assert!(matches!(
+            utils::is_valid_tx(&tx, &store, None, &mainnet),
+            Err(ValidationError::ScriptFailure { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_rules() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let miner = Client::new_random(&secp, &mut rng);
+        let lock = create_lock(&miner.pb_key);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        store.set(
+            &[1u8; 32],
+            0,
+            Utxo::new(1000, create_lock(&alice.pb_key), 0),
+        );
+        let reward = utils::calculate_mining_reward(1, &settings);
+
+        // a coinbase needs an output with a lock of the network
+        assert!(matches!(
+            Coinbase::new(1, 0, &settings).build(),
+            Err(ValidationError::EmptyCoinbase)
+        ));
+        assert!(matches!(
+            Coinbase::new(1, 0, &settings)
+                .pay(reward, "1 1 eq".to_string())
+                .build(),
+            Err(ValidationError::MalformedCoinbaseLock { output: 0 })
+        ));
+        let testnet_lock = create_lock_with_addr(&pb_key_to_addr_with_version(
+            &miner.pb_key.serialize(),
+            Settings::testnet().address_version,
+        ));
+        assert!(matches!(
+            Coinbase::new(1, 0, &settings)
+                .pay(1, lock.clone())
+                .pay(1, testnet_lock)
+                .build(),
+            Err(ValidationError::MalformedCoinbaseLock { output: 1 })
+        ));
+        assert!(matches!(
+            Coinbase::new(1, 100, &settings)
+                .pay(reward + 101, lock.clone())
+                .build(),
+            Err(ValidationError::RewardTooHigh { .. })
+        ));
+
+        // alice pays a fee of 100
+        let tx = alice
+            .create_transaction(&secp, vec![([1u8; 32], 0)], vec![(900, alice.pb_key)])
+            .expect("Couldn't create the transaction!");
+
+        // the coinbase has to be the first transaction
+        let coinbase = Coinbase::new(1, 100, &settings)
+            .pay_remaining(lock.clone())
+            .build()?;
+        assert_eq!(coinbase.vout_total(), reward + 100);
+        let mut b = Block::new(
+            current_time(),
+            bc.at(-1).hash(None)?,
+            diff,
+            vec![tx.clone(), coinbase],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::CoinbaseNotFirst { index: 1 })
+        );
+
+        // claiming less than allowed burns the rest
+        let coinbase = Coinbase::new(1, 100, &settings).pay(reward - 50, lock);
+        assert_eq!(coinbase.remaining(), 150);
+        let mut b = Block::new(
+            current_time(),
+            bc.at(-1).hash(None)?,
+            diff,
+            vec![coinbase.build()?, tx],
+        )?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+        bc.add(&mut store, b);
+
+        let fees = bc.fees(1, &settings).expect("Block not found!");
+        assert_eq!(
+            fees,
+            BlockFees {
+                reward,
+                fees: 100,
+                claimed: reward - 50,
+            }
+        );
+        assert_eq!(fees.burned(), 150);
+        assert_eq!(bc.fees(0, &settings).map(|fees| fees.burned()), Some(0));
+        assert!(bc.fees(2, &settings).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_maturity() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            coinbase_maturity: 3,
+            ..Settings::regtest()
+        };
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut miner = Client::new_random(&secp, &mut rng);
+        miner.address_version = settings.address_version;
+        let lock = create_lock_with_addr(&pb_key_to_addr_with_version(
+            &miner.pb_key.serialize(),
+            settings.address_version,
+        ));
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        assert_eq!(store.height(), 1);
+
+        let b = mine(&bc, vec![], &lock, &settings)?;
+        let cb_hash = b.transactions[0].hash()?;
+        let reward = b.transactions[0].vout_total();
+        bc.add(&mut store, b);
+
+        // the store remembers where the output comes from
+        let utxo = store.get(&cb_hash, &0).expect("Coinbase output not found!");
+        assert_eq!(utxo.height, 1);
+        assert!(utxo.coinbase);
+
+        let tx = miner
+            .create_transaction(&secp, vec![(cb_hash, 0)], vec![(reward, miner.pb_key)])
+            .expect("Couldn't create the transaction!");
+
+        // too early in the next two blocks
+        for depth in 1..3 {
+            assert_eq!(
+                utils::is_valid_tx(&tx, &store, None, &settings),
+                Err(ValidationError::ImmatureCoinbase {
+                    hash: cb_hash,
+                    index: 0,
+                    depth,
+                    maturity: 3,
+                })
+            );
+            let b = mine(&bc, vec![tx.clone()], &lock, &settings)?;
+            assert!(matches!(
+                bc.valid_next(&b, &store, &bc.next_difficulty(&settings), &settings),
+                Err(ValidationError::InvalidTransaction { index: 1, .. })
+            ));
+
+            let b = mine(&bc, vec![], &lock, &settings)?;
+            bc.add(&mut store, b);
+        }
+
+        // three blocks deep the output can be spent
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_ok());
+        let b = mine(&bc, vec![tx.clone()], &lock, &settings)?;
+        bc.valid_next(&b, &store, &bc.next_difficulty(&settings), &settings)?;
+        bc.add(&mut store, b);
+
+        // outputs of normal transactions aren't coinbase outputs
+        let utxo = store.get(&tx.hash()?, &0).expect("Output not found!");
+        assert_eq!(utxo.height, 4);
+        assert!(!utxo.coinbase);
+
+        // disconnecting the spend makes the coinbase output spendable again
+        bc.disconnect_tip(&mut store);
+        assert_eq!(store.height(), 4);
+        assert!(store.get(&cb_hash, &0).is_some());
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timelocks() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            coinbase_maturity: 1,
+            ..Settings::regtest()
+        };
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut miner = Client::new_random(&secp, &mut rng);
+        miner.address_version = settings.address_version;
+        let address =
+            pb_key_to_addr_with_version(&miner.pb_key.serialize(), settings.address_version);
+        let lock = create_lock_with_addr(&address);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+
+        let spend = |hash: [u8; 32], index: usize| -> Result<Transaction, Box<dyn Error>> {
+            let mut tx = Transaction {
+                version: TX_VERSION,
+                nonce: 0,
+                vin: vec![(hash, index, String::new())],
+                vout: vec![(1, lock.clone())],
+            };
+            tx.vin[0].2 = sign_input(&secp, &miner, &tx, 0, SigHashType::ALL)?;
+            Ok(tx)
+        };
+
+        let b = mine(&bc, vec![], &lock, &settings)?;
+        let cb_hash = b.transactions[0].hash()?;
+        bc.add(&mut store, b);
+
+        // locked until height 5, two blocks after it was mined
+        // and until a time in the future
+        let lock_time = current_time() + 3600;
+        let mut locked = Transaction {
+            version: TX_VERSION,
+            nonce: 0,
+            vin: vec![(cb_hash, 0, String::new())],
+            vout: vec![
+                (10, create_lock_until(&address, 5)),
+                (10, create_lock_after(&address, 2)),
+                (10, create_lock_until(&address, lock_time)),
+            ],
+        };
+        locked.vin[0].2 = sign_input(&secp, &miner, &locked, 0, SigHashType::ALL)?;
+        let locked_hash = locked.hash()?;
+        let b = mine(&bc, vec![locked], &lock, &settings)?;
+        bc.add(&mut store, b);
+        assert_eq!(store.height(), 3);
+
+        let until_height = spend(locked_hash, 0)?;
+        let after_blocks = spend(locked_hash, 1)?;
+        let until_time = spend(locked_hash, 2)?;
+
+        // the output of block 2 is only one block deep in block 3
+        let median_time = bc.median_time_past(&settings);
+        assert_eq!(
+            utils::is_valid_tx(&after_blocks, &store, median_time, &settings),
+            Err(ValidationError::ScriptFailure {
+                input: 0,
+                position: 3,
+                opcode: "check_age".to_string(),
+            })
+        );
+        let b = mine(&bc, vec![until_height.clone()], &lock, &settings)?;
+        assert!(matches!(
+            bc.valid_next(&b, &store, &bc.next_difficulty(&settings), &settings),
+            Err(ValidationError::InvalidTransaction { index: 1, .. })
+        ));
+        let b = mine(&bc, vec![], &lock, &settings)?;
+        bc.add(&mut store, b);
+
+        // block 4 can include the relative timelock, but not the one until height 5
+        let median_time = bc.median_time_past(&settings);
+        assert!(utils::is_valid_tx(&after_blocks, &store, median_time, &settings).is_ok());
+        assert!(utils::is_valid_tx(&until_height, &store, median_time, &settings).is_err());
+        let b = mine(&bc, vec![after_blocks], &lock, &settings)?;
+        bc.valid_next(&b, &store, &bc.next_difficulty(&settings), &settings)?;
+        bc.add(&mut store, b);
+
+        let b = mine(&bc, vec![until_height], &lock, &settings)?;
+        bc.valid_next(&b, &store, &bc.next_difficulty(&settings), &settings)?;
+        bc.add(&mut store, b);
+
+        // time locks depend on the median time past
+        assert!(utils::is_valid_tx(&until_time, &store, None, &settings).is_err());
+        assert!(utils::is_valid_tx(&until_time, &store, Some(lock_time - 1), &settings).is_err());
+        assert!(utils::is_valid_tx(&until_time, &store, Some(lock_time), &settings).is_ok());
+        let b = mine(&bc, vec![until_time], &lock, &settings)?;
+        assert!(bc
+            .valid_next(&b, &store, &bc.next_difficulty(&settings), &settings)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_to_script_hash() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            coinbase_maturity: 1,
+            ..Settings::regtest()
+        };
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut miner = Client::new_random(&secp, &mut rng);
+        miner.address_version = settings.address_version;
+        miner.script_address_version = settings.script_address_version;
+        let lock = create_lock_with_addr(&pb_key_to_addr_with_version(
+            &miner.pb_key.serialize(),
+            settings.address_version,
+        ));
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+
+        let b = mine(&bc, vec![], &lock, &settings)?;
+        let cb_hash = b.transactions[0].hash()?;
+        let reward = b.transactions[0].vout_total();
+        bc.add(&mut store, b);
+
+        // the client pays to script hash addresses with a script hash lock
+        let redeem = create_multisig_lock(1, &[miner.pb_key]).unwrap();
+        let redeem_script = script::Script::assemble(&redeem)?;
+        let address =
+            script_to_addr_with_version(redeem_script.as_bytes(), settings.script_address_version);
+        let tx = miner
+            .create_transaction_addr(&secp, vec![(cb_hash, 0)], vec![(reward, address.clone())])
+            .expect("Couldn't create the transaction!");
+        assert_eq!(tx.vout[0].1, create_script_lock(&address));
+        assert!(utils::is_valid_tx(&tx, &store, None, &settings).is_ok());
+        let tx_hash = tx.hash()?;
+
+        // new coins can be paid to a script hash,
+        // but only with the version of script addresses
+        let diff = bc.next_difficulty(&settings);
+        let key_address =
+            pb_key_to_addr_with_version(&miner.pb_key.serialize(), settings.address_version);
+        for wrong in [
+            create_script_lock(&key_address),
+            create_lock_with_addr(&address),
+        ] {
+            let b = mine(&bc, vec![tx.clone()], &wrong, &settings)?;
+            assert_eq!(
+                bc.valid_next(&b, &store, &diff, &settings),
+                Err(ValidationError::MalformedCoinbaseLock { output: 0 })
+            );
+        }
+
+        let b = mine(&bc, vec![tx], &create_script_lock(&address), &settings)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+        bc.add(&mut store, b);
+
+        // the solution is the one of the script followed by the script
+        let mut spend = Transaction {
+            version: TX_VERSION,
+            nonce: 0,
+            vin: vec![(tx_hash, 0, String::new())],
+            vout: vec![(reward, lock.clone())],
+        };
+        let signature = sign_multisig_input(&secp, &miner, &spend, 0, SigHashType::ALL)?;
+        let solution = combine_multisig_solution(&redeem, &[signature]).unwrap();
+        spend.vin[0].2 = create_script_solution(&solution, &redeem_script);
+        assert!(utils::is_valid_tx(&spend, &store, None, &settings).is_ok());
+
+        // the solution has to end with the script
+        let mut unsolved = spend.clone();
+        unsolved.vin[0].2 = solution;
+        assert!(matches!(
+            utils::is_valid_tx(&unsolved, &store, None, &settings),
+            Err(ValidationError::ScriptFailure { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_genesis_and_checkpoints() -> Result<(), Box<dyn Error>> {
+        // the genesis blocks are fixed
+        for (settings, hash) in [
+            (
+                Settings::mainnet(),
+                "0007f84edf58965d43f5b93c69f99fe2cb49480a1b1330b64dc62244e2f3383a",
+            ),
+            (
+                Settings::testnet(),
+                "00070092af319915fd4f52f523e5ebea6d870c0459e99cf5a7584a82d9075a08",
+            ),
+        ] {
+            assert_eq!(Block::genesis(&settings).hash(None)?.to_hex(), hash);
+
+            let mut store = TxStore::new_empty();
+            let bc = Blockchain::new(&mut store, &settings);
+            assert_eq!(bc.height(), 1);
+            assert!(bc.is_valid(&settings).is_ok());
+        }
+
+        // another first block isn't accepted, even with the right previous hash
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+        let mut b = Block::new(current_time(), [0u8; 32], diff, vec![])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        assert_eq!(
+            Blockchain::new_empty().valid_next(&b, &TxStore::new_empty(), &diff, &settings),
+            Err(ValidationError::WrongGenesis)
+        );
+
+        // two regtest chains which differ at height 2
+        let regtest = Settings::regtest();
+        let diff = regtest.start_difficulty;
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &regtest);
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let miner = Client::new_random(&secp, &mut rng);
+        let lock = create_lock_with_addr(&pb_key_to_addr_with_version(
+            &miner.pb_key.serialize(),
+            regtest.address_version,
+        ));
+        let coinbase = |bc: &Blockchain| {
+            let reward = utils::calculate_mining_reward(bc.height(), &regtest);
+            vec![Transaction::new_coinbase(bc.height(), reward, lock.clone())]
+        };
+        let now = current_time();
+        for timestamp in [now - 2, now - 1] {
+            let b = Block::new(timestamp, bc.at(-1).hash(None)?, diff, coinbase(&bc))?;
+            bc.add(&mut store, b);
+        }
+        let mut fork = bc.clone();
+        let mut fork_store = store.clone();
+        fork.disconnect_tip(&mut fork_store);
+        let other = Block::new(now, fork.at(-1).hash(None)?, diff, coinbase(&fork))?;
+
+        // the checkpoint decides which one is valid
+        let checkpoints: &'static [(usize, [u8; 32])] =
+            Box::leak(vec![(2, bc.at(2).hash(None)?)].into_boxed_slice());
+        let checkpointed = Settings {
+            checkpoints,
+            ..regtest
+        };
+        assert!(bc.is_valid(&checkpointed).is_ok());
+        assert_eq!(
+            fork.valid_next(&other, &fork_store, &diff, &checkpointed),
+            Err(ValidationError::CheckpointMismatch { height: 2 })
+        );
+        assert_eq!(utils::final_height(&checkpointed), 3);
+        assert_eq!(utils::final_height(&regtest), 1);
 
         Ok(())
     }
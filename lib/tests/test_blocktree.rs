@@ -0,0 +1,413 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::blockchain::*;
+    use macc_lib::blocktree::*;
+    use macc_lib::ecdsa::*;
+    use macc_lib::settings::{Retarget, Settings};
+    use macc_lib::utils::*;
+
+    // mines a block with a coinbase transaction on top of `previous`
+    fn mine(
+        previous: [u8; 32],
+        height: usize,
+        transactions: Vec<Transaction>,
+        miner: &Client,
+        settings: &Settings,
+    ) -> Result<Block, Box<dyn Error>> {
+        mine_at(
+            current_time(),
+            previous,
+            height,
+            transactions,
+            miner,
+            settings,
+        )
+    }
+
+    fn mine_at(
+        timestamp: u64,
+        previous: [u8; 32],
+        height: usize,
+        mut transactions: Vec<Transaction>,
+        miner: &Client,
+        settings: &Settings,
+    ) -> Result<Block, Box<dyn Error>> {
+        let reward = utils::calculate_mining_reward(height, settings);
+        let cb_tx = Transaction::new_coinbase(height, reward, create_lock(&miner.pb_key));
+        transactions.insert(0, cb_tx);
+
+        let diff = settings.start_difficulty;
+        let mut b = Block::new(timestamp, previous, diff, transactions)?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+
+        Ok(b)
+    }
+
+    #[test]
+    fn test_heaviest_chain_reorg() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            coinbase_maturity: 1,
+            ..Settings::default()
+        };
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut tree = BlockTree::from_chain(&bc);
+        let genesis_hash = bc.at(0).hash(None)?;
+
+        // common first block after the genesis block
+        let b0 = mine(genesis_hash, 1, vec![], &alice, &settings)?;
+        let b0_hash = b0.hash(None)?;
+        let cb_hash = b0.transactions[0].hash()?;
+        let reorg = tree
+            .insert(b0, &mut bc, &mut store, &settings)?
+            .expect("First block wasn't connected!");
+        assert_eq!(reorg.connected.len(), 1);
+        let store_after_b0 = store.clone();
+
+        // branch a: alice pays bob
+        let reward = utils::calculate_mining_reward(1, &settings);
+        let payment = alice
+            .create_transaction(&secp, vec![(cb_hash, 0)], vec![(reward, bob.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let a1 = mine(b0_hash, 2, vec![payment.clone()], &alice, &settings)?;
+        let a1_hash = a1.hash(None)?;
+        assert!(tree.insert(a1, &mut bc, &mut store, &settings)?.is_some());
+        assert_eq!(bc.at(-1).hash(None)?, a1_hash);
+
+        // branch b: same amount of work, the first seen branch stays active
+        let b1 = mine(b0_hash, 2, vec![], &bob, &settings)?;
+        let b1_hash = b1.hash(None)?;
+        assert!(tree.insert(b1, &mut bc, &mut store, &settings)?.is_none());
+        assert_eq!(bc.at(-1).hash(None)?, a1_hash);
+        // only the side branch keeps its block in the tree
+        assert!(tree.side_block(&b1_hash).is_some());
+        assert!(tree.side_block(&a1_hash).is_none());
+        assert!(tree.side_block(&b0_hash).is_none());
+
+        // branch b gets more work and becomes active
+        let b2 = mine(b1_hash, 3, vec![], &bob, &settings)?;
+        let reorg = tree
+            .insert(b2, &mut bc, &mut store, &settings)?
+            .expect("Heavier branch wasn't connected!");
+
+        assert_eq!(reorg.disconnected.len(), 1);
+        assert_eq!(reorg.connected.len(), 2);
+        assert_eq!(bc.height(), 4);
+        assert_eq!(bc.at(2).hash(None)?, b1_hash);
+        assert!(tree.side_block(&b1_hash).is_none());
+        assert!(tree.side_block(&a1_hash).is_some());
+
+        // the payment was only part of branch a and returns to the mempool
+        let orphaned = reorg.orphaned_transactions();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].hash()?, payment.hash()?);
+        assert!(utils::is_valid_tx(&orphaned[0], &store, None, &settings).is_ok());
+
+        // the store is the one of the new chain
+        let (expected, _) = bc.is_valid(&settings)?;
+        assert_eq!(store, expected);
+        assert_ne!(store, store_after_b0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_branch_is_rejected() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            coinbase_maturity: 1,
+            ..Settings::default()
+        };
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut tree = BlockTree::from_chain(&bc);
+        let genesis_hash = bc.at(0).hash(None)?;
+
+        let b0 = mine(genesis_hash, 1, vec![], &alice, &settings)?;
+        let b0_hash = b0.hash(None)?;
+        let cb_hash = b0.transactions[0].hash()?;
+        tree.insert(b0, &mut bc, &mut store, &settings)?;
+
+        let a1 = mine(b0_hash, 2, vec![], &alice, &settings)?;
+        let a1_hash = a1.hash(None)?;
+        tree.insert(a1, &mut bc, &mut store, &settings)?;
+        let store_before = store.clone();
+
+        // heavier branch which spends more than it has
+        let reward = utils::calculate_mining_reward(1, &settings);
+        let overspend = alice
+            .create_transaction(&secp, vec![(cb_hash, 0)], vec![(reward + 1, alice.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let bob = Client::new_random(&secp, &mut rng);
+        let b1 = mine(b0_hash, 2, vec![], &bob, &settings)?;
+        let b1_hash = b1.hash(None)?;
+        let b2 = mine(b1_hash, 3, vec![overspend], &bob, &settings)?;
+        let b2_hash = b2.hash(None)?;
+
+        tree.insert(b1, &mut bc, &mut store, &settings)?;
+        assert!(matches!(
+            tree.insert(b2, &mut bc, &mut store, &settings),
+            Err(ValidationError::InvalidTransaction { .. })
+        ));
+
+        // the old chain is still active and the invalid block is forgotten
+        assert_eq!(bc.height(), 3);
+        assert_eq!(bc.at(-1).hash(None)?, a1_hash);
+        assert_eq!(store, store_before);
+        assert!(tree.side_block(&b1_hash).is_some());
+        assert!(tree.side_block(&a1_hash).is_none());
+        assert!(!tree.contains(&b2_hash));
+
+        // blocks with an unknown previous block can't be placed
+        let orphan = mine([1u8; 32], 5, vec![], &alice, &settings)?;
+        assert_eq!(
+            tree.insert(orphan, &mut bc, &mut store, &settings)
+                .map(|_| ()),
+            Err(ValidationError::OrphanBlock)
+        );
+
+        // once the chain passed a checkpoint, no branch can fork off below it
+        let checkpoints: &'static [(usize, [u8; 32])] =
+            Box::leak(vec![(1, b0_hash)].into_boxed_slice());
+        let checkpointed = Settings {
+            checkpoints,
+            ..settings
+        };
+        let fork = mine(genesis_hash, 1, vec![], &bob, &settings)?;
+        assert_eq!(
+            tree.insert(fork, &mut bc, &mut store, &checkpointed)
+                .map(|_| ()),
+            Err(ValidationError::BelowCheckpoint {
+                height: 1,
+                checkpoint: 1
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_future_block_is_kept() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut tree = BlockTree::from_chain(&bc);
+        let genesis_hash = bc.at(0).hash(None)?;
+
+        let future = current_time() + 10 * settings.max_future_drift;
+        let b0 = mine_at(future, genesis_hash, 1, vec![], &alice, &settings)?;
+        let b0_hash = b0.hash(None)?;
+        let b1 = mine_at(future, b0_hash, 2, vec![], &alice, &settings)?;
+        let b1_hash = b1.hash(None)?;
+        // spends an output which doesn't exist
+        let missing = alice
+            .create_transaction(&secp, vec![([9; 32], 0)], vec![(1, alice.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let b2 = mine_at(future, b1_hash, 3, vec![missing], &alice, &settings)?;
+        let b2_hash = b2.hash(None)?;
+
+        for block in [b0.clone(), b1, b2] {
+            assert!(matches!(
+                tree.insert(block, &mut bc, &mut store, &settings),
+                Err(ValidationError::FutureTimestamp { .. })
+            ));
+        }
+
+        // the blocks aren't connected, but kept for later
+        assert_eq!(bc.height(), 1);
+        assert!(tree.contains(&b0_hash));
+        assert!(tree.contains(&b1_hash));
+        assert!(tree.contains(&b2_hash));
+
+        // once the time has come, inserting the block again connects the branch
+        // the invalid last block is dropped, but the rest has more work than the chain
+        let later = Settings {
+            max_future_drift: 20 * settings.max_future_drift,
+            ..settings
+        };
+        let reorg = tree
+            .insert(b0, &mut bc, &mut store, &later)?
+            .expect("Branch wasn't connected!");
+        assert_eq!(reorg.connected.len(), 2);
+        assert_eq!(bc.at(-1).hash(None)?, b1_hash);
+        assert!(!tree.contains(&b2_hash));
+        let (expected, _) = bc.is_valid(&later)?;
+        assert_eq!(store, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_branches() -> Result<(), Box<dyn Error>> {
+        let settings = Settings {
+            max_fork_depth: 2,
+            ..Settings::default()
+        };
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut tree = BlockTree::from_chain(&bc);
+        let genesis_hash = bc.at(0).hash(None)?;
+
+        // the active chain and a side branch of bob
+        let mut previous = genesis_hash;
+        for height in 1..3 {
+            let b = mine(previous, height, vec![], &alice, &settings)?;
+            previous = b.hash(None)?;
+            tree.insert(b, &mut bc, &mut store, &settings)?;
+        }
+        let side = mine(genesis_hash, 1, vec![], &bob, &settings)?;
+        let side_hash = side.hash(None)?;
+        let side_child = mine(side_hash, 2, vec![], &bob, &settings)?;
+        let side_child_hash = side_child.hash(None)?;
+        assert!(tree.insert(side, &mut bc, &mut store, &settings)?.is_none());
+        assert!(tree
+            .insert(side_child, &mut bc, &mut store, &settings)?
+            .is_none());
+        assert_eq!(tree.len(), 5);
+
+        // once the chain grew, the side branch can't become active anymore
+        // and is forgotten
+        let b3 = mine(previous, 3, vec![], &alice, &settings)?;
+        tree.insert(b3, &mut bc, &mut store, &settings)?;
+        assert!(!tree.contains(&side_hash));
+        assert!(!tree.contains(&side_child_hash));
+        assert_eq!(tree.len(), 4);
+
+        // and new branches can't fork off that deep
+        let deep = mine(genesis_hash, 1, vec![], &bob, &settings)?;
+        assert_eq!(
+            tree.insert(deep, &mut bc, &mut store, &settings)
+                .map(|_| ()),
+            Err(ValidationError::ForkTooDeep {
+                height: 1,
+                min_height: 2
+            })
+        );
+
+        // blocks easier than the difficulty of their branch aren't stored
+        let easy = Settings {
+            start_difficulty: difficulty::create(0)?,
+            ..settings
+        };
+        let cheap = mine(previous, 3, vec![], &bob, &easy)?;
+        let cheap_hash = cheap.hash(None)?;
+        assert_eq!(
+            tree.insert(cheap, &mut bc, &mut store, &settings)
+                .map(|_| ()),
+            Err(ValidationError::WrongTarget)
+        );
+        assert!(!tree.contains(&cheap_hash));
+
+        // only a limited number of blocks is kept at the same height
+        let limited = Settings {
+            max_blocks_per_height: 2,
+            ..settings
+        };
+        let side = mine(previous, 3, vec![], &bob, &settings)?;
+        assert!(tree.insert(side, &mut bc, &mut store, &limited)?.is_none());
+        let carol = Client::new_random(&secp, &mut rng);
+        let full = mine(previous, 3, vec![], &carol, &settings)?;
+        assert_eq!(
+            tree.insert(full, &mut bc, &mut store, &limited).map(|_| ()),
+            Err(ValidationError::TooManyBlocks {
+                height: 3,
+                limit: 2
+            })
+        );
+        assert_eq!(tree.len(), 5);
+
+        // neither are blocks without a valid compact target
+        let mut negative = mine(bc.at(-1).hash(None)?, 4, vec![], &bob, &settings)?;
+        negative.header.bits |= 0x0080_0000;
+        assert_eq!(
+            tree.insert(negative, &mut bc, &mut store, &settings)
+                .map(|_| ()),
+            Err(ValidationError::WrongTarget)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_side_branch_difficulty() -> Result<(), Box<dyn Error>> {
+        // the difficulty changes every block
+        let mut settings = Settings {
+            adjustment_interval: 2,
+            retarget: Retarget::Lwma,
+            min_difficulty: difficulty::create(0)?,
+            genesis_timestamp: current_time() - 1000,
+            ..Settings::default()
+        };
+        settings.genesis_nonce =
+            find_nonce(&Block::genesis(&settings), &settings.start_difficulty)?;
+        let start = settings.genesis_timestamp;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        // mines on top of the chain with its next difficulty
+        let mine_on = |chain: &Blockchain, timestamp: u64, miner: &Client| {
+            let height = chain.height();
+            let reward = utils::calculate_mining_reward(height, &settings);
+            let cb_tx = Transaction::new_coinbase(height, reward, create_lock(&miner.pb_key));
+            let diff = chain.next_difficulty(&settings);
+            let mut b = Block::new(timestamp, chain.at(-1).hash(None)?, diff, vec![cb_tx])?;
+            b.header.nonce = find_nonce(&b, &diff)?;
+            Ok::<Block, Box<dyn Error>>(b)
+        };
+
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut tree = BlockTree::from_chain(&bc);
+        let mut side_store = TxStore::new_empty();
+        let mut side_bc = Blockchain::new(&mut side_store, &settings);
+
+        // slow blocks of alice are active
+        for i in 1..=3 {
+            let b = mine_on(&bc, start + 100 * i, &alice)?;
+            assert!(tree.insert(b, &mut bc, &mut store, &settings)?.is_some());
+        }
+
+        // the fast blocks of bob get harder, the tree calculates
+        // the same difficulties for them as the chain of the branch
+        let mut last = [0; 32];
+        for i in 1..=4 {
+            let b = mine_on(&side_bc, start + i, &bob)?;
+            last = b.hash(None)?;
+            side_bc.add(&mut side_store, b.clone());
+            tree.insert(b, &mut bc, &mut store, &settings)?;
+        }
+        assert_ne!(side_bc.difficulty(&settings), settings.start_difficulty);
+        assert_eq!(bc.at(-1).hash(None)?, last);
+        assert_eq!(store, side_store);
+
+        Ok(())
+    }
+}
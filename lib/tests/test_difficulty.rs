@@ -0,0 +1,112 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use macc_lib::blockchain::difficulty;
+    use macc_lib::settings::{Retarget, Settings};
+    use macc_lib::target::Target;
+
+    #[test]
+    fn test_windowed_retarget() {
+        let settings = Settings::default();
+        let expected = settings.target_time * settings.adjustment_interval as u64;
+        let current = difficulty::create(16).expect("UNREACHABLE");
+        let current_bi = current.to_biguint();
+
+        // blocks at the target time keep the difficulty
+        assert_eq!(difficulty::adjusted(&current, expected, &settings), current);
+
+        // twice as slow halves the difficulty, twice as fast doubles it
+        assert_eq!(
+            difficulty::adjusted(&current, 2 * expected, &settings).to_biguint(),
+            &current_bi * 2u8
+        );
+        assert_eq!(
+            difficulty::adjusted(&current, expected / 2, &settings).to_biguint(),
+            &current_bi / 2u8
+        );
+
+        // the change per interval is limited, also for an interval of 0
+        assert_eq!(
+            difficulty::adjusted(&current, 0, &settings).to_biguint(),
+            &current_bi / settings.max_adjustment
+        );
+        assert_eq!(
+            difficulty::adjusted(&current, 1000 * expected, &settings).to_biguint(),
+            &current_bi * settings.max_adjustment
+        );
+
+        // other factors are rounded to the compact encoding of the header
+        let next = difficulty::adjusted(&current, expected * 4 / 3, &settings);
+        assert_eq!(Target::from_compact(next.to_compact()), Some(next));
+        assert!(next.to_biguint() <= &current_bi * 4u8 / 3u8);
+        assert!(next > current);
+
+        // it never gets easier than the minimum difficulty
+        let floor = Settings {
+            min_difficulty: Target::from_biguint(&(&current_bi * 3u8)).expect("UNREACHABLE"),
+            ..settings
+        };
+        assert_eq!(
+            difficulty::adjusted(&current, 1000 * expected, &floor),
+            floor.min_difficulty
+        );
+
+        // only adjusted every interval
+        let interval = settings.adjustment_interval as usize;
+        let timestamp_at = |i: usize| i as u64;
+        let target_at = |_: usize| current;
+        assert_eq!(
+            difficulty::next(current, interval + 1, timestamp_at, target_at, &settings),
+            current
+        );
+        assert_ne!(
+            difficulty::next(current, interval, timestamp_at, target_at, &settings),
+            current
+        );
+    }
+
+    #[test]
+    fn test_lwma_retarget() {
+        let settings = Settings {
+            retarget: Retarget::Lwma,
+            ..Settings::default()
+        };
+        let current = difficulty::create(16).expect("UNREACHABLE");
+        let target_at = |_: usize| current;
+        let height = settings.adjustment_interval as usize + 5;
+
+        // blocks at the target time keep the difficulty
+        let on_time = |i: usize| i as u64 * settings.target_time;
+        assert_eq!(
+            difficulty::next(current, height, on_time, target_at, &settings),
+            current
+        );
+
+        // the difficulty is adjusted every block
+        let fast = |i: usize| i as u64 * settings.target_time / 2;
+        for height in height..height + 3 {
+            assert_eq!(
+                difficulty::next(current, height, fast, target_at, &settings).to_biguint(),
+                current.to_biguint() / 2u8
+            );
+        }
+
+        // a single timestamp far in the future has a limited effect
+        let warped = |i: usize| {
+            if i == height - 1 {
+                u64::MAX / 2
+            } else {
+                on_time(i)
+            }
+        };
+        let next = difficulty::next(current, height, warped, target_at, &settings);
+        assert!(next.to_biguint() < current.to_biguint() * 2u8);
+
+        // not enough blocks yet
+        assert_eq!(
+            difficulty::next(current, 3, fast, target_at, &settings),
+            current
+        );
+    }
+}
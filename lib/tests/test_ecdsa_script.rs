@@ -4,7 +4,13 @@ extern crate macc_lib;
 mod tests {
     use std::error::Error;
 
-    use macc_lib::{ecdsa::*, hex::ToHex, script};
+    use macc_lib::{
+        blockchain::{Transaction, TX_VERSION},
+        ecdsa::*,
+        script,
+        settings::Settings,
+        sighash::SigHashType,
+    };
 
     #[test]
     fn test_ecdsa_with_script() -> Result<(), Box<dyn Error>> {
@@ -25,18 +31,179 @@ mod tests {
         // test script
         let lock = create_lock(&client.pb_key);
 
-        let solution = create_solution(&secp, &client, &message);
+        let mut tx = Transaction {
+            version: TX_VERSION,
+            nonce: 1,
+            vin: vec![([1; 32], 0, String::new())],
+            vout: vec![(10, lock.clone())],
+        };
 
-        let stack = script::eval(format!(
-            "{} {} {}",
-            solution,
-            message.as_ref().to_hex(),
-            lock
-        ));
+        let solution = sign_input(&secp, &client, &tx, 0, SigHashType::ALL)?;
+        tx.vin[0].2 = solution.clone();
 
-        let valid = stack.is_some();
+        let context = script::Context::new(&tx, 0, &Settings::default());
+        assert!(script::verify_text(&solution, &lock, &context).is_ok());
 
-        assert!(valid);
+        // the signature commits to the outputs
+        let mut changed = tx.clone();
+        changed.vout[0].0 = 11;
+        let context = script::Context::new(&changed, 0, &Settings::default());
+        let error = script::verify_text(&solution, &lock, &context).unwrap_err();
+        assert_eq!(error.position, 2);
+        assert_eq!(error.opcode, "verify_sig");
+
+        // another key doesn't unlock the coins
+        let other = Client::new_random(&secp, &mut rng);
+        let other_solution = sign_input(&secp, &other, &tx, 0, SigHashType::ALL)?;
+        let context = script::Context::new(&tx, 0, &Settings::default());
+        let error = script::verify_text(&other_solution, &lock, &context).unwrap_err();
+        assert_eq!(error.position, 5);
+        assert_eq!(error.opcode, "eq");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multisig() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+
+        let clients = (0..3)
+            .map(|_| Client::new_random(&secp, &mut rng))
+            .collect::<Vec<Client>>();
+        let pb_keys = clients.iter().map(|c| c.pb_key).collect::<Vec<_>>();
+
+        let lock = create_multisig_lock(2, &pb_keys).unwrap();
+        assert_eq!(multisig_lock_keys(&lock), Some((2, pb_keys.clone())));
+        assert_eq!(script::Script::assemble(&lock)?.disassemble()?, lock);
+        // numbers that can't be satisfied
+        assert!(create_multisig_lock(0, &pb_keys).is_none());
+        assert!(create_multisig_lock(4, &pb_keys).is_none());
+
+        let tx = Transaction {
+            version: TX_VERSION,
+            nonce: 1,
+            vin: vec![([1; 32], 0, String::new())],
+            vout: vec![(10, create_lock(&clients[0].pb_key))],
+        };
+        let context = script::Context::new(&tx, 0, &Settings::default());
+
+        let partials = clients
+            .iter()
+            .map(|client| sign_multisig_input(&secp, client, &tx, 0, SigHashType::ALL))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // any 2 of the 3 keys, the signers can come in any order
+        for signers in [[0, 1], [0, 2], [2, 1]] {
+            let signed = signers.map(|i| partials[i].clone());
+            let solution = combine_multisig_solution(&lock, &signed).unwrap();
+            assert!(script::verify_text(&solution, &lock, &context).is_ok());
+        }
+        // only the first 2 signatures are used
+        let solution = combine_multisig_solution(&lock, &partials).unwrap();
+        assert_eq!(solution, format!("{} {}", partials[0].1, partials[1].1));
+        assert!(script::verify_text(&solution, &lock, &context).is_ok());
+
+        // insufficient signatures
+        assert!(combine_multisig_solution(&lock, &partials[..1]).is_none());
+        let error = script::verify_text(&partials[0].1, &lock, &context).unwrap_err();
+        assert_eq!(error.opcode, "check_multisig");
+        assert_eq!(error.position, 1 + 5);
+
+        // duplicated signatures of the same key
+        let solution = format!("{} {}", partials[0].1, partials[0].1);
+        let error = script::verify_text(&solution, &lock, &context).unwrap_err();
+        assert_eq!(error.opcode, "check_multisig");
+
+        // signatures in the wrong order
+        let solution = format!("{} {}", partials[1].1, partials[0].1);
+        assert!(script::verify_text(&solution, &lock, &context).is_err());
+
+        // a signature of another transaction
+        let mut other_tx = tx.clone();
+        other_tx.nonce = 2;
+        let (_, other_sig) =
+            sign_multisig_input(&secp, &clients[1], &other_tx, 0, SigHashType::ALL)?;
+        let solution = format!("{} {}", partials[0].1, other_sig);
+        assert!(script::verify_text(&solution, &lock, &context).is_err());
+
+        // a key that isn't part of the lock
+        let stranger = Client::new_random(&secp, &mut rng);
+        let stranger = sign_multisig_input(&secp, &stranger, &tx, 0, SigHashType::ALL)?;
+        let solution = format!("{} {}", partials[0].1, stranger.1);
+        assert!(script::verify_text(&solution, &lock, &context).is_err());
+        assert!(combine_multisig_solution(&lock, &[partials[0].clone(), stranger]).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_to_script_hash() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+
+        let clients = (0..3)
+            .map(|_| Client::new_random(&secp, &mut rng))
+            .collect::<Vec<Client>>();
+        let pb_keys = clients.iter().map(|c| c.pb_key).collect::<Vec<_>>();
+
+        // 2-of-3 multisig behind a script hash
+        let redeem = create_multisig_lock(2, &pb_keys).unwrap();
+        let redeem_script = script::Script::assemble(&redeem)?;
+        let address = script_to_addr(redeem_script.as_bytes());
+
+        // script hash addresses have their own version
+        assert!(address.starts_with('3'));
+        assert!(is_valid_addr(&address, MAINNET_SCRIPT_ADDRESS_VERSION));
+        assert!(!is_valid_addr(&address, MAINNET_ADDRESS_VERSION));
+        let testnet = Settings::testnet();
+        let testnet_address =
+            script_to_addr_with_version(redeem_script.as_bytes(), testnet.script_address_version);
+        assert!(testnet_address.starts_with('2'));
+
+        // the lock only holds the address
+        let lock = create_script_lock(&address);
+        assert!(lock.len() < redeem.len());
+        assert_eq!(lock_to_script_addr(&lock), Some(address.as_str()));
+        assert_eq!(lock_to_addr(&lock), None);
+        assert!(script::Script::assemble(&lock)?.is_script_hash());
+        assert!(!script::Script::assemble(&create_lock(&pb_keys[0]))?.is_script_hash());
+
+        let tx = Transaction {
+            version: TX_VERSION,
+            nonce: 1,
+            vin: vec![([1; 32], 0, String::new())],
+            vout: vec![(10, create_lock(&pb_keys[0]))],
+        };
+        let context = script::Context::new(&tx, 0, &Settings::default());
+
+        let partials = clients
+            .iter()
+            .map(|client| sign_multisig_input(&secp, client, &tx, 0, SigHashType::ALL))
+            .collect::<Result<Vec<_>, _>>()?;
+        let signatures = combine_multisig_solution(&redeem, &partials[1..]).unwrap();
+
+        // the solution supplies the script which is run after the lock
+        let solution = create_script_solution(&signatures, &redeem_script);
+        assert!(script::verify_text(&solution, &lock, &context).is_ok());
+
+        // the script still has to be satisfied, its instructions come after the lock
+        let solution = create_script_solution(&partials[0].1, &redeem_script);
+        let error = script::verify_text(&solution, &lock, &context).unwrap_err();
+        assert_eq!(error.opcode, "check_multisig");
+        assert_eq!(error.position, 2 + 3 + 5);
+
+        // another script doesn't match the address
+        let other_script = script::Script::assemble(&create_multisig_lock(1, &pb_keys).unwrap())?;
+        let solution = create_script_solution(&partials[0].1, &other_script);
+        let error = script::verify_text(&solution, &lock, &context).unwrap_err();
+        assert_eq!(error.opcode, "eq");
+        assert_eq!(error.position, 2 + 2);
+
+        // addresses of one network can't be spent on another
+        let solution = create_script_solution(&signatures, &redeem_script);
+        let context = script::Context::new(&tx, 0, &testnet);
+        assert!(script::verify_text(&solution, &lock, &context).is_err());
 
         Ok(())
     }
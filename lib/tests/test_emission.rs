@@ -0,0 +1,112 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use macc_lib::emission::*;
+    use macc_lib::settings::Settings;
+
+    // sums up the rewards one block at a time
+    fn sum_rewards(height: usize, settings: &Settings) -> u128 {
+        (0..height).map(|h| block_reward(h, settings)).sum()
+    }
+
+    #[test]
+    fn test_halvings() {
+        let settings = Settings {
+            start_mining_reward: 1000,
+            halvings_interval: 10,
+            ..Settings::default()
+        };
+
+        // the genesis block doesn't create coins
+        assert_eq!(block_reward(0, &settings), 0);
+        assert_eq!(block_reward(1, &settings), 1000);
+        assert_eq!(block_reward(9, &settings), 1000);
+        assert_eq!(block_reward(10, &settings), 500);
+        assert_eq!(block_reward(95, &settings), 1);
+        assert_eq!(block_reward(100, &settings), 0);
+
+        // saturates at zero instead of overflowing
+        assert_eq!(block_reward(10 * 32, &settings), 0);
+        assert_eq!(block_reward(10 * 200, &settings), 0);
+        assert_eq!(block_reward(usize::MAX, &settings), 0);
+
+        let mainnet = Settings::default();
+        assert_eq!(
+            block_reward(mainnet.halvings_interval * 127, &mainnet),
+            mainnet.start_mining_reward >> 127
+        );
+        assert_eq!(block_reward(mainnet.halvings_interval * 128, &mainnet), 0);
+
+        // an interval of 0 never halves instead of dividing by zero
+        let constant = Settings {
+            halvings_interval: 0,
+            ..settings
+        };
+        assert_eq!(block_reward(1, &constant), 1000);
+        assert_eq!(block_reward(usize::MAX, &constant), 1000);
+        assert_eq!(supply(11, &constant), 10_000);
+        assert_eq!(supply(11, &constant), sum_rewards(11, &constant));
+        assert_eq!(max_supply(&constant), None);
+        let nothing = Settings {
+            start_mining_reward: 0,
+            ..constant
+        };
+        assert_eq!(max_supply(&nothing), Some(0));
+    }
+
+    #[test]
+    fn test_supply() {
+        let settings = Settings {
+            start_mining_reward: 1000,
+            halvings_interval: 10,
+            ..Settings::default()
+        };
+
+        assert_eq!(supply(0, &settings), 0);
+        assert_eq!(supply(1, &settings), 0);
+        assert_eq!(supply(11, &settings), 9500);
+        for height in 0..200 {
+            assert_eq!(supply(height, &settings), sum_rewards(height, &settings));
+        }
+
+        assert_eq!(max_supply(&settings), Some(18940));
+        assert_eq!(supply(usize::MAX, &settings), 18940);
+
+        // the mainnet supply stays below twice the coins of the first era
+        let mainnet = Settings::default();
+        let first_era = mainnet.start_mining_reward * mainnet.halvings_interval as u128;
+        let max = max_supply(&mainnet).expect("Mainnet has no tail emission!");
+        assert!(max > first_era && max < 2 * first_era);
+    }
+
+    #[test]
+    fn test_tail_emission() {
+        let settings = Settings {
+            start_mining_reward: 1000,
+            halvings_interval: 10,
+            tail_emission: Some(100),
+            ..Settings::default()
+        };
+
+        assert_eq!(block_reward(25, &settings), 250);
+        assert_eq!(block_reward(35, &settings), 125);
+        assert_eq!(block_reward(45, &settings), 100);
+        assert_eq!(block_reward(usize::MAX, &settings), 100);
+
+        for height in 0..200 {
+            assert_eq!(supply(height, &settings), sum_rewards(height, &settings));
+        }
+
+        // the supply keeps growing
+        assert_eq!(max_supply(&settings), None);
+        assert_eq!(
+            supply(10_000, &settings),
+            supply(1_000, &settings) + 9_000 * 100
+        );
+        assert_eq!(
+            supply(usize::MAX, &settings),
+            usize::MAX as u128 * 100 + 13_750
+        );
+    }
+}
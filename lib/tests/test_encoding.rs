@@ -0,0 +1,139 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::blockchain::*;
+    use macc_lib::encoding::{self, DecodeError};
+    use macc_lib::hex::ToHex;
+    use macc_lib::sighash::{self, SigHashType};
+
+    // the vectors of docs/encoding.md
+    fn vector_tx() -> Transaction {
+        Transaction {
+            version: 1,
+            nonce: 7,
+            vin: vec![([0x11; 32], 1, "ab".to_string())],
+            vout: vec![(1000, "cd".to_string())],
+        }
+    }
+
+    fn vector_header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            timestamp: 1_792_281_600,
+            previous: [0x22; 32],
+            merkle_root: [0x33; 32],
+            bits: 0x1d00ffff,
+            nonce: 5,
+        }
+    }
+
+    #[test]
+    fn test_primitives() {
+        assert_eq!(encoding::encode(&1_u32).to_hex(), "01000000");
+        assert_eq!(encoding::encode(&258_usize).to_hex(), "0201000000000000");
+        assert_eq!(encoding::encode(&true).to_hex(), "01");
+        assert_eq!(encoding::encode("ab").to_hex(), "02000000000000006162");
+        assert_eq!(
+            encoding::encode(&vec![(1_u8, 2_u32)]).to_hex(),
+            "01000000000000000102000000"
+        );
+
+        assert_eq!(encoding::decode::<u32>(&[1, 0, 0, 0]), Ok(1));
+        assert_eq!(
+            encoding::decode::<u32>(&[1, 0, 0]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            encoding::decode::<u32>(&[1, 0, 0, 0, 0]),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        assert_eq!(
+            encoding::decode::<bool>(&[2]),
+            Err(DecodeError::InvalidBool(2))
+        );
+        assert_eq!(
+            encoding::decode::<String>(&[1, 0, 0, 0, 0, 0, 0, 0, 0xff]),
+            Err(DecodeError::InvalidUtf8)
+        );
+        // a huge length doesn't allocate anything
+        assert_eq!(
+            encoding::decode::<Vec<u8>>(&[0xff; 8]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_vectors() -> Result<(), Box<dyn Error>> {
+        let tx = vector_tx();
+        let expected = [
+            "01000000",                         // version
+            "07000000000000000000000000000000", // nonce
+            "0100000000000000",                 // 1 input
+            &"11".repeat(32),                   // hash
+            "0100000000000000",                 // index
+            "0200000000000000",                 // solution length
+            "6162",                             // solution "ab"
+            "0100000000000000",                 // 1 output
+            "e8030000000000000000000000000000", // value
+            "0200000000000000",                 // lock length
+            "6364",                             // lock "cd"
+        ]
+        .concat();
+        assert_eq!(encoding::encode(&tx).to_hex(), expected);
+        assert_eq!(tx.size()?, expected.len() / 2);
+        assert_eq!(
+            tx.hash()?.to_hex(),
+            "178da3790735865e8fe4d2ba1bf5b35c94b88b6cabf32aedfacd4ac5f057bf16"
+        );
+        assert_eq!(
+            sighash::sighash(&tx, 0, SigHashType::ALL)?.to_hex(),
+            "b83b95b8fe67b875c3d2a5c0a8821476d79813398abd9ae36092f11304c9b0fb"
+        );
+
+        let header = vector_header();
+        let expected = [
+            "01000000",                         // version
+            "000cd46a00000000",                 // timestamp
+            &"22".repeat(32),                   // previous
+            &"33".repeat(32),                   // merkle root
+            "ffff001d",                         // bits
+            "05000000000000000000000000000000", // nonce
+        ]
+        .concat();
+        assert_eq!(encoding::encode(&header).to_hex(), expected);
+        assert_eq!(
+            header.hash(None)?.to_hex(),
+            "49b6480a7ee9e3e37bb7f0ad26de5498a97b9e9cd16f2ab9bd2137e7f03eaf17"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<(), Box<dyn Error>> {
+        let block = Block {
+            header: vector_header(),
+            transactions: vec![
+                vector_tx(),
+                Transaction::new_coinbase(3, 50, "ef".to_string()),
+            ],
+        };
+
+        let bytes = encoding::encode(&block);
+        assert_eq!(block.size()?, bytes.len());
+
+        let decoded: Block = encoding::decode(&bytes)?;
+        assert_eq!(decoded.header, block.header);
+        assert_eq!(decoded.tx_hashes()?, block.tx_hashes()?);
+
+        // every truncation is detected
+        for len in 0..bytes.len() {
+            assert!(encoding::decode::<Block>(&bytes[..len]).is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::blockchain::*;
+    use macc_lib::ecdsa::*;
+    use macc_lib::htlc;
+    use macc_lib::settings::Settings;
+    use macc_lib::sighash::SigHashType;
+    use macc_lib::utils::*;
+
+    struct Chain {
+        settings: Settings,
+        bc: Blockchain,
+        store: TxStore,
+    }
+
+    impl Chain {
+        fn new(settings: Settings) -> Self {
+            let settings = Settings {
+                coinbase_maturity: 1,
+                ..settings
+            };
+            let mut store = TxStore::new_empty();
+            let bc = Blockchain::new(&mut store, &settings);
+
+            Self {
+                settings,
+                bc,
+                store,
+            }
+        }
+
+        fn address(&self, client: &Client) -> String {
+            pb_key_to_addr_with_version(&client.pb_key.serialize(), self.settings.address_version)
+        }
+
+        fn lock(&self, client: &Client) -> String {
+            create_lock_with_addr(&self.address(client))
+        }
+
+        // mines a block with the transactions and returns the hash of the coinbase
+        fn mine(
+            &mut self,
+            mut transactions: Vec<Transaction>,
+            miner: &Client,
+        ) -> Result<[u8; 32], Box<dyn Error>> {
+            let height = self.bc.height();
+            let reward = utils::calculate_mining_reward(height, &self.settings);
+            let cb_tx = Transaction::new_coinbase(height, reward, self.lock(miner));
+            let cb_hash = cb_tx.hash()?;
+            transactions.insert(0, cb_tx);
+
+            let diff = self.bc.next_difficulty(&self.settings);
+            let mut b = Block::new(
+                current_time(),
+                self.bc.at(-1).hash(None)?,
+                diff,
+                transactions,
+            )?;
+            b.header.nonce = find_nonce(&b, &diff)?;
+            self.bc.valid_next(&b, &self.store, &diff, &self.settings)?;
+            self.bc.add(&mut self.store, b);
+
+            Ok(cb_hash)
+        }
+
+        fn is_valid(&self, tx: &Transaction) -> Result<(), ValidationError> {
+            let median_time = self.bc.median_time_past(&self.settings);
+            utils::is_valid_tx(tx, &self.store, median_time, &self.settings)
+        }
+    }
+
+    // transaction sending the whole output to `lock`, the solution is added later
+    fn send(hash: [u8; 32], value: u128, lock: String) -> Transaction {
+        Transaction {
+            version: TX_VERSION,
+            nonce: 0,
+            vin: vec![(hash, 0, String::new())],
+            vout: vec![(value, lock)],
+        }
+    }
+
+    #[test]
+    fn test_atomic_swap() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        // alice has coins on regtest and bob on testnet
+        let mut regtest = Chain::new(Settings::regtest());
+        let mut testnet = Chain::new(Settings::testnet());
+        let alice_coins = regtest.mine(vec![], &alice)?;
+        let bob_coins = testnet.mine(vec![], &bob)?;
+        let alice_value = regtest.store.get(&alice_coins, &0).unwrap().value;
+        let bob_value = testnet.store.get(&bob_coins, &0).unwrap().value;
+
+        // only alice knows the secret
+        let secret = [42; htlc::SECRET_LEN].to_vec();
+        let hash = htlc::hash_secret(&secret);
+
+        // alice locks her coins for bob, she can take them back after height 12
+        let lock_a = htlc::create_lock(&hash, &regtest.address(&bob), &regtest.address(&alice), 12);
+        let mut fund_a = send(alice_coins, alice_value, lock_a);
+        fund_a.vin[0].2 = sign_input(&secp, &alice, &fund_a, 0, SigHashType::ALL)?;
+        regtest.mine(vec![fund_a.clone()], &alice)?;
+
+        // bob locks his coins with the same hash and an earlier lock time,
+        // so alice has to reveal the secret while bob still can claim
+        let lock_b = htlc::create_lock(&hash, &testnet.address(&alice), &testnet.address(&bob), 8);
+        let mut fund_b = send(bob_coins, bob_value, lock_b);
+        fund_b.vin[0].2 = sign_input(&secp, &bob, &fund_b, 0, SigHashType::ALL)?;
+        testnet.mine(vec![fund_b.clone()], &bob)?;
+
+        // bob can't take his coins back yet
+        let mut refund_b = send(fund_b.hash()?, bob_value, testnet.lock(&bob));
+        refund_b.vin[0].2 =
+            htlc::create_refund_solution(&secp, &bob, &refund_b, 0, SigHashType::ALL)?;
+        assert_eq!(
+            testnet.is_valid(&refund_b),
+            Err(ValidationError::ScriptFailure {
+                input: 0,
+                position: 16,
+                opcode: "check_lock_time".to_string(),
+            })
+        );
+
+        // alice needs the right secret to claim the coins of bob
+        let mut claim_b = send(fund_b.hash()?, bob_value, testnet.lock(&alice));
+        let guessed = [7; htlc::SECRET_LEN];
+        claim_b.vin[0].2 =
+            htlc::create_claim_solution(&secp, &alice, &claim_b, 0, &guessed, SigHashType::ALL)?;
+        assert_eq!(
+            testnet.is_valid(&claim_b),
+            Err(ValidationError::ScriptFailure {
+                input: 0,
+                position: 10,
+                opcode: "eq".to_string(),
+            })
+        );
+
+        // and the secret needs the right size
+        assert!(htlc::create_claim_solution(
+            &secp,
+            &alice,
+            &claim_b,
+            0,
+            b"too short",
+            SigHashType::ALL
+        )
+        .is_err());
+        let signature = sign_input(&secp, &alice, &claim_b, 0, SigHashType::ALL)?;
+        claim_b.vin[0].2 = format!("{} {} 01", signature, "00".repeat(htlc::SECRET_LEN + 1));
+        assert_eq!(
+            testnet.is_valid(&claim_b),
+            Err(ValidationError::ScriptFailure {
+                input: 0,
+                position: 7,
+                opcode: "eq".to_string(),
+            })
+        );
+        claim_b.vin[0].2 =
+            htlc::create_claim_solution(&secp, &alice, &claim_b, 0, &secret, SigHashType::ALL)?;
+        testnet.mine(vec![claim_b], &bob)?;
+
+        // bob learns the secret from the claim in the testnet block
+        let claim = &testnet.bc.at(-1).transactions[1];
+        let revealed = htlc::secret_from_solution(&claim.vin[0].2).unwrap();
+        assert_eq!(revealed, secret);
+
+        // and claims the coins of alice with it
+        let mut claim_a = send(fund_a.hash()?, alice_value, regtest.lock(&bob));
+        claim_a.vin[0].2 =
+            htlc::create_claim_solution(&secp, &bob, &claim_a, 0, &revealed, SigHashType::ALL)?;
+        regtest.mine(vec![claim_a.clone()], &alice)?;
+
+        // both got the coins of the other
+        let claim_b_hash = testnet.bc.at(-1).tx_hashes()?[1];
+        assert_eq!(
+            testnet.store.get(&claim_b_hash, &0).unwrap().value,
+            bob_value
+        );
+        assert_eq!(
+            testnet.store.get(&claim_b_hash, &0).unwrap().lock,
+            testnet.lock(&alice)
+        );
+        assert!(regtest.store.get(&claim_a.hash()?, &0).is_some());
+        assert!(regtest.store.get(&fund_a.hash()?, &0).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refund() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        let mut chain = Chain::new(Settings::regtest());
+        let coins = chain.mine(vec![], &alice)?;
+        let value = chain.store.get(&coins, &0).unwrap().value;
+
+        let hash = htlc::hash_secret(b"never revealed");
+        let lock = htlc::create_lock(&hash, &chain.address(&bob), &chain.address(&alice), 5);
+        let mut fund = send(coins, value, lock);
+        fund.vin[0].2 = sign_input(&secp, &alice, &fund, 0, SigHashType::ALL)?;
+        chain.mine(vec![fund.clone()], &alice)?;
+
+        let mut refund = send(fund.hash()?, value, chain.lock(&alice));
+        refund.vin[0].2 =
+            htlc::create_refund_solution(&secp, &alice, &refund, 0, SigHashType::ALL)?;
+        // the refund path is only for the sender
+        let mut stolen = send(fund.hash()?, value, chain.lock(&bob));
+        stolen.vin[0].2 = htlc::create_refund_solution(&secp, &bob, &stolen, 0, SigHashType::ALL)?;
+        // refunds don't reveal a secret
+        assert_eq!(htlc::secret_from_solution(&refund.vin[0].2), None);
+
+        // the next block has height 3, the refund is valid from height 5 on
+        while chain.bc.height() < 5 {
+            assert!(chain.is_valid(&refund).is_err());
+            chain.mine(vec![], &alice)?;
+        }
+        assert!(chain.is_valid(&stolen).is_err());
+        chain.mine(vec![refund], &alice)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::blockchain::*;
+    use macc_lib::ecdsa::*;
+    use macc_lib::mempool::*;
+    use macc_lib::settings::Settings;
+    use macc_lib::PublicKey;
+
+    #[test]
+    fn test_mempool() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let mut bob = Client::new_random(&secp, &mut rng);
+
+        // alice owns three outputs
+        let mut store = TxStore::new_empty();
+        for i in 1..=3 {
+            store.set(&[i; 32], 0, Utxo::new(1000, create_lock(&alice.pb_key), 0));
+        }
+
+        let pay = |client: &mut Client, hash: [u8; 32], fee: u128, to: PublicKey| {
+            client
+                .create_transaction(&secp, vec![(hash, 0)], vec![(1000 - fee, to)])
+                .expect("Couldn't create the transaction!")
+        };
+        let low = pay(&mut alice, [1; 32], 10, bob.pb_key);
+        let high = pay(&mut alice, [2; 32], 100, bob.pb_key);
+        let conflict = pay(&mut alice, [1; 32], 500, bob.pb_key);
+
+        let settings = Settings {
+            mempool_expiry: 60,
+            ..Settings::default()
+        };
+        let mut mempool = Mempool::new(store.clone(), None, &settings);
+        let low_hash = mempool.add(low.clone(), 0)?;
+        let high_hash = mempool.add(high.clone(), 0)?;
+        assert_eq!(mempool.get(&low_hash).map(|entry| entry.fee), Some(10));
+
+        // outputs can't be spent twice
+        assert_eq!(mempool.add(low, 0), Err(MempoolError::AlreadyKnown));
+        assert!(matches!(
+            mempool.add(conflict, 0),
+            Err(MempoolError::Invalid(ValidationError::MissingUtxo { .. }))
+        ));
+
+        // bob spends the output of the low fee transaction with a high fee
+        let child = bob
+            .create_transaction(&secp, vec![(low_hash, 0)], vec![(490, alice.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let child_hash = mempool.add(child, 1)?;
+        assert_eq!(
+            mempool.get(&child_hash).map(|entry| entry.parents.clone()),
+            Some(vec![low_hash])
+        );
+
+        // highest fee rate first, but children only after their parents
+        let hashes = |txs: Vec<Transaction>| -> Vec<[u8; 32]> {
+            txs.iter()
+                .map(|tx| tx.hash().expect("UNREACHABLE"))
+                .collect()
+        };
+        assert_eq!(
+            hashes(mempool.template(10, usize::MAX)),
+            vec![high_hash, low_hash, child_hash]
+        );
+        assert_eq!(hashes(mempool.template(1, usize::MAX)), vec![high_hash]);
+        let size = mempool
+            .get(&high_hash)
+            .map(|entry| entry.size)
+            .unwrap_or_default();
+        assert_eq!(hashes(mempool.template(10, size)), vec![high_hash]);
+
+        // removing a transaction removes its descendants and restores the store
+        let before = mempool.store().clone();
+        assert_eq!(mempool.remove(&low_hash).len(), 2);
+        assert_eq!(mempool.len(), 1);
+        let mut expected = store.clone();
+        utils::add_tx_to_store(&high, &mut expected);
+        assert_eq!(mempool.store(), &expected);
+        assert_ne!(mempool.store(), &before);
+
+        // stale transactions expire
+        assert_eq!(mempool.expire(60), 0);
+        assert_eq!(mempool.expire(61), 1);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.size(), 0);
+        assert_eq!(mempool.store(), &store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_limits() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let mut bob = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        for i in 1..=3 {
+            store.set(&[i; 32], 0, Utxo::new(1000, create_lock(&alice.pb_key), 0));
+        }
+
+        let txs: Vec<Transaction> = [(1, 50), (2, 10), (3, 100)]
+            .iter()
+            .map(|(i, fee)| {
+                alice
+                    .create_transaction(&secp, vec![([*i; 32], 0)], vec![(1000 - fee, bob.pb_key)])
+                    .expect("Couldn't create the transaction!")
+            })
+            .collect();
+        let size = txs[0].size()?;
+
+        // only two transactions fit
+        let settings = Settings {
+            mempool_max_size: 2 * size,
+            ..Settings::default()
+        };
+        let mut mempool = Mempool::new(store.clone(), None, &settings);
+        let first = mempool.add(txs[0].clone(), 0)?;
+        mempool.add(txs[1].clone(), 0)?;
+
+        // the lowest fee rate is evicted for a better one
+        let third = mempool.add(txs[2].clone(), 0)?;
+        assert_eq!(mempool.len(), 2);
+        assert!(mempool.contains(&first) && mempool.contains(&third));
+
+        // a transaction with a too low fee rate isn't accepted
+        assert_eq!(
+            mempool.add(txs[1].clone(), 0),
+            Err(MempoolError::FeeRateTooLow)
+        );
+        assert!(mempool.size() <= 2 * size);
+
+        // once a transaction is mined it is dropped on reset
+        let mut mined = store.clone();
+        utils::add_tx_to_store(&txs[0], &mut mined);
+        mempool.reset(mined.clone(), None, vec![], 0);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&third));
+
+        // a transaction spending an output of a disconnected block
+        // is kept once the block's transactions are returned
+        let child = bob
+            .create_transaction(&secp, vec![(first, 0)], vec![(900, alice.pb_key)])
+            .expect("Couldn't create the transaction!");
+        let mut mempool = Mempool::new(mined.clone(), None, &settings);
+        let child_hash = mempool.add(child.clone(), 0)?;
+        mempool.reset(store.clone(), None, vec![txs[0].clone()], 1);
+        assert!(mempool.contains(&first) && mempool.contains(&child_hash));
+        assert_eq!(
+            mempool.get(&child_hash).map(|entry| entry.parents.clone()),
+            Some(vec![first])
+        );
+        assert_eq!(mempool.get(&first).map(|entry| entry.time), Some(1));
+
+        // without them it is dropped
+        let mut mempool = Mempool::new(mined, None, &settings);
+        mempool.add(child, 0)?;
+        mempool.reset(store, None, vec![], 1);
+        assert!(mempool.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_rate_overflow() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let mut alice = Client::new_random(&secp, &mut rng);
+        let bob = Client::new_random(&secp, &mut rng);
+
+        // fees times sizes don't fit into an u128
+        let value = u128::MAX / 2;
+        let mut store = TxStore::new_empty();
+        for i in 1..=2 {
+            store.set(&[i; 32], 0, Utxo::new(value, create_lock(&alice.pb_key), 0));
+        }
+
+        let txs: Vec<Transaction> = [(1, value / 4), (2, value / 2)]
+            .iter()
+            .map(|(i, fee)| {
+                alice
+                    .create_transaction(&secp, vec![([*i; 32], 0)], vec![(value - fee, bob.pb_key)])
+                    .expect("Couldn't create the transaction!")
+            })
+            .collect();
+
+        // only one transaction fits
+        let settings = Settings {
+            mempool_max_size: txs[0].size()?,
+            ..Settings::default()
+        };
+        let mut mempool = Mempool::new(store, None, &settings);
+        mempool.add(txs[0].clone(), 0)?;
+        let high = mempool.add(txs[1].clone(), 0)?;
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&high));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::blockchain::*;
+    use macc_lib::blocktree::BlockTree;
+    use macc_lib::ecdsa::*;
+    use macc_lib::hashes;
+    use macc_lib::merkle;
+    use macc_lib::settings::Settings;
+    use macc_lib::utils::*;
+
+    #[test]
+    fn test_merkle_proofs() {
+        let leaves: Vec<[u8; 32]> = (0u8..7).map(|i| hashes::sha256(&[i])).collect();
+
+        // the root of a single hash is its leaf hash
+        let mut leaf = vec![0u8];
+        leaf.extend(leaves[0]);
+        assert_eq!(merkle::root(&leaves[..1]), hashes::sha256(&leaf));
+        assert_eq!(merkle::root(&[]), [0u8; 32]);
+
+        // every leaf can be proven, also with an odd number of leaves
+        for count in 1..=leaves.len() {
+            let root = merkle::root(&leaves[..count]);
+
+            for (index, leaf) in leaves[..count].iter().enumerate() {
+                let proof = merkle::proof(&leaves[..count], index).expect("Couldn't create proof!");
+                assert!(merkle::verify(leaf, &proof, &root));
+
+                // the proof doesn't work for other leaves
+                let other = hashes::sha256(b"other");
+                assert!(!merkle::verify(&other, &proof, &root));
+            }
+        }
+
+        // proofs can't be moved to another position
+        let root = merkle::root(&leaves);
+        let mut proof = merkle::proof(&leaves, 2).expect("Couldn't create proof!");
+        proof.index = 3;
+        assert!(!merkle::verify(&leaves[2], &proof, &root));
+
+        assert!(merkle::proof(&leaves, leaves.len()).is_none());
+
+        // or past the last leaf
+        let mut proof = merkle::proof(&leaves, 6).expect("Couldn't create proof!");
+        proof.index = 7;
+        assert!(!merkle::verify(&leaves[6], &proof, &root));
+        proof.leaves = 8;
+        assert!(!merkle::verify(&leaves[6], &proof, &root));
+
+        // all siblings have to be used
+        let mut proof = merkle::proof(&leaves, 0).expect("Couldn't create proof!");
+        proof.siblings.push(leaves[1]);
+        assert!(!merkle::verify(&leaves[0], &proof, &root));
+    }
+
+    #[test]
+    fn test_merkle_mutation() {
+        let leaves: Vec<[u8; 32]> = (0u8..3).map(|i| hashes::sha256(&[i])).collect();
+
+        // repeating the last odd hash doesn't give the same root
+        let mut mutated = leaves.clone();
+        mutated.push(leaves[2]);
+        assert_ne!(merkle::root(&mutated), merkle::root(&leaves));
+
+        // neither does replacing two hashes with the node above them
+        let proof = merkle::proof(&leaves, 2).expect("Couldn't create proof!");
+        let node = proof.siblings[0];
+        assert_ne!(merkle::root(&[node, leaves[2]]), merkle::root(&leaves));
+    }
+
+    #[test]
+    fn test_block_merkle_root() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+        let diff = settings.start_difficulty;
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let client = Client::new_random(&secp, &mut rng);
+
+        let mut store = TxStore::new_empty();
+        let bc = Blockchain::new(&mut store, &settings);
+
+        let reward = utils::calculate_mining_reward(bc.height(), &settings);
+        let cb_tx = Transaction::new_coinbase(bc.height(), reward, create_lock(&client.pb_key));
+        let cb_hash = cb_tx.hash()?;
+
+        let mut b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, vec![cb_tx])?;
+        b.header.nonce = find_nonce(&b, &diff)?;
+        bc.valid_next(&b, &store, &diff, &settings)?;
+
+        // the header alone proves that the transaction is in the block
+        let proof = b.merkle_proof(0).expect("Couldn't create proof!");
+        assert!(merkle::verify(&cb_hash, &proof, &b.header.merkle_root));
+
+        // changing the transactions doesn't change the block hash
+        // but the merkle root won't match anymore
+        let hash = b.hash(None)?;
+        b.transactions[0].vout[0].0 = reward / 2;
+        assert_eq!(b.hash(None)?, hash);
+        assert_eq!(
+            bc.valid_next(&b, &store, &diff, &settings),
+            Err(ValidationError::BadMerkleRoot)
+        );
+
+        // such a copy isn't stored under the hash of the real block
+        let mut tree = BlockTree::from_chain(&bc);
+        let mut chain = bc.clone();
+        assert_eq!(
+            tree.insert(b.clone(), &mut chain, &mut store, &settings)
+                .map(|_| ()),
+            Err(ValidationError::BadMerkleRoot)
+        );
+        assert!(!tree.contains(&hash));
+
+        Ok(())
+    }
+}
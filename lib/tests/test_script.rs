@@ -0,0 +1,273 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::{
+        blockchain::{Transaction, TX_VERSION},
+        ecdsa::*,
+        hex::ToHex,
+        script::{self, Instruction, Opcode, Script, ScriptError},
+        settings::Settings,
+        sighash::SigHashType,
+    };
+
+    fn empty_tx() -> Transaction {
+        Transaction {
+            version: TX_VERSION,
+            nonce: 0,
+            vin: vec![([0; 32], 0, String::new())],
+            vout: vec![],
+        }
+    }
+
+    #[test]
+    fn test_assemble() -> Result<(), Box<dyn Error>> {
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let client = Client::new_random(&secp, &mut rng);
+
+        let lock = create_lock(&client.pb_key);
+        let script = Script::assemble(&lock)?;
+        // opcode, opcode, 1 byte length and the address, opcode
+        assert_eq!(script.len(), 1 + 1 + 1 + 25 + 1);
+        assert_eq!(script.as_bytes()[0], Opcode::VerifySig.to_byte());
+        assert_eq!(script.disassemble()?, lock);
+        assert_eq!(
+            lock_to_addr(&lock),
+            Some(pb_key_to_addr(&client.pb_key.serialize()).as_str())
+        );
+
+        let solution = sign_input(&secp, &client, &empty_tx(), 0, SigHashType::ALL)?;
+        assert_eq!(Script::assemble(&solution)?.disassemble()?, solution);
+
+        // opcodes are case insensitive, but written in lowercase
+        assert_eq!(
+            Script::assemble("VERIFY_SIG 0 ab")?.disassemble()?,
+            "verify_sig 0 ab"
+        );
+
+        // unknown tokens are rejected instead of pushed as text
+        assert_eq!(
+            Script::assemble("verify_sgi to_addr"),
+            Err(ScriptError {
+                position: 0,
+                opcode: "verify_sgi".to_string()
+            })
+        );
+        assert!(Script::assemble("abc").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_data() -> Result<(), Box<dyn Error>> {
+        assert_eq!(Script::new().push_data(&[]).as_bytes(), [0x00]);
+        assert_eq!(Script::new().push_data(&[7; 75]).as_bytes()[0], 75);
+
+        let script = Script::new().push_data(&[7; 76]);
+        assert_eq!(script.as_bytes()[..2], [0x4c, 76]);
+        assert_eq!(script.len(), 2 + 76);
+
+        let script = Script::new().push_data(&[7; 300]);
+        assert_eq!(script.as_bytes()[..3], [0x4d, 0x2c, 0x01]);
+        assert_eq!(script.len(), 3 + 300);
+
+        let instructions = script.instructions().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(instructions, [Instruction::Push(&[7; 300])]);
+        assert_eq!(Script::assemble(&script.disassemble()?)?, script);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed() {
+        // push of 5 bytes with only 2 left
+        let script = Script::from_bytes(vec![0x88, 0x05, 0xaa, 0xbb]);
+        assert_eq!(
+            script.disassemble(),
+            Err(ScriptError {
+                position: 1,
+                opcode: format!("invalid({})", [0x05, 0xaa, 0xbb].to_hex()),
+            })
+        );
+
+        // unknown opcode
+        let script = Script::from_bytes(vec![0xff]);
+        assert!(script.disassemble().is_err());
+
+        // cut off length of a push
+        let script = Script::from_bytes(vec![0x4d, 0x01]);
+        assert!(script.disassemble().is_err());
+    }
+
+    #[test]
+    fn test_eval() -> Result<(), Box<dyn Error>> {
+        let tx = empty_tx();
+        let context = script::Context::new(&tx, 0, &Settings::default());
+
+        assert!(script::verify_text("ab", "ab eq", &context).is_ok());
+        assert!(script::verify_text("0", "0 eq", &context).is_ok());
+
+        // positions of the lock continue after the solution
+        assert_eq!(
+            script::verify_text("ab cd", "eq", &context),
+            Err(ScriptError {
+                position: 2,
+                opcode: "eq".to_string()
+            })
+        );
+        // an empty stack fails
+        assert_eq!(
+            script::verify_text("", "ab ab eq eq", &context),
+            Err(ScriptError {
+                position: 3,
+                opcode: "eq".to_string()
+            })
+        );
+        // errors of the assembler are counted the same way
+        assert_eq!(
+            script::verify_text("ab", "ab xyz", &context).map_err(|e| e.position),
+            Err(2)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timelock_opcodes() -> Result<(), Box<dyn Error>> {
+        assert_eq!(script::encode_number(0), Vec::<u8>::new());
+        assert_eq!(script::encode_number(1000), [0xe8, 0x03]);
+        assert_eq!(script::encode_number(u64::MAX), [0xff; 8]);
+
+        let tx = empty_tx();
+        let context = script::Context::new(&tx, 0, &Settings::default());
+
+        // heights
+        let at_height = |height| context_at(&context, height, None, 0);
+        assert!(script::verify_text("", "e803 check_lock_time", &at_height(1000)).is_ok());
+        assert!(script::verify_text("", "e803 check_lock_time", &at_height(999)).is_err());
+        assert!(script::verify_text("", "0 check_lock_time", &at_height(0)).is_ok());
+
+        // timestamps are compared to the median time past
+        let time = script::LOCK_TIME_THRESHOLD + 10;
+        let time_hex = script::encode_number(time).to_hex();
+        let lock = format!("{} check_lock_time", time_hex);
+        let at_time = |median_time| context_at(&context, 1_000_000, median_time, 0);
+        assert!(script::verify_text("", &lock, &at_time(Some(time))).is_ok());
+        assert!(script::verify_text("", &lock, &at_time(Some(time - 1))).is_err());
+        assert!(script::verify_text("", &lock, &at_time(None)).is_err());
+
+        // the age of the spent output
+        let aged = |height, utxo_height| context_at(&context, height, None, utxo_height);
+        assert!(script::verify_text("", "05 check_age", &aged(15, 10)).is_ok());
+        assert!(script::verify_text("", "05 check_age", &aged(14, 10)).is_err());
+
+        // numbers have at most 8 bytes
+        let too_long = format!("{} check_age", "00".repeat(9));
+        assert!(script::verify_text("", &too_long, &aged(15, 10)).is_err());
+        assert!(script::verify_text("", "check_age", &aged(15, 10)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_opcodes() -> Result<(), Box<dyn Error>> {
+        let tx = empty_tx();
+        let context = script::Context::new(&tx, 0, &Settings::default());
+
+        let sha256_empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let lock = format!("sha256 {} eq", sha256_empty);
+        assert!(script::verify_text("0", &lock, &context).is_ok());
+        assert!(script::verify_text("00", &lock, &context).is_err());
+
+        let ripemd160_empty = "9c1185a5c5e9fc54612808977ee8f548b2258d31";
+        let lock = format!("ripemd160 {} eq", ripemd160_empty);
+        assert!(script::verify_text("0", &lock, &context).is_ok());
+
+        // hash160 of a public key is the hash in its address
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let client = Client::new_random(&secp, &mut rng);
+        let pb_key = client.pb_key.serialize();
+        let address = pb_key_to_addr_bytes(&pb_key, MAINNET_ADDRESS_VERSION);
+        let lock = format!("hash160 {} eq", address[1..21].to_hex());
+        assert!(script::verify_text(&pb_key.to_hex(), &lock, &context).is_ok());
+
+        // hashing needs an item
+        assert!(script::verify_text("", "sha256", &context).is_err());
+
+        // size keeps the item
+        assert!(script::verify_text("abcd", "size 02 eq abcd eq", &context).is_ok());
+        assert!(script::verify_text("0", "size 0 eq 0 eq", &context).is_ok());
+        assert!(script::verify_text("abcd", "size 03 eq", &context).is_err());
+        assert!(script::verify_text("", "size", &context).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditions() {
+        let tx = empty_tx();
+        let context = script::Context::new(&tx, 0, &Settings::default());
+        let lock = "if aa else bb endif eq";
+
+        // the first branch runs for anything but empty items and zeros
+        for condition in ["01", "0100", "ff"] {
+            let solution = format!("aa {}", condition);
+            assert!(script::verify_text(&solution, lock, &context).is_ok());
+            let solution = format!("bb {}", condition);
+            assert!(script::verify_text(&solution, lock, &context).is_err());
+        }
+        for condition in ["0", "00", "0000"] {
+            let solution = format!("bb {}", condition);
+            assert!(script::verify_text(&solution, lock, &context).is_ok());
+        }
+
+        // failing instructions in the branch which doesn't run are skipped
+        assert!(script::verify_text("01", "if 01 else eq endif", &context).is_ok());
+        assert!(script::verify_text("0", "if 01 else eq endif", &context).is_err());
+
+        // nested conditions, the inner condition isn't popped if the outer branch doesn't run
+        let lock = "if if aa else bb endif else cc endif";
+        for (solution, top) in [("01 01", "aa"), ("0 01", "bb"), ("01 0", "cc")] {
+            let lock = format!("{} {} eq", lock, top);
+            assert!(script::verify_text(solution, &lock, &context).is_ok());
+        }
+
+        // unbalanced conditions
+        assert_eq!(
+            script::verify_text("01", "aa if", &context),
+            Err(ScriptError {
+                position: 2,
+                opcode: "if".to_string()
+            })
+        );
+        assert_eq!(
+            script::verify_text("01", "else", &context),
+            Err(ScriptError {
+                position: 1,
+                opcode: "else".to_string()
+            })
+        );
+        assert!(script::verify_text("", "endif", &context).is_err());
+        // the condition is missing
+        assert!(script::verify_text("", "if endif", &context).is_err());
+        // a branch can't span the solution and the lock
+        assert!(script::verify_text("01 if", "endif", &context).is_err());
+    }
+
+    fn context_at<'a>(
+        context: &script::Context<'a>,
+        height: usize,
+        median_time: Option<u64>,
+        utxo_height: usize,
+    ) -> script::Context<'a> {
+        script::Context::new(context.tx, context.input, &Settings::default()).with_chain(
+            height,
+            median_time,
+            utxo_height,
+        )
+    }
+}
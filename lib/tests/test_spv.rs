@@ -0,0 +1,84 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use macc_lib::blockchain::*;
+    use macc_lib::ecdsa::*;
+    use macc_lib::settings::Settings;
+    use macc_lib::spv::*;
+    use macc_lib::utils::*;
+
+    #[test]
+    fn test_spv_inclusion() -> Result<(), Box<dyn Error>> {
+        let settings = Settings::default();
+
+        let secp = create_secp();
+        let mut rng = create_rng()?;
+        let miner = Client::new_random(&secp, &mut rng);
+
+        // full node mines a few blocks
+        let mut store = TxStore::new_empty();
+        let mut bc = Blockchain::new(&mut store, &settings);
+        let mut diff = settings.start_difficulty;
+        let mut cb_hashes: Vec<[u8; 32]> = Vec::new();
+
+        for _ in 0..3 {
+            diff = bc.adjust_difficulty(diff, &settings);
+
+            let reward = utils::calculate_mining_reward(bc.height(), &settings);
+            let cb_tx = Transaction::new_coinbase(bc.height(), reward, create_lock(&miner.pb_key));
+            cb_hashes.push(cb_tx.hash()?);
+
+            let mut b = Block::new(current_time(), bc.at(-1).hash(None)?, diff, vec![cb_tx])?;
+            b.header.nonce = find_nonce(&b, &diff)?;
+
+            bc.valid_next(&b, &store, &diff, &settings)?;
+            bc.add(&mut store, b);
+        }
+
+        // light client only downloads the headers
+        let headers = bc.headers(0, bc.height()).expect("Couldn't get headers!");
+        let chain = HeaderChain::from_headers(headers.clone(), &settings)?;
+        assert_eq!(chain.height(), 4);
+
+        // and verifies the payment with the proof of the full node
+        let proof = bc.tx_proof(&cb_hashes[1]).expect("Couldn't create proof!");
+        assert_eq!(proof.height, 2);
+        assert!(chain.verify_tx(&cb_hashes[1], &proof));
+        assert_eq!(chain.confirmations(proof.height), 2);
+
+        // a proof for another transaction or block fails
+        assert!(!chain.verify_tx(&cb_hashes[0], &proof));
+        let moved = TxProof {
+            height: 3,
+            proof: proof.proof.clone(),
+        };
+        assert!(!chain.verify_tx(&cb_hashes[1], &moved));
+        assert!(bc.tx_proof(&[0u8; 32]).is_none());
+
+        // headers without valid work are rejected
+        let mut forged = headers.clone();
+        forged[2].merkle_root = [0u8; 32];
+        forged[2].nonce = (0..)
+            .find(|nonce| {
+                !difficulty::satisfies(&diff, &forged[2].hash(Some(*nonce)).expect("UNREACHABLE"))
+            })
+            .expect("UNREACHABLE");
+        assert!(matches!(
+            HeaderChain::from_headers(forged, &settings),
+            Err(ValidationError::InvalidBlock { height: 2, .. })
+        ));
+
+        // headers have to link to each other
+        let mut unlinked = headers;
+        unlinked.remove(1);
+        assert!(matches!(
+            HeaderChain::from_headers(unlinked, &settings),
+            Err(ValidationError::InvalidBlock { height: 1, .. })
+        ));
+
+        Ok(())
+    }
+}
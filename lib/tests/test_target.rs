@@ -0,0 +1,122 @@
+extern crate macc_lib;
+
+#[cfg(test)]
+mod tests {
+    use macc_lib::blockchain::{difficulty, utils, Block, Blockchain, ValidationError};
+    use macc_lib::settings::Settings;
+    use macc_lib::spv::HeaderChain;
+    use macc_lib::target::Target;
+    use macc_lib::utils::find_nonce;
+    use num::BigUint;
+
+    #[test]
+    fn test_compact() {
+        // bitcoin's genesis target
+        let target = Target::from_compact(0x1d00ffff).expect("UNREACHABLE");
+        let mut bytes = [0u8; 32];
+        bytes[4] = 0xff;
+        bytes[5] = 0xff;
+        assert_eq!(target.to_bytes(), bytes);
+        assert_eq!(target.to_compact(), 0x1d00ffff);
+
+        // the mantissa is shifted so the sign bit isn't set
+        let target = Target::from_biguint(&BigUint::from(0x80u8)).expect("UNREACHABLE");
+        assert_eq!(target.to_compact(), 0x02008000);
+        assert_eq!(Target::from_compact(0x02008000), Some(target));
+
+        // small sizes only use the upper bytes of the mantissa
+        assert_eq!(
+            Target::from_compact(0x01120000).map(Target::to_biguint),
+            Some(BigUint::from(0x12u8))
+        );
+        assert_eq!(Target::from_compact(0).map(Target::to_compact), Some(0));
+
+        // every target has only one encoding, other encodings are rejected
+        // lower bytes which are cut off
+        assert_eq!(Target::from_compact(0x01123456), None);
+        // a zero padded mantissa with a larger size
+        assert_eq!(
+            Target::from_compact(0x04010000).map(Target::to_biguint),
+            Some(BigUint::from(0x0100_0000u32))
+        );
+        assert_eq!(Target::from_compact(0x05000100), None);
+        // the sign bit on a zero mantissa
+        assert_eq!(Target::from_compact(0x00800000), None);
+        assert_eq!(Target::from_compact(0x03800000), None);
+
+        // negative and too large numbers are rejected
+        assert_eq!(Target::from_compact(0x04923456), None);
+        assert_eq!(Target::from_compact(0x23000001), None);
+        assert!(Target::from_biguint(&(BigUint::from(1u8) << 256)).is_none());
+
+        // the compact encoding keeps the 3 most significant bytes
+        let mut bytes = [0xFF; 32];
+        bytes[0] = 0;
+        let easy = Target::from_bytes(bytes);
+        let normalized = easy.normalized();
+        assert_eq!(normalized.to_compact(), easy.to_compact());
+        assert_eq!(&normalized.to_bytes()[..3], &easy.to_bytes()[..3]);
+        assert!(normalized <= easy);
+        assert_eq!(
+            Target::from_compact(easy.to_compact()).map(Target::to_compact),
+            Some(easy.to_compact())
+        );
+    }
+
+    #[test]
+    fn test_work() {
+        // a target of all ones is satisfied by every hash
+        let max = Target::from_bytes([0xFF; 32]);
+        assert_eq!(max.work(), BigUint::from(1u8));
+        assert!(max.is_satisfied_by(&[0xFF; 32]));
+
+        // every leading zero bit needs twice as many hashes
+        let eight = difficulty::create(8).expect("UNREACHABLE");
+        let nine = difficulty::create(9).expect("UNREACHABLE");
+        assert_eq!(difficulty::work(&eight), BigUint::from(256u16));
+        assert_eq!(difficulty::work(&nine), BigUint::from(512u16));
+        assert!(nine.is_satisfied_by(&[0u8; 32]));
+        assert!(!nine.is_satisfied_by(&eight.to_bytes()));
+        assert!(eight.is_satisfied_by(&nine.to_bytes()));
+
+        // the created targets fit into the header
+        assert_eq!(Target::from_compact(nine.to_compact()), Some(nine));
+        assert_eq!(
+            difficulty::create(255).map(Target::to_biguint).ok(),
+            Some(BigUint::from(1u8))
+        );
+        assert!(difficulty::create(256).is_err());
+
+        // empty chains have no work
+        assert_eq!(Blockchain::new_empty().total_work(), BigUint::from(0u8));
+        assert_eq!(HeaderChain::new_empty().total_work(), BigUint::from(0u8));
+        assert_eq!(Blockchain::new_empty().hashrate(10), None);
+    }
+
+    #[test]
+    fn test_header_bits() -> Result<(), Box<dyn std::error::Error>> {
+        // 0x21007fff encodes the same number, but isn't canonical
+        let difficulty = Target::from_compact(0x207fff00).expect("UNREACHABLE");
+        let mut settings = Settings {
+            start_difficulty: difficulty,
+            min_difficulty: difficulty,
+            ..Settings::regtest()
+        };
+        settings.genesis_nonce = find_nonce(&Block::genesis(&settings), &difficulty)?;
+
+        let header = Block::genesis(&settings).header;
+        assert_eq!(header.bits, 0x207fff00);
+        assert!(utils::validate_header(&header, None, None, &difficulty, &settings).is_ok());
+
+        // the header has to use the encoding of the difficulty
+        let mut other = header.clone();
+        other.bits = 0x21007fff;
+        assert_eq!(other.target(), None);
+        assert_eq!(
+            utils::validate_header(&other, None, None, &difficulty, &settings),
+            Err(ValidationError::WrongTarget)
+        );
+
+        Ok(())
+    }
+}
@@ -32,7 +32,7 @@ function DisplayTransaction({ tx, index }) {
     )
 }
 
-function BlockDisplay({ block, height }) {
+function BlockDisplay({ block, height, fees }) {
     let transactions = block.transactions.map((tx, index) => <DisplayTransaction tx={tx} index={index} key={index}/> )
 
     return (
@@ -41,8 +41,15 @@ function BlockDisplay({ block, height }) {
                 <div className='data'>
                     <h1>Block Explorer for Block #{height}</h1>
                     <p>Height: {height}</p>
-                    <p>Timestamp: {block.timestamp}</p>
+                    <p>Timestamp: {block.header.timestamp}</p>
                     <p>Block reward: {wasm.calculate_mining_reward(height)}</p>
+                    {fees && (
+                        <>
+                            <p>Fees: {fees.fees}</p>
+                            <p>Claimed by the miner: {fees.claimed}</p>
+                            <p>Burned: {fees.burned}</p>
+                        </>
+                    )}
                     <p>Transactions:</p>
                     <div>
                         {transactions}
@@ -58,6 +65,7 @@ export default function BlockExplorer() {
     const { height } = router.query;
 
     const [block, setBlock] = useState(null);
+    const [fees, setFees] = useState(null);
     const [isLoading, setLoading] = useState(false);
     
     useEffect(() => {
@@ -73,12 +81,17 @@ export default function BlockExplorer() {
             setLoading(false);
         })
         .catch((_) => setLoading(false));
+
+        fetch(`${Settings.apiUrl}/fees?height=${h}`)
+        .then((res) => res.json())
+        .then((f) => setFees(f))
+        .catch((_) => setFees(null));
     }, [block, height])
     
     if (isLoading) return <Loading />
     if (!block) return <Error />
 
     return (
-        <BlockDisplay block={block[0]} height={height}/>
+        <BlockDisplay block={block[0]} height={height} fees={fees}/>
     )
 }
\ No newline at end of file
@@ -42,8 +42,12 @@ function RenderWallet( { client, store } ) {
             headers: {},
             body: tx
         })
-        .then((res) => res.text())
-        .then((_) => {
+        .then((res) => res.text().then((reason) => [res.ok, reason]))
+        .then(([ok, reason]) => {
+            if (!ok) {
+                alert(`Transaction rejected: ${reason}`)
+                return;
+            }
             alert(`Transaction sent successfully! Hash: ${tx_hash(tx)}`)
         })
     }
@@ -1,5 +1,5 @@
 use wasm_bindgen::prelude::*;
-use macc_lib::{hex::{ToHex, FromHex}, settings::Settings, blockchain::{utils, Transaction, TxStore, Block, Blockchain}, ecdsa::{Client, pb_key_to_addr, generate_transaction}};
+use macc_lib::{hex::{ToHex, FromHex}, settings::Settings, emission, blockchain::{utils, Transaction, TxStore, Block, BlockHeader}, ecdsa::{Client, pb_key_to_addr, generate_transaction}, spv::{HeaderChain, TxProof}};
 
 // utils
 
@@ -10,7 +10,19 @@ pub fn to_hex(data: &[u8]) -> String {
 
 #[wasm_bindgen]
 pub fn calculate_mining_reward(block_height: usize) -> String {
-    utils::calculate_mining_reward(block_height, &Settings::default()).to_string()
+    emission::block_reward(block_height, &Settings::default()).to_string()
+}
+
+// coins created by the blocks below the height
+#[wasm_bindgen]
+pub fn supply(block_height: usize) -> String {
+    emission::supply(block_height, &Settings::default()).to_string()
+}
+
+// undefined if there is no maximum
+#[wasm_bindgen]
+pub fn max_supply() -> Option<String> {
+    emission::max_supply(&Settings::default()).map(|max| max.to_string())
 }
 
 #[wasm_bindgen]
@@ -41,6 +53,19 @@ pub fn tx_vin_total(tx_str: String, store_str: String) -> Option<String> {
     Some(tx.vin_total(&store)?.to_string())
 }
 
+// returns why the transaction is invalid or an empty string if it is valid
+#[wasm_bindgen]
+pub fn tx_validate(tx_str: String, store_str: String) -> Option<String> {
+    let tx: Transaction = serde_json::from_str(&tx_str).ok()?;
+    let store: TxStore = serde_json::from_str(&store_str).ok()?;
+
+    // the store has no timestamps, outputs locked until a time are reported as locked
+    match utils::is_valid_tx(&tx, &store, None, &Settings::default()) {
+        Ok(()) => Some(String::new()),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
 #[wasm_bindgen]
 pub fn block_hash(block_str: String) -> Option<String> {
     let block: Block = serde_json::from_str(&block_str).ok()?;
@@ -50,14 +75,28 @@ pub fn block_hash(block_str: String) -> Option<String> {
 
 #[wasm_bindgen]
 pub fn get_tx(blockchain_str: String, hash_str: String) -> Option<String> {
-    let bc: Blockchain = serde_json::from_str(&blockchain_str).ok()?;
+    let blocks: Vec<Block> = serde_json::from_str(&blockchain_str).ok()?;
     let hash: [u8; 32] = Vec::from_hex(&hash_str).ok()?.try_into().ok()?;
 
-    let found = bc.get_transaction(&hash)?;
+    let found = utils::find_transaction(&blocks, &hash)?;
 
     Some(serde_json::to_string(&found).ok()?)
 }
 
+// light client
+
+// checks the headers and if the transaction is included in them
+#[wasm_bindgen]
+pub fn verify_tx_inclusion(headers_str: String, proof_str: String, hash_str: String) -> Option<bool> {
+    let headers: Vec<BlockHeader> = serde_json::from_str(&headers_str).ok()?;
+    let proof: TxProof = serde_json::from_str(&proof_str).ok()?;
+    let hash: [u8; 32] = Vec::from_hex(&hash_str).ok()?.try_into().ok()?;
+
+    let chain = HeaderChain::from_headers(headers, &Settings::default()).ok()?;
+
+    Some(chain.verify_tx(&hash, &proof))
+}
+
 // Wallet
 
 #[wasm_bindgen]
@@ -84,7 +123,7 @@ pub fn send(owned_str: String, sk_key: String, addr: String, amount_str: String)
     let amount: u128 = amount_str.parse().ok()?;
 
     
-    let tx: Transaction = generate_transaction(owned, sk_key, addr, amount)?;
+    let tx: Transaction = generate_transaction(owned, sk_key, addr, amount, &Settings::default())?;
     let tx_str = serde_json::to_string(&tx).ok()?;
 
     Some(tx_str)